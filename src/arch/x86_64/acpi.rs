@@ -0,0 +1,482 @@
+use alloc::vec::Vec;
+use core::{mem::size_of, ptr::read_unaligned};
+use spin::Once;
+
+use crate::memory::addr::phys_to_virt;
+
+use super::{ioapic::{Polarity, Trigger, add_isa_override, register_ioapic}, lapic::{LAPIC_DEFAULT_ADDR, set_lapic_base}, memory::is_mapped};
+use log::{info, warn};
+
+/// Root System Description Pointer, the revision 2 fields are only valid
+/// when `revision >= 2`.
+#[allow(dead_code)]
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    ext_checksum: u8,
+    reserved: [u8; 3],
+}
+
+/// Header shared by all system description tables.
+#[allow(dead_code)]
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+
+/// Generic Address Structure, a register in some address space.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+    pub const PCI_CONFIG: u8 = 2;
+}
+
+/// A processor listed in the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u32,
+    pub apic_id: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: usize,
+    pub gsi_base: u32,
+}
+
+/// An interrupt source override, isa `irq` is wired to `gsi`.
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: Trigger,
+}
+
+/// ACPI power management timer, a 3.579545 MHz counter in I/O space.
+#[derive(Debug, Clone, Copy)]
+pub struct PmTimer {
+    pub port: u16,
+    /// 32 bit counter, 24 bit otherwise
+    pub is_32bit: bool,
+}
+
+/// What the kernel needs from the FADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub sci_irq: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub pm_timer: Option<PmTimer>,
+    /// register and value that reset the machine
+    pub reset: Option<(GenericAddress, u8)>,
+    /// cmos register holding the century, 0 if there is none
+    pub century: u8,
+    /// IA-PC boot architecture flags
+    pub boot_arch: u16,
+    /// physical address of the DSDT
+    pub dsdt: usize,
+}
+
+impl Fadt {
+    /// legacy devices like the rtc and serial ports are present
+    pub const BOOT_ARCH_LEGACY_DEVICES: u16 = 1 << 0;
+    /// an 8042 keyboard controller is present
+    pub const BOOT_ARCH_8042: u16 = 1 << 1;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HpetInfo {
+    /// physical address of the registers
+    pub address: usize,
+    pub number: u8,
+    /// smallest periodic tick without lost interrupts, in main counter ticks
+    pub min_tick: u16,
+}
+
+/// One PCI segment's enhanced configuration space, from the MCFG.
+#[derive(Debug, Clone, Copy)]
+pub struct EcamRange {
+    pub address: usize,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+/// Everything found in the ACPI tables.
+#[derive(Debug)]
+pub struct AcpiInfo {
+    pub revision: u8,
+    pub lapic_address: usize,
+    pub processors: Vec<Processor>,
+    pub ioapics: Vec<IoApicInfo>,
+    pub overrides: Vec<InterruptOverride>,
+    pub fadt: Option<Fadt>,
+    pub hpet: Option<HpetInfo>,
+    pub ecam: Vec<EcamRange>,
+    /// `SLP_TYP` for pm1a and pm1b to enter S5, from the `_S5_` object of the DSDT
+    pub s5: Option<(u8, u8)>,
+}
+
+static ACPI: Once<AcpiInfo> = Once::new();
+
+/// tables parsed by `init_acpi`, none if the firmware has no ACPI
+pub fn acpi() -> Option<&'static AcpiInfo> {
+    ACPI.get()
+}
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IOAPIC: u8 = 1;
+const MADT_OVERRIDE: u8 = 2;
+const MADT_LAPIC_ADDRESS: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+
+/// processor usable right away, the ones only online capable need hotplug
+const MADT_CPU_ENABLED: u32 = 1 << 0;
+
+/// the FADT advertises `reset_reg`
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+const FADT_TMR_VAL_EXT: u32 = 1 << 8;
+
+const SDT_HEADER_SIZE: usize = size_of::<SdtHeader>();
+
+// AML opcodes around `Name(_S5_, Package() { a, b, ... })`
+const AML_NAME_OP: u8 = 0x08;
+const AML_ROOT_CHAR: u8 = b'\\';
+const AML_PACKAGE_OP: u8 = 0x12;
+const AML_ZERO_OP: u8 = 0x00;
+const AML_ONE_OP: u8 = 0x01;
+const AML_BYTE_PREFIX: u8 = 0x0a;
+
+/// Read a `T` at `offset` into the table mapped at `virt`.
+#[inline]
+unsafe fn read<T: Copy>(virt: usize, offset: usize) -> T {
+    read_unaligned((virt + offset) as *const T)
+}
+
+fn checksum_ok(virt: usize, len: usize) -> bool {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(unsafe { read::<u8>(virt, i) })) == 0
+}
+
+/// whether an RSDP is at physical `addr`
+fn is_rsdp(addr: usize) -> bool {
+    let virt = phys_to_virt(addr);
+    unsafe { read::<[u8; 8]>(virt, 0) } == *b"RSD PTR " && checksum_ok(virt, 20)
+}
+
+/// Look for the RSDP in the first KB of the EBDA, then in the BIOS area.
+///
+/// only BIOS machines have it there, under UEFI the bootloader must
+/// hand it over
+fn find_rsdp() -> Option<usize> {
+    let ebda = unsafe { read::<u16>(phys_to_virt(0x40e), 0) } as usize * 16;
+    let mut areas = Vec::new();
+    if ebda >= 0x80000 && ebda < 0xa0000 {
+        areas.push(ebda..ebda + 1024);
+    }
+    areas.push(0xe0000..0x100000);
+    areas.into_iter()
+        .flat_map(|area| area.step_by(16))
+        .find(|&addr| is_rsdp(addr))
+}
+
+/// physical addresses of all tables listed by the RSDT or XSDT
+fn table_addresses(rsdp: &Rsdp) -> Vec<usize> {
+    let (root, entry_size) = if rsdp.revision >= 2 && rsdp.xsdt_address != 0 {
+        (rsdp.xsdt_address as usize, 8)
+    } else {
+        (rsdp.rsdt_address as usize, 4)
+    };
+    let virt = phys_to_virt(root);
+    let header: SdtHeader = unsafe { read(virt, 0) };
+    let len = header.length as usize;
+    if !checksum_ok(virt, len) {
+        warn!("bad root table checksum");
+        return Vec::new();
+    }
+    (SDT_HEADER_SIZE..len)
+        .step_by(entry_size)
+        .map(|off| unsafe {
+            if entry_size == 8 {
+                read::<u64>(virt, off) as usize
+            } else {
+                read::<u32>(virt, off) as usize
+            }
+        })
+        .collect()
+}
+
+fn polarity(flags: u16) -> Polarity {
+    // 0 conforms to the bus, isa is active high
+    if flags & 3 == 3 { Polarity::Low } else { Polarity::High }
+}
+
+fn trigger(flags: u16) -> Trigger {
+    // 0 conforms to the bus, isa is edge triggered
+    if (flags >> 2) & 3 == 3 { Trigger::Level } else { Trigger::Edge }
+}
+
+fn parse_madt(virt: usize, len: usize, info: &mut AcpiInfo) {
+    info.lapic_address = unsafe { read::<u32>(virt, 36) } as usize;
+    let mut off = 44;
+    while off + 2 <= len {
+        let (kind, entry_len) = unsafe { (read::<u8>(virt, off), read::<u8>(virt, off + 1) as usize) };
+        if entry_len < 2 {
+            break;
+        }
+        let e = virt + off;
+        unsafe {
+            match kind {
+                MADT_LOCAL_APIC => {
+                    let flags: u32 = read(e, 4);
+                    if flags & MADT_CPU_ENABLED != 0 {
+                        info.processors.push(Processor {
+                            acpi_id: read::<u8>(e, 2) as u32,
+                            apic_id: read::<u8>(e, 3) as u32,
+                        });
+                    }
+                }
+                MADT_LOCAL_X2APIC => {
+                    let flags: u32 = read(e, 8);
+                    if flags & MADT_CPU_ENABLED != 0 {
+                        info.processors.push(Processor {
+                            acpi_id: read(e, 12),
+                            apic_id: read(e, 4),
+                        });
+                    }
+                }
+                MADT_IOAPIC => info.ioapics.push(IoApicInfo {
+                    id: read(e, 2),
+                    address: read::<u32>(e, 4) as usize,
+                    gsi_base: read(e, 8),
+                }),
+                MADT_OVERRIDE => {
+                    let flags: u16 = read(e, 8);
+                    info.overrides.push(InterruptOverride {
+                        irq: read(e, 3),
+                        gsi: read(e, 4),
+                        polarity: polarity(flags),
+                        trigger: trigger(flags),
+                    });
+                }
+                MADT_LAPIC_ADDRESS => info.lapic_address = read::<u64>(e, 4) as usize,
+                _ => {}
+            }
+        }
+        off += entry_len;
+    }
+}
+
+fn parse_fadt(virt: usize, len: usize) -> Fadt {
+    unsafe {
+        let flags: u32 = if len >= 116 { read(virt, 112) } else { 0 };
+        let pm_timer_port: u32 = read(virt, 76);
+        let pm_timer = if pm_timer_port != 0 && read::<u8>(virt, 91) == 4 {
+            Some(PmTimer {
+                port: pm_timer_port as u16,
+                is_32bit: flags & FADT_TMR_VAL_EXT != 0,
+            })
+        } else {
+            None
+        };
+        let reset = if len >= 129 && flags & FADT_RESET_REG_SUP != 0 {
+            Some((read::<GenericAddress>(virt, 116), read::<u8>(virt, 128)))
+        } else {
+            None
+        };
+        // the 64 bit pointer wins when present
+        let x_dsdt: u64 = if len >= 148 { read(virt, 140) } else { 0 };
+        Fadt {
+            sci_irq: read(virt, 46),
+            smi_cmd: read(virt, 48),
+            acpi_enable: read(virt, 52),
+            pm1a_control: read(virt, 64),
+            pm1b_control: read(virt, 68),
+            pm_timer,
+            reset,
+            century: read(virt, 108),
+            boot_arch: if len >= 111 { read(virt, 109) } else { 0 },
+            dsdt: if x_dsdt != 0 { x_dsdt as usize } else { read::<u32>(virt, 40) as usize },
+        }
+    }
+}
+
+fn parse_hpet(virt: usize) -> HpetInfo {
+    unsafe {
+        let address: GenericAddress = read(virt, 40);
+        HpetInfo {
+            address: address.address as usize,
+            number: read(virt, 52),
+            min_tick: read(virt, 53),
+        }
+    }
+}
+
+fn parse_mcfg(virt: usize, len: usize, info: &mut AcpiInfo) {
+    // 8 reserved bytes after the header, then 16 byte entries
+    let mut off = SDT_HEADER_SIZE + 8;
+    while off + 16 <= len {
+        unsafe {
+            info.ecam.push(EcamRange {
+                address: read::<u64>(virt, off) as usize,
+                segment: read(virt, off + 8),
+                start_bus: read(virt, off + 10),
+                end_bus: read(virt, off + 11),
+            });
+        }
+        off += 16;
+    }
+}
+
+/// An integer element of an AML package at `*off`, stepping past it.
+fn aml_byte(virt: usize, off: &mut usize) -> u8 {
+    let op = unsafe { read::<u8>(virt, *off) };
+    *off += 1;
+    match op {
+        AML_ZERO_OP => 0,
+        AML_ONE_OP => 1,
+        AML_BYTE_PREFIX => {
+            *off += 1;
+            unsafe { read(virt, *off - 1) }
+        }
+        // some firmware writes small values without a prefix
+        _ => op,
+    }
+}
+
+/// Find the `_S5_` package in the DSDT at `addr` and take its first two
+/// elements, without an AML interpreter: a plain search for the name works
+/// on the tables of qemu and most firmware.
+fn parse_s5(addr: usize) -> Option<(u8, u8)> {
+    let virt = phys_to_virt(addr);
+    let header: SdtHeader = unsafe { read(virt, 0) };
+    let len = header.length as usize;
+    if &header.signature != b"DSDT" || !checksum_ok(virt, len) {
+        warn!("bad dsdt");
+        return None;
+    }
+    let name = (SDT_HEADER_SIZE..len.saturating_sub(4)).find(|&off| {
+        unsafe { read::<[u8; 4]>(virt, off) } == *b"_S5_" && {
+            let before: [u8; 2] = unsafe { read(virt, off - 2) };
+            before[1] == AML_NAME_OP || before == [AML_NAME_OP, AML_ROOT_CHAR]
+        }
+    })?;
+    let mut off = name + 4;
+    if unsafe { read::<u8>(virt, off) } != AML_PACKAGE_OP {
+        return None;
+    }
+    // the top bits of the package length say how many more bytes it has
+    let pkg_len_bytes = (unsafe { read::<u8>(virt, off + 1) } >> 6) as usize;
+    // package op, package length, element count
+    off += 2 + pkg_len_bytes + 1;
+    if off + 4 > len {
+        return None;
+    }
+    let a = aml_byte(virt, &mut off);
+    let b = aml_byte(virt, &mut off);
+    Some((a, b))
+}
+
+fn parse(rsdp_addr: usize) -> AcpiInfo {
+    let rsdp: Rsdp = unsafe { read_unaligned(phys_to_virt(rsdp_addr) as *const Rsdp) };
+    let mut info = AcpiInfo {
+        revision: rsdp.revision,
+        lapic_address: LAPIC_DEFAULT_ADDR,
+        processors: Vec::new(),
+        ioapics: Vec::new(),
+        overrides: Vec::new(),
+        fadt: None,
+        hpet: None,
+        ecam: Vec::new(),
+        s5: None,
+    };
+    for addr in table_addresses(&rsdp) {
+        let virt = phys_to_virt(addr);
+        let header: SdtHeader = unsafe { read(virt, 0) };
+        let len = header.length as usize;
+        if !checksum_ok(virt, len) {
+            warn!("bad checksum in {:?}", core::str::from_utf8(&header.signature));
+            continue;
+        }
+        match &header.signature {
+            b"APIC" => parse_madt(virt, len, &mut info),
+            b"FACP" => info.fadt = Some(parse_fadt(virt, len)),
+            b"HPET" => info.hpet = Some(parse_hpet(virt)),
+            b"MCFG" => parse_mcfg(virt, len, &mut info),
+            _ => {}
+        }
+    }
+    if let Some(fadt) = info.fadt {
+        if fadt.dsdt != 0 {
+            info.s5 = parse_s5(fadt.dsdt);
+        }
+    }
+    info
+}
+
+/// Parse the ACPI tables from the RSDP at `rsdp`, the address the bootloader
+/// handed over, or the one found by scanning, then set up the local apic
+/// base and the ioapics they describe.
+///
+/// must run before anything touches the local apic
+pub fn init_acpi(rsdp: Option<usize>) {
+    let rsdp = rsdp.filter(|&addr| {
+        let ok = is_mapped(phys_to_virt(addr)) && is_rsdp(addr);
+        if !ok {
+            warn!("no rsdp at {:#x}, scanning", addr);
+        }
+        ok
+    });
+    let info = match rsdp.or_else(find_rsdp) {
+        Some(rsdp) => ACPI.call_once(|| parse(rsdp)),
+        None => {
+            warn!("no rsdp found");
+            set_lapic_base(LAPIC_DEFAULT_ADDR);
+            return;
+        }
+    };
+    info!(
+        "revision {}, {} cpus, {} ioapics, hpet {}, {} ecam ranges, s5 {:?}",
+        info.revision,
+        info.processors.len(),
+        info.ioapics.len(),
+        info.hpet.is_some(),
+        info.ecam.len(),
+        info.s5,
+    );
+    set_lapic_base(info.lapic_address);
+    for io in info.ioapics.iter() {
+        register_ioapic(io.id, io.address, io.gsi_base);
+    }
+    for o in info.overrides.iter() {
+        add_isa_override(o.irq, o.gsi, o.polarity, o.trigger);
+    }
+}
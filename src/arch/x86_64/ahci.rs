@@ -1,9 +1,10 @@
 use alloc::sync::Arc;
+use core::ptr::{read_volatile, write_volatile};
 use isomorphic_drivers::{block::ahci::AHCI, provider::Provider};
 //use rcore_fs::dev::{BlockDevice, BlockId, DevError};
 use spin::Mutex;
 use alloc::string::String;
-use crate::{drivers::{Driver, block::BlockDriver}, memory::{BITMAP_ALLOCATOR, addr::{phys_to_virt, virt_to_phys}, bitalloc::BitAlloc}};
+use crate::{drivers::{DeviceType, Driver, block::BlockDriver}, memory::{BITMAP_ALLOCATOR, addr::{phys_to_virt, virt_to_phys}, bitalloc::BitAlloc}};
 
 struct MyProvider;
 
@@ -29,28 +30,59 @@ impl Provider for MyProvider {
     }
 }
 
-pub struct AHCIDriver(Mutex<AHCI<MyProvider>>);
+/// hba interrupt status, one bit per port
+const HBA_IS: usize = 0x08;
+const HBA_PORT_BASE: usize = 0x100;
+const HBA_PORT_SIZE: usize = 0x80;
+/// interrupt status of a port, relative to its registers
+const PORT_IS: usize = 0x10;
+
+pub struct AHCIDriver {
+    ahci: Mutex<AHCI<MyProvider>>,
+    /// address of the hba registers
+    header: usize,
+    irq: Option<usize>,
+}
 
 
 impl AHCIDriver {
     pub fn write(&self,block_id: usize, buf: &[u8]) {
-        let mut loc = self.0.lock();
+        let mut loc = self.ahci.lock();
         loc.write_block(block_id, buf);
     }
 
     pub fn read(&self, block_id: usize, buf: &mut [u8]) {
-        let mut loc = self.0.lock();
+        let mut loc = self.ahci.lock();
         loc.read_block(block_id, buf);
     }
+
+    fn reg(&self, offset: usize) -> *mut u32 {
+        (self.header + offset) as *mut u32
+    }
 }
 
 impl Driver for AHCIDriver {
+    /// commands are polled for completion, so the interrupt only needs to be acknowledged
     fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
-        todo!()
+        if irq.is_some() && irq != self.irq {
+            return false;
+        }
+        unsafe {
+            let pending = read_volatile(self.reg(HBA_IS));
+            if pending == 0 {
+                return false;
+            }
+            for port in (0..32).filter(|p| pending & (1 << p) != 0) {
+                let port_is = self.reg(HBA_PORT_BASE + port * HBA_PORT_SIZE + PORT_IS);
+                write_volatile(port_is, read_volatile(port_is));
+            }
+            write_volatile(self.reg(HBA_IS), pending);
+        }
+        true
     }
 
-    fn device_type(&self) -> crate::drivers::DeviceType {
-        todo!()
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
     }
 
     fn get_id(&self) -> String {
@@ -71,9 +103,13 @@ impl BlockDriver for AHCIDriver {
     }
 }
 
-pub fn init(_irq: Option<usize>, header: usize, size: usize) -> Option<Arc<AHCIDriver>> {
+pub fn init(irq: Option<usize>, header: usize, size: usize) -> Option<Arc<AHCIDriver>> {
     if let Some(ahci) = AHCI::new(header, size) {
-        let driver = Arc::new(AHCIDriver(Mutex::new(ahci)));
+        let driver = Arc::new(AHCIDriver {
+            ahci: Mutex::new(ahci),
+            header,
+            irq,
+        });
         Some(driver)
     } else {
         None
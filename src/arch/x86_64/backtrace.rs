@@ -0,0 +1,80 @@
+use core::{fmt::{self, Write}, sync::atomic::{AtomicBool, Ordering}};
+
+use crate::ksyms::{lookup, symbol_count};
+
+use super::memory::is_mapped;
+
+/// frames printed at most, a corrupted chain could loop
+const MAX_FRAMES: usize = 64;
+
+/// set once the missing symbols were pointed out
+static NO_SYMBOLS_NOTED: AtomicBool = AtomicBool::new(false);
+
+/// frame pointer of the caller
+#[inline(always)]
+pub fn frame_pointer() -> usize {
+    let rbp: usize;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    rbp
+}
+
+/// `print!` as a `fmt::Write`
+struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+fn print_frame(out: &mut dyn Write, depth: usize, ip: usize, is_return: bool) {
+    // a return address points past the call, which may be the next function
+    let at = if is_return { ip.wrapping_sub(1) } else { ip };
+    let _ = match lookup(at) {
+        Some((name, offset)) => writeln!(out, "  #{:<2} {:#018x} {}+{:#x}", depth, ip, name, offset + (ip - at)),
+        None => writeln!(out, "  #{:<2} {:#018x} ?", depth, ip),
+    };
+}
+
+/// Walk the saved frame pointers from `rbp` and print each return address.
+///
+/// every frame starts with the caller's rbp followed by the return address,
+/// the chain ends at a zero or unmapped rbp
+fn walk(out: &mut dyn Write, mut rbp: usize, mut depth: usize) {
+    if symbol_count() == 0 && !NO_SYMBOLS_NOTED.swap(true, Ordering::Relaxed) {
+        let _ = writeln!(out, "  (no kernel symbols, boot it with `cargo run` or `make` to get names)");
+    }
+    while depth < MAX_FRAMES {
+        if rbp == 0 || rbp % 8 != 0 || !is_mapped(rbp) || !is_mapped(rbp + 15) {
+            break;
+        }
+        let (next, ret) = unsafe { (*(rbp as *const usize), *((rbp + 8) as *const usize)) };
+        if ret == 0 {
+            break;
+        }
+        print_frame(out, depth, ret, true);
+        depth += 1;
+        // stacks grow down, so callers have higher frames
+        if next <= rbp {
+            break;
+        }
+        rbp = next;
+    }
+}
+
+/// Print the call chain of the caller.
+#[inline(always)]
+pub fn print_backtrace() {
+    println!("backtrace:");
+    walk(&mut Console, frame_pointer(), 0);
+}
+
+/// Write the call chain of interrupted code from its `ip` and `rbp` to `out`.
+pub fn write_backtrace_from(out: &mut dyn Write, ip: usize, rbp: usize) {
+    let _ = writeln!(out, "backtrace:");
+    print_frame(out, 0, ip, false);
+    walk(out, rbp, 1);
+}
@@ -0,0 +1,92 @@
+use log::{info, warn};
+use x86_64::instructions::port::Port;
+
+use crate::{consts::FRAMEBUFFER_MODE, drivers::{framebuffer::{FrameBufferInfo, PixelFormat, init_framebuffer}, pci::{BAR, PCIDevice}}};
+
+use super::{consts::PAGE_SIZE, memory::map_mmio};
+
+/// the bochs display adapter, `-vga std` in qemu
+pub const VENDOR_ID: u16 = 0x1234;
+pub const DEVICE_ID: u16 = 0x1111;
+
+const DISPI_INDEX: u16 = 0x1ce;
+const DISPI_DATA: u16 = 0x1cf;
+
+const DISPI_ID: u16 = 0;
+const DISPI_XRES: u16 = 1;
+const DISPI_YRES: u16 = 2;
+const DISPI_BPP: u16 = 3;
+const DISPI_ENABLE: u16 = 4;
+const DISPI_VIRT_WIDTH: u16 = 6;
+
+/// first interface version with a linear framebuffer and 32 bpp
+const DISPI_ID_LFB: u16 = 0xb0c4;
+const DISPI_ID_MAX: u16 = 0xb0cf;
+
+const DISPI_ENABLED: u16 = 0x01;
+const DISPI_LFB_ENABLED: u16 = 0x40;
+
+fn dispi_read(index: u16) -> u16 {
+    unsafe {
+        Port::new(DISPI_INDEX).write(index);
+        Port::new(DISPI_DATA).read()
+    }
+}
+
+fn dispi_write(index: u16, value: u16) {
+    unsafe {
+        Port::new(DISPI_INDEX).write(index);
+        Port::new(DISPI_DATA).write(value);
+    }
+}
+
+/// Switch the adapter to `FRAMEBUFFER_MODE` and put the console on its
+/// linear framebuffer, which is BAR 0.
+pub fn init(dev: &PCIDevice) {
+    let id = dispi_read(DISPI_ID);
+    if !(DISPI_ID_LFB..=DISPI_ID_MAX).contains(&id) {
+        warn!("bochs display interface {:#x} has no linear framebuffer", id);
+        return;
+    }
+    let addr = match dev.bars[0] {
+        Some(BAR::Memory(addr, _, _, _)) => addr as usize,
+        _ => {
+            warn!("bochs display without a framebuffer bar");
+            return;
+        }
+    };
+    let (width, height, bpp) = FRAMEBUFFER_MODE;
+    dispi_write(DISPI_ENABLE, 0);
+    dispi_write(DISPI_XRES, width as u16);
+    dispi_write(DISPI_YRES, height as u16);
+    dispi_write(DISPI_BPP, bpp as u16);
+    // enabling clears the framebuffer
+    dispi_write(DISPI_ENABLE, DISPI_ENABLED | DISPI_LFB_ENABLED);
+
+    // the adapter rounds or refuses modes it cannot do
+    let width = dispi_read(DISPI_XRES) as usize;
+    let height = dispi_read(DISPI_YRES) as usize;
+    let bpp = dispi_read(DISPI_BPP) as usize;
+    // 8 bpp is palettized and 15 bpp is 5:5:5, neither is a format we draw
+    if ![16, 24, 32].contains(&bpp) {
+        warn!("bochs display refused {} bpp, left it at {} bpp", FRAMEBUFFER_MODE.2, bpp);
+        dispi_write(DISPI_ENABLE, 0);
+        return;
+    }
+    let bytes_per_pixel = bpp / 8;
+    let stride = dispi_read(DISPI_VIRT_WIDTH) as usize;
+    let byte_len = stride * height * bytes_per_pixel;
+    for page in (addr..addr + byte_len).step_by(PAGE_SIZE) {
+        map_mmio(page);
+    }
+    info!("bochs display {:#x}, {}x{}x{} at {:#x}", id, width, height, bytes_per_pixel * 8, addr);
+    init_framebuffer(addr, FrameBufferInfo {
+        byte_len,
+        horizontal_resolution: width,
+        vertical_resolution: height,
+        // little endian xrgb
+        pixel_format: PixelFormat::Bgr,
+        bytes_per_pixel,
+        stride,
+    });
+}
@@ -1,4 +1,9 @@
-use x86_64::registers::control::Cr2;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use alloc::sync::Arc;
+use x86_64::{VirtAddr, registers::{control::Cr2, model_specific::GsBase}};
+
+use crate::{consts::MAX_CPU_NUM, process::proc::Process, sync::mutex::SpinNoIrqLock};
 
 pub fn halt() {
     unsafe {
@@ -22,4 +27,119 @@ pub fn disable_pic() {
             "out 0x21, al"
         )
     }
-}
\ No newline at end of file
+}
+
+
+/// Per-cpu data area, the gs base of every cpu points to its own entry.
+#[repr(C)]
+pub struct PerCpu {
+    /// address of this structure, must stay the first field so `this_cpu`
+    /// can find it with a single `gs` relative load
+    self_ptr: AtomicUsize,
+    id: AtomicUsize,
+    apic_id: AtomicU32,
+    online: AtomicBool,
+    /// process running on this cpu
+    current: SpinNoIrqLock<Option<Arc<Process>>>,
+    /// process to run when the run queue is empty
+    idle: SpinNoIrqLock<Option<Arc<Process>>>,
+    /// exited process this cpu last switched away from, whose stack holds
+    /// the trap frame it is returning through
+    dying: SpinNoIrqLock<Option<Arc<Process>>>,
+}
+
+impl PerCpu {
+    const INIT: PerCpu = PerCpu {
+        self_ptr: AtomicUsize::new(0),
+        id: AtomicUsize::new(0),
+        apic_id: AtomicU32::new(0),
+        online: AtomicBool::new(false),
+        current: SpinNoIrqLock::new(None),
+        idle: SpinNoIrqLock::new(None),
+        dying: SpinNoIrqLock::new(None),
+    };
+
+    /// logical cpu id, 0 is the bsp
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    pub fn apic_id(&self) -> u32 {
+        self.apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+
+    pub fn current(&self) -> Option<Arc<Process>> {
+        self.current.lock().clone()
+    }
+
+    pub fn set_current(&self, proc: Option<Arc<Process>>) {
+        *self.current.lock() = proc;
+    }
+
+    pub fn idle(&self) -> Option<Arc<Process>> {
+        self.idle.lock().clone()
+    }
+
+    pub fn set_idle(&self, proc: Option<Arc<Process>>) {
+        *self.idle.lock() = proc;
+    }
+
+    /// Put `proc` in the dying slot, returning the one it held.
+    pub fn replace_dying(&self, proc: Option<Arc<Process>>) -> Option<Arc<Process>> {
+        core::mem::replace(&mut *self.dying.lock(), proc)
+    }
+}
+
+static CPUS: [PerCpu; MAX_CPU_NUM] = [PerCpu::INIT; MAX_CPU_NUM];
+
+/// Set up the per-cpu area of the calling cpu and point its gs base at it.
+pub fn init_percpu(id: usize, apic_id: u32) {
+    let cpu = &CPUS[id];
+    cpu.self_ptr.store(cpu as *const PerCpu as usize, Ordering::Relaxed);
+    cpu.id.store(id, Ordering::Relaxed);
+    cpu.apic_id.store(apic_id, Ordering::Relaxed);
+    GsBase::write(VirtAddr::new(cpu as *const PerCpu as u64));
+    cpu.online.store(true, Ordering::Release);
+}
+
+/// Per-cpu data of the calling cpu.
+#[inline]
+pub fn this_cpu() -> &'static PerCpu {
+    let ptr: usize;
+    unsafe {
+        asm!(
+            "mov {}, gs:[0]",
+            out(reg) ptr,
+            options(nostack, readonly)
+        );
+        &*(ptr as *const PerCpu)
+    }
+}
+
+/// logical id of the calling cpu
+#[inline]
+pub fn cpu_id() -> usize {
+    this_cpu().id()
+}
+
+/// logical id of the calling cpu, `None` before its per-cpu area is set up
+pub fn try_cpu_id() -> Option<usize> {
+    if GsBase::read().as_u64() == 0 {
+        return None;
+    }
+    Some(cpu_id())
+}
+
+/// Per-cpu data of cpu `id`.
+pub fn cpu(id: usize) -> &'static PerCpu {
+    &CPUS[id]
+}
+
+/// iterate over the cpus that finished booting
+pub fn online_cpus() -> impl Iterator<Item = &'static PerCpu> {
+    CPUS.iter().filter(|c| c.is_online())
+}
@@ -0,0 +1,555 @@
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::info;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+use crate::{consts::{GDB_COM, GDB_ENABLED, GDB_WAIT_AT_BOOT}, sync::mutex::SpinNoIrqLock};
+
+use super::{cpu::{cpu_id, online_cpus}, interrupt::{exception::{BREAKPOINT, DEBUG, DIVIDE_ERROR, PAGE_FAULT}, trap::TrapFrame}, lapic::send_nmi, memory::is_mapped, serial::{COM, SerialPort}};
+
+/// largest packet, as announced in `qSupported`
+const PACKET_SIZE: usize = 4096;
+const MAX_BREAKPOINTS: usize = 32;
+/// how long the other cpus get to park before the stub goes on without them
+const PARK_SPINS: usize = 10_000_000;
+
+const INT3: u8 = 0xcc;
+/// trap flag, raises a debug exception after the next instruction
+const RFLAGS_TF: u64 = 1 << 8;
+
+// signals reported in stop replies
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGFPE: u8 = 8;
+const SIGSEGV: u8 = 11;
+
+/// registers in the order of gdb's amd64 'g' packet: 16 general purpose
+/// registers, rip and eflags, then the 32 bit segment registers
+const GENERAL_REGS: usize = 16;
+const RIP: usize = GENERAL_REGS;
+const EFLAGS: usize = 17;
+const SEGMENT_REGS: usize = 6;
+const NUM_REGS: usize = GENERAL_REGS + 2 + SEGMENT_REGS;
+
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// index in `COM` of the port gdb talks on
+static PORT: AtomicUsize = AtomicUsize::new(0);
+/// a cpu is stopped in the debugger, the others park in their nmi handler
+static STOPPED: AtomicBool = AtomicBool::new(false);
+/// cpus parked until `STOPPED` is cleared
+static PARKED: AtomicUsize = AtomicUsize::new(0);
+
+struct Stub {
+    packet: [u8; PACKET_SIZE],
+    reply: [u8; PACKET_SIZE],
+    reply_len: usize,
+    /// software breakpoints: address and the byte the int3 replaced
+    breakpoints: [Option<(usize, u8)>; MAX_BREAKPOINTS],
+}
+
+/// also keeps a second cpu out while one is stopped in the debugger
+static STUB: SpinNoIrqLock<Stub> = SpinNoIrqLock::new(Stub {
+    packet: [0; PACKET_SIZE],
+    reply: [0; PACKET_SIZE],
+    reply_len: 0,
+    breakpoints: [None; MAX_BREAKPOINTS],
+});
+
+fn port() -> &'static SerialPort {
+    &COM[PORT.load(Ordering::Relaxed)]
+}
+
+/// whether traps and kernel faults go to the debugger
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// a big endian hex number, as used for addresses and lengths
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0u64, |acc, &c| Some(acc << 4 | hex_digit(c)? as u64))
+}
+
+fn parse_byte(s: &[u8]) -> Option<u8> {
+    Some(hex_digit(*s.get(0)?)? << 4 | hex_digit(*s.get(1)?)?)
+}
+
+/// a little endian value of `size` bytes, as registers are sent
+fn parse_le(s: &[u8], size: usize) -> Option<u64> {
+    if s.len() < size * 2 {
+        return None;
+    }
+    (0..size).try_fold(0u64, |acc, i| Some(acc | (parse_byte(&s[i * 2..])? as u64) << (i * 8)))
+}
+
+/// split `a,b` at the first `sep`
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let at = s.iter().position(|&c| c == sep)?;
+    Some((&s[..at], &s[at + 1..]))
+}
+
+fn reg(tf: &TrapFrame, n: usize) -> Option<u64> {
+    Some(match n {
+        0 => tf.rax,
+        1 => tf.rbx,
+        2 => tf.rcx,
+        3 => tf.rdx,
+        4 => tf.rsi,
+        5 => tf.rdi,
+        6 => tf.rbp,
+        7 => tf.rsp,
+        8 => tf.r8,
+        9 => tf.r9,
+        10 => tf.r10,
+        11 => tf.r11,
+        12 => tf.r12,
+        13 => tf.r13,
+        14 => tf.r14,
+        15 => tf.r15,
+        RIP => tf.ip,
+        EFLAGS => tf.rflags,
+        18 => tf.cs,
+        19 => tf.ss,
+        // ds, es, fs and gs are not saved, and unused in long mode
+        20..=23 => 0,
+        _ => return None,
+    })
+}
+
+/// Set register `n`, the segment registers are read only.
+fn set_reg(tf: &mut TrapFrame, n: usize, val: u64) -> bool {
+    let slot = match n {
+        0 => &mut tf.rax,
+        1 => &mut tf.rbx,
+        2 => &mut tf.rcx,
+        3 => &mut tf.rdx,
+        4 => &mut tf.rsi,
+        5 => &mut tf.rdi,
+        6 => &mut tf.rbp,
+        7 => &mut tf.rsp,
+        8 => &mut tf.r8,
+        9 => &mut tf.r9,
+        10 => &mut tf.r10,
+        11 => &mut tf.r11,
+        12 => &mut tf.r12,
+        13 => &mut tf.r13,
+        14 => &mut tf.r14,
+        15 => &mut tf.r15,
+        RIP => &mut tf.ip,
+        EFLAGS => &mut tf.rflags,
+        18..=23 => return true,
+        _ => return false,
+    };
+    *slot = val;
+    true
+}
+
+fn reg_size(n: usize) -> usize {
+    if n < EFLAGS { 8 } else { 4 }
+}
+
+fn range_mapped(addr: usize, len: usize) -> bool {
+    match addr.checked_add(len) {
+        Some(end) => (addr & !0xfff..end).step_by(0x1000).all(is_mapped),
+        None => false,
+    }
+}
+
+/// Write `bytes` at `addr`, kernel text is mapped read only so write
+/// protection is lifted meanwhile.
+fn poke(addr: usize, bytes: impl Iterator<Item = u8>) {
+    let cr0 = Cr0::read();
+    unsafe {
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        for (i, b) in bytes.enumerate() {
+            core::ptr::write_volatile((addr + i) as *mut u8, b);
+        }
+        Cr0::write(cr0);
+    }
+}
+
+/// What the debugger asked for once a packet has been answered.
+enum Resume {
+    Stay,
+    Continue,
+    Step,
+    /// `D`, breakpoints removed
+    Detach,
+    Kill,
+}
+
+impl Stub {
+    fn reply(&mut self, s: &[u8]) {
+        let n = s.len().min(PACKET_SIZE - self.reply_len);
+        self.reply[self.reply_len..self.reply_len + n].copy_from_slice(&s[..n]);
+        self.reply_len += n;
+    }
+
+    fn reply_hex(&mut self, val: u64, size: usize) {
+        for i in 0..size {
+            let b = (val >> (i * 8)) as u8;
+            self.reply(&[HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+        }
+    }
+
+    fn reply_error(&mut self, code: u8) {
+        self.reply_len = 0;
+        self.reply(&[b'E', HEX[(code >> 4) as usize], HEX[(code & 0xf) as usize]]);
+    }
+
+    /// Wait for a packet with a good checksum and acknowledge it,
+    /// returns its length in `self.packet`.
+    fn receive(&mut self) -> usize {
+        let port = port();
+        loop {
+            while port.read_byte_polled() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            loop {
+                let c = port.read_byte_polled();
+                if c == b'#' {
+                    break;
+                }
+                if len < PACKET_SIZE {
+                    self.packet[len] = c;
+                    len += 1;
+                }
+                sum = sum.wrapping_add(c);
+            }
+            let check = [port.read_byte_polled(), port.read_byte_polled()];
+            if parse_byte(&check) == Some(sum) {
+                port.write_byte_polled(b'+');
+                return len;
+            }
+            port.write_byte_polled(b'-');
+        }
+    }
+
+    /// Send the reply until gdb acknowledges it.
+    fn send(&mut self) {
+        let port = port();
+        loop {
+            port.write_byte_polled(b'$');
+            let mut sum = 0u8;
+            for &c in self.reply[..self.reply_len].iter() {
+                port.write_byte_polled(c);
+                sum = sum.wrapping_add(c);
+            }
+            port.write_byte_polled(b'#');
+            port.write_byte_polled(HEX[(sum >> 4) as usize]);
+            port.write_byte_polled(HEX[(sum & 0xf) as usize]);
+            // anything but an ack means it has to go again
+            if port.read_byte_polled() == b'+' {
+                break;
+            }
+        }
+        self.reply_len = 0;
+    }
+
+    fn stop_reply(&mut self, signal: u8) {
+        self.reply(&[b'S', HEX[(signal >> 4) as usize], HEX[(signal & 0xf) as usize]]);
+    }
+
+    fn read_registers(&mut self, tf: &TrapFrame) {
+        for n in 0..NUM_REGS {
+            self.reply_hex(reg(tf, n).unwrap_or(0), reg_size(n));
+        }
+    }
+
+    fn write_registers(&mut self, tf: &mut TrapFrame, len: usize) {
+        let mut at = 1;
+        for n in 0..NUM_REGS {
+            let size = reg_size(n);
+            match parse_le(&self.packet[at..len], size) {
+                Some(val) => set_reg(tf, n, val),
+                None => break,
+            };
+            at += size * 2;
+        }
+        self.reply(b"OK");
+    }
+
+    fn read_register(&mut self, tf: &TrapFrame, len: usize) {
+        match parse_hex(&self.packet[1..len]).and_then(|n| Some((n as usize, reg(tf, n as usize)?))) {
+            Some((n, val)) => self.reply_hex(val, reg_size(n)),
+            None => self.reply_error(0),
+        }
+    }
+
+    fn write_register(&mut self, tf: &mut TrapFrame, len: usize) {
+        let ok = split(&self.packet[1..len], b'=')
+            .and_then(|(n, val)| {
+                let n = parse_hex(n)? as usize;
+                Some(set_reg(tf, n, parse_le(val, reg_size(n))?))
+            })
+            .unwrap_or(false);
+        if ok { self.reply(b"OK") } else { self.reply_error(0) }
+    }
+
+    fn read_memory(&mut self, len: usize) {
+        let range = split(&self.packet[1..len], b',')
+            .and_then(|(addr, n)| Some((parse_hex(addr)? as usize, parse_hex(n)? as usize)));
+        match range {
+            Some((addr, n)) if n * 2 <= PACKET_SIZE && range_mapped(addr, n) => {
+                for i in 0..n {
+                    let b = unsafe { core::ptr::read_volatile((addr + i) as *const u8) };
+                    self.reply_hex(b as u64, 1);
+                }
+            }
+            _ => self.reply_error(SIGSEGV),
+        }
+    }
+
+    fn write_memory(&mut self, len: usize) {
+        let parsed = split(&self.packet[1..len], b':').and_then(|(range, data)| {
+            let (addr, n) = split(range, b',')?;
+            let (addr, n) = (parse_hex(addr)? as usize, parse_hex(n)? as usize);
+            if data.len() < n * 2 || !range_mapped(addr, n) {
+                return None;
+            }
+            Some((addr, n, data))
+        });
+        match parsed {
+            Some((addr, n, data)) => {
+                poke(addr, (0..n).map(|i| parse_byte(&data[i * 2..]).unwrap_or(0)));
+                self.reply(b"OK");
+            }
+            None => self.reply_error(SIGSEGV),
+        }
+    }
+
+    /// `Z0,addr,kind` and `z0,addr,kind`, other breakpoint types are not supported
+    fn set_breakpoint(&mut self, len: usize) {
+        let insert = self.packet[0] == b'Z';
+        let args = &self.packet[1..len];
+        if args.get(0) != Some(&b'0') {
+            return;
+        }
+        let addr = match split(&args[2.min(args.len())..], b',').and_then(|(addr, _)| parse_hex(addr)) {
+            Some(addr) => addr as usize,
+            None => return self.reply_error(0),
+        };
+        let slot = self.breakpoints.iter().position(|b| b.map_or(false, |(a, _)| a == addr));
+        match (insert, slot) {
+            (true, Some(_)) | (false, None) => self.reply(b"OK"),
+            (true, None) => {
+                let free = self.breakpoints.iter().position(Option::is_none);
+                match free {
+                    Some(free) if range_mapped(addr, 1) => {
+                        let orig = unsafe { core::ptr::read_volatile(addr as *const u8) };
+                        poke(addr, core::iter::once(INT3));
+                        self.breakpoints[free] = Some((addr, orig));
+                        self.reply(b"OK");
+                    }
+                    _ => self.reply_error(SIGSEGV),
+                }
+            }
+            (false, Some(slot)) => {
+                let (addr, orig) = self.breakpoints[slot].take().unwrap();
+                poke(addr, core::iter::once(orig));
+                self.reply(b"OK");
+            }
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for b in self.breakpoints.iter_mut() {
+            if let Some((addr, orig)) = b.take() {
+                poke(addr, core::iter::once(orig));
+            }
+        }
+    }
+
+    /// `c [addr]` and `s [addr]` may name where to resume
+    fn resume_at(&self, tf: &mut TrapFrame, len: usize) {
+        if let Some(addr) = parse_hex(&self.packet[1..len]) {
+            tf.ip = addr;
+        }
+    }
+
+    fn handle_packet(&mut self, tf: &mut TrapFrame, signal: u8, len: usize) -> Resume {
+        if len == 0 {
+            return Resume::Stay;
+        }
+        match self.packet[0] {
+            b'?' => self.stop_reply(signal),
+            b'g' => self.read_registers(tf),
+            b'G' => self.write_registers(tf, len),
+            b'p' => self.read_register(tf, len),
+            b'P' => self.write_register(tf, len),
+            b'm' => self.read_memory(len),
+            b'M' => self.write_memory(len),
+            b'Z' | b'z' => self.set_breakpoint(len),
+            b'c' => {
+                self.resume_at(tf, len);
+                return Resume::Continue;
+            }
+            b's' => {
+                self.resume_at(tf, len);
+                return Resume::Step;
+            }
+            b'D' => {
+                self.remove_all_breakpoints();
+                self.reply(b"OK");
+                self.send();
+                return Resume::Detach;
+            }
+            b'k' => {
+                self.remove_all_breakpoints();
+                return Resume::Kill;
+            }
+            b'q' if self.packet[..len].starts_with(b"qSupported") => {
+                self.reply(b"PacketSize=1000");
+            }
+            b'q' if self.packet[..len].starts_with(b"qAttached") => self.reply(b"1"),
+            b'q' if &self.packet[..len] == b"qC" => self.reply(b"QC1"),
+            b'H' => self.reply(b"OK"),
+            // anything else is answered with an empty packet, meaning unsupported
+            _ => {}
+        }
+        self.send();
+        Resume::Stay
+    }
+
+    /// Report the stop to gdb and serve its requests until it resumes,
+    /// detaches or kills.
+    fn run(&mut self, tf: &mut TrapFrame, signal: u8) -> Resume {
+        self.stop_reply(signal);
+        self.send();
+        loop {
+            let len = self.receive();
+            match self.handle_packet(tf, signal, len) {
+                Resume::Stay => {}
+                Resume::Step => {
+                    tf.rflags |= RFLAGS_TF;
+                    return Resume::Step;
+                }
+                resume => {
+                    tf.rflags &= !RFLAGS_TF;
+                    return resume;
+                }
+            }
+        }
+    }
+}
+
+fn signal_for(vector: usize) -> u8 {
+    match vector {
+        BREAKPOINT | DEBUG => SIGTRAP,
+        DIVIDE_ERROR | 16 | 19 => SIGFPE,
+        6 => SIGILL,
+        PAGE_FAULT | 12 | 13 => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+/// Whether the nmi being handled asks this cpu to park.
+pub fn park_requested() -> bool {
+    STOPPED.load(Ordering::Acquire)
+}
+
+/// Wait in the nmi handler while another cpu is stopped in the debugger.
+pub fn park() {
+    PARKED.fetch_add(1, Ordering::AcqRel);
+    while STOPPED.load(Ordering::Acquire) {
+        core::hint::spin_loop();
+    }
+    PARKED.fetch_sub(1, Ordering::AcqRel);
+}
+
+/// Park every other online cpu with an nmi, which gets through even where
+/// they run with interrupts disabled.
+fn stop_others() {
+    STOPPED.store(true, Ordering::Release);
+    let me = cpu_id();
+    let mut others = 0;
+    for cpu in online_cpus().filter(|c| c.id() != me) {
+        send_nmi(cpu.apic_id());
+        others += 1;
+    }
+    for _ in 0..PARK_SPINS {
+        if PARKED.load(Ordering::Acquire) >= others {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Let the parked cpus go, and wait until they left so the next stop
+/// counts them again.
+fn release_others() {
+    STOPPED.store(false, Ordering::Release);
+    for _ in 0..PARK_SPINS {
+        if PARKED.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Stop in the debugger on exception `vector` of the code in `tf`,
+/// returning when gdb continues or steps.
+///
+/// the other cpus are parked meanwhile; one that hit a trap of its own
+/// before it got the nmi stops in the debugger after this one resumes.
+/// continuing a fault runs the faulting instruction again, which faults
+/// again unless gdb changed what caused it; `k`, or detaching from a fault,
+/// panics instead
+pub fn handle_trap(vector: usize, tf: &mut TrapFrame) {
+    let mut stub = STUB.lock();
+    stop_others();
+    if vector == BREAKPOINT {
+        // rip is past the int3, gdb wants it on the breakpoint it set
+        let at = tf.ip as usize - 1;
+        if stub.breakpoints.iter().any(|b| b.map_or(false, |(a, _)| a == at)) {
+            tf.ip -= 1;
+        }
+    }
+    if vector == DEBUG {
+        tf.rflags &= !RFLAGS_TF;
+    }
+    let resume = stub.run(tf, signal_for(vector));
+    release_others();
+    drop(stub);
+    match resume {
+        Resume::Kill => panic!("killed from gdb"),
+        // nobody would stop the fault it comes back to
+        Resume::Detach if !matches!(vector, BREAKPOINT | DEBUG) => panic!("gdb detached from a fault"),
+        _ => {}
+    }
+}
+
+/// Stop in the debugger at the caller.
+#[inline(always)]
+pub fn breakpoint() {
+    unsafe { asm!("int3", options(nomem, nostack)) };
+}
+
+/// Make `COM[GDB_COM]` the debugger port if `GDB_ENABLED` is set and the
+/// port is present, and wait for gdb right away if `GDB_WAIT_AT_BOOT` is set.
+///
+/// must run after `init_serial`
+pub fn init_gdb() {
+    if !GDB_ENABLED || GDB_COM >= COM.len() || !COM[GDB_COM].is_present() {
+        return;
+    }
+    PORT.store(GDB_COM, Ordering::Relaxed);
+    ACTIVE.store(true, Ordering::Relaxed);
+    info!("stub on com{}", GDB_COM + 1);
+    if GDB_WAIT_AT_BOOT {
+        info!("waiting for gdb");
+        breakpoint();
+    }
+}
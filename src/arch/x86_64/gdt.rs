@@ -0,0 +1,59 @@
+use alloc::boxed::Box;
+use x86_64::{PrivilegeLevel, VirtAddr};
+use x86_64::instructions::{segmentation::{load_ds, load_es, load_ss, set_cs}, tables::load_tss};
+use x86_64::structures::{gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector}, tss::TaskStateSegment};
+
+use crate::memory::{BITMAP_ALLOCATOR, addr::phys_to_virt, bitalloc::BitAlloc};
+
+use super::consts::PAGE_SIZE;
+
+/// ist slot used by the double fault handler
+pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
+/// ist slot used by external interrupts, so the trap frame of the interrupted
+/// process is not on its own stack while another cpu may already resume it
+pub const IRQ_IST_INDEX: u16 = 1;
+
+/// ist slots of the non maskable interrupt and the machine check, which can
+/// arrive in the middle of any other handler. the other exceptions run on
+/// the stack they happened on, so a nested one pushes below the outer frame
+pub const NMI_IST_INDEX: u16 = 2;
+pub const MACHINE_CHECK_IST_INDEX: u16 = 3;
+
+/// kernel code selector, the same on every cpu
+pub const KERNEL_CS: u16 = 1 << 3;
+
+/// size of each interrupt stack, in 4k frames
+const IST_STACK_FRAMES: usize = 4;
+
+/// Build and load the gdt and tss of the calling cpu.
+///
+/// each cpu needs its own tss (the cpu marks it busy on `ltr`),
+/// so both tables are allocated once per cpu and never freed
+pub fn init_gdt() {
+    let mut tss = TaskStateSegment::new();
+    for &index in [DOUBLE_FAULT_IST_INDEX, IRQ_IST_INDEX, NMI_IST_INDEX, MACHINE_CHECK_IST_INDEX].iter() {
+        let frame = BITMAP_ALLOCATOR.lock()
+            .alloc_contiguous(IST_STACK_FRAMES, 0)
+            .expect("no memory for interrupt stack");
+        let top = phys_to_virt((frame + IST_STACK_FRAMES) * PAGE_SIZE);
+        tss.interrupt_stack_table[index as usize] = VirtAddr::new(top as u64);
+    }
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+
+    let mut gdt = GlobalDescriptorTable::new();
+    let code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let tss_selector = gdt.add_entry(Descriptor::tss_segment(tss));
+    debug_assert_eq!(code.0, KERNEL_CS);
+    let gdt: &'static GlobalDescriptorTable = Box::leak(Box::new(gdt));
+
+    gdt.load();
+    unsafe {
+        let null = SegmentSelector::new(0, PrivilegeLevel::Ring0);
+        set_cs(code);
+        load_ss(null);
+        load_ds(null);
+        load_es(null);
+        load_tss(tss_selector);
+    }
+}
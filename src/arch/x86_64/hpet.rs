@@ -0,0 +1,91 @@
+use core::{ptr::{read_volatile, write_volatile}, sync::atomic::{AtomicU64, AtomicUsize, Ordering}};
+
+use crate::time::clocksource::{ClockSource, register_clocksource};
+
+use super::{acpi::acpi, memory::map_mmio};
+use log::info;
+
+const CAPABILITIES: usize = 0x000;
+const CONFIG: usize = 0x010;
+const MAIN_COUNTER: usize = 0x0f0;
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+
+const FS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// address of the hpet registers, 0 if there is none
+static HPET_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// length of a main counter tick, in femtoseconds
+static PERIOD_FS: AtomicU64 = AtomicU64::new(0);
+
+fn read(reg: usize) -> u64 {
+    unsafe { read_volatile((HPET_BASE.load(Ordering::Relaxed) + reg) as *const u64) }
+}
+
+fn write(reg: usize, val: u64) {
+    unsafe { write_volatile((HPET_BASE.load(Ordering::Relaxed) + reg) as *mut u64, val) }
+}
+
+/// Start the main counter of the hpet listed in the ACPI tables, if any.
+pub fn init_hpet() {
+    let info = match acpi().and_then(|a| a.hpet) {
+        Some(info) => info,
+        None => return,
+    };
+    map_mmio(info.address);
+    HPET_BASE.store(info.address, Ordering::Relaxed);
+    let period = read(CAPABILITIES) >> 32;
+    if period == 0 {
+        HPET_BASE.store(0, Ordering::Relaxed);
+        return;
+    }
+    PERIOD_FS.store(period, Ordering::Relaxed);
+    write(CONFIG, read(CONFIG) | CONFIG_ENABLE);
+    info!("{} Hz", frequency());
+    register_clocksource(&HpetClock);
+}
+
+/// The hpet main counter, slow to read but the same on every cpu.
+struct HpetClock;
+
+impl ClockSource for HpetClock {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn read(&self) -> u64 {
+        counter()
+    }
+
+    fn frequency(&self) -> u64 {
+        frequency()
+    }
+
+    fn rating(&self) -> u32 {
+        250
+    }
+}
+
+pub fn is_present() -> bool {
+    HPET_BASE.load(Ordering::Relaxed) != 0
+}
+
+/// main counter, only meaningful when `is_present`
+#[inline]
+pub fn counter() -> u64 {
+    read(MAIN_COUNTER)
+}
+
+/// main counter ticks per second
+pub fn frequency() -> u64 {
+    FS_PER_SEC / PERIOD_FS.load(Ordering::Relaxed).max(1)
+}
+
+/// Busy wait for `us` microseconds.
+pub fn udelay(us: u64) {
+    let end = counter() + us * 1_000_000_000 / PERIOD_FS.load(Ordering::Relaxed).max(1);
+    while counter() < end {
+        core::hint::spin_loop();
+    }
+}
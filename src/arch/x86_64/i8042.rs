@@ -0,0 +1,179 @@
+use log::warn;
+use x86_64::instructions::port::Port;
+
+use crate::sync::mutex::SpinNoIrqLock;
+
+const DATA: u16 = 0x60;
+/// status on read, command on write
+const STATUS_COMMAND: u16 = 0x64;
+
+pub const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// the byte in the output buffer came from the second (mouse) port
+pub const STATUS_AUX_DATA: u8 = 1 << 5;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_AUX: u8 = 0xa7;
+const CMD_ENABLE_AUX: u8 = 0xa8;
+const CMD_DISABLE_KBD: u8 = 0xad;
+const CMD_ENABLE_KBD: u8 = 0xae;
+/// the next data byte goes to the second port
+const CMD_WRITE_AUX: u8 = 0xd4;
+/// pulse the cpu reset line
+const CMD_PULSE_RESET: u8 = 0xfe;
+
+pub const CONFIG_KBD_IRQ: u8 = 1 << 0;
+pub const CONFIG_AUX_IRQ: u8 = 1 << 1;
+pub const CONFIG_KBD_CLOCK_OFF: u8 = 1 << 4;
+pub const CONFIG_AUX_CLOCK_OFF: u8 = 1 << 5;
+/// scancode set 2 from the keyboard is translated to set 1
+pub const CONFIG_TRANSLATE: u8 = 1 << 6;
+
+/// device acknowledged a command
+pub const ACK: u8 = 0xfa;
+pub const RESEND: u8 = 0xfe;
+
+/// isa irqs of the two ports
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const MOUSE_IRQ: u8 = 12;
+
+/// polls before a controller or device is considered gone
+const TIMEOUT: usize = 100_000;
+
+/// the two ports of the controller
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Port {
+    Keyboard,
+    Aux,
+}
+
+/// Serializes command sequences, the controller has a single data port.
+static CONTROLLER: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+
+fn status() -> u8 {
+    unsafe { Port::new(STATUS_COMMAND).read() }
+}
+
+fn wait_input_empty() -> bool {
+    (0..TIMEOUT).any(|_| status() & STATUS_INPUT_FULL == 0)
+}
+
+fn wait_output_full() -> bool {
+    (0..TIMEOUT).any(|_| status() & STATUS_OUTPUT_FULL != 0)
+}
+
+fn command(cmd: u8) {
+    if wait_input_empty() {
+        unsafe { Port::new(STATUS_COMMAND).write(cmd) }
+    }
+}
+
+fn write_data(val: u8) {
+    if wait_input_empty() {
+        unsafe { Port::new(DATA).write(val) }
+    }
+}
+
+fn read_data() -> Option<u8> {
+    if wait_output_full() {
+        Some(unsafe { Port::new(DATA).read() })
+    } else {
+        None
+    }
+}
+
+/// The byte waiting in the output buffer if it came from `port`,
+/// a byte of the other port is left for its own handler.
+///
+/// for interrupt handlers, which must not wait
+pub fn read_pending(port: Ps2Port) -> Option<u8> {
+    let status = status();
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+    let from = if status & STATUS_AUX_DATA != 0 { Ps2Port::Aux } else { Ps2Port::Keyboard };
+    if from != port {
+        return None;
+    }
+    Some(unsafe { Port::new(DATA).read() })
+}
+
+/// Drop whatever the devices sent that nobody read.
+fn flush() {
+    for _ in 0..16 {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { Port::<u8>::new(DATA).read() };
+    }
+}
+
+/// Change the controller configuration byte, returns the new value.
+///
+/// nothing is written if the controller does not answer with the current
+/// one, a guess would turn off what `f` does not know about
+pub fn update_config(f: impl FnOnce(u8) -> u8) -> Option<u8> {
+    let _controller = CONTROLLER.lock();
+    command(CMD_READ_CONFIG);
+    let config = match read_data() {
+        Some(config) => f(config),
+        None => {
+            warn!("no reply to read config");
+            return None;
+        }
+    };
+    command(CMD_WRITE_CONFIG);
+    write_data(config);
+    Some(config)
+}
+
+/// Send `byte` to the device on `port` and return its reply,
+/// resending when asked to.
+pub fn send(port: Ps2Port, byte: u8) -> Option<u8> {
+    let _controller = CONTROLLER.lock();
+    for _ in 0..3 {
+        if port == Ps2Port::Aux {
+            command(CMD_WRITE_AUX);
+        }
+        write_data(byte);
+        match read_data() {
+            Some(RESEND) => continue,
+            reply => return reply,
+        }
+    }
+    None
+}
+
+/// Send a command and its argument bytes, true if every byte was acknowledged.
+pub fn send_command(port: Ps2Port, bytes: &[u8]) -> bool {
+    bytes.iter().all(|&b| send(port, b) == Some(ACK))
+}
+
+/// next byte from the device, for replies that follow the ack
+pub fn receive() -> Option<u8> {
+    let _controller = CONTROLLER.lock();
+    read_data()
+}
+
+/// Turn both ports off and drop pending bytes, so the configuration
+/// can be changed without a device interfering.
+pub fn disable_ports() {
+    let _controller = CONTROLLER.lock();
+    command(CMD_DISABLE_KBD);
+    command(CMD_DISABLE_AUX);
+    flush();
+}
+
+pub fn enable_port(port: Ps2Port) {
+    let _controller = CONTROLLER.lock();
+    command(match port {
+        Ps2Port::Keyboard => CMD_ENABLE_KBD,
+        Ps2Port::Aux => CMD_ENABLE_AUX,
+    });
+}
+
+/// Reset the machine through the controller's reset line, returns if nothing happened.
+pub fn pulse_reset() {
+    command(CMD_PULSE_RESET);
+}
@@ -0,0 +1,176 @@
+use core::fmt::Write;
+
+use x86_64::structures::idt::PageFaultErrorCode;
+
+use crate::{arch::{backtrace::write_backtrace_from, cpu::cpu_id, gdb, memory::is_mapped, serial::Polled}, process::{SCHEDULE, current, is_idle, proc::kill_current, runqueue::schedule}};
+
+use super::trap::TrapFrame;
+
+pub const DIVIDE_ERROR: usize = 0;
+pub const DEBUG: usize = 1;
+pub const NMI: usize = 2;
+pub const BREAKPOINT: usize = 3;
+pub const DOUBLE_FAULT: usize = 8;
+pub const PAGE_FAULT: usize = 14;
+pub const MACHINE_CHECK: usize = 18;
+
+/// interrupts were enabled where the fault happened
+const RFLAGS_IF: u64 = 1 << 9;
+
+const EXCEPTION_NAMES: [&str; 32] = [
+    "DIVIDE ERROR",
+    "DEBUG",
+    "NON MASKABLE INTERRUPT",
+    "BREAKPOINT",
+    "OVERFLOW",
+    "BOUND RANGE EXCEEDED",
+    "INVALID OPCODE",
+    "DEVICE NOT AVAILABLE",
+    "DOUBLE FAULT",
+    "COPROCESSOR SEGMENT OVERRUN",
+    "INVALID TSS",
+    "SEGMENT NOT PRESENT",
+    "STACK SEGMENT FAULT",
+    "GENERAL PROTECTION FAULT",
+    "PAGE FAULT",
+    "RESERVED",
+    "X87 FLOATING POINT",
+    "ALIGNMENT CHECK",
+    "MACHINE CHECK",
+    "SIMD FLOATING POINT",
+    "VIRTUALIZATION",
+    "CONTROL PROTECTION",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "RESERVED",
+    "HYPERVISOR INJECTION",
+    "VMM COMMUNICATION",
+    "SECURITY EXCEPTION",
+    "RESERVED",
+];
+
+/// instruction bytes shown in a dump, the longest x86 instruction is 15 bytes
+const CODE_DUMP_LEN: usize = 16;
+
+/// whether the cpu pushes an error code for `vector`
+fn has_error_code(vector: usize) -> bool {
+    matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+}
+
+/// Handle exception `vector`, called by `myfun` with the trap frame of the faulting code.
+pub fn handle_exception(vector: usize, tf: &mut TrapFrame) {
+    match vector {
+        BREAKPOINT => breakpoint_handler(tf),
+        // single step trap, from the trap flag the gdb stub sets
+        DEBUG if gdb::is_active() => gdb::handle_trap(vector, tf),
+        NMI if gdb::park_requested() => gdb::park(),
+        NMI => {
+            dump(vector, tf);
+        }
+        DOUBLE_FAULT | MACHINE_CHECK => {
+            dump(vector, tf);
+            panic!("EXCEPTION: {}", EXCEPTION_NAMES[vector]);
+        }
+        _ => fault(vector, tf),
+    }
+}
+
+pub fn breakpoint_handler(tf: &mut TrapFrame) {
+    if gdb::is_active() {
+        gdb::handle_trap(BREAKPOINT, tf);
+    } else {
+        dump(BREAKPOINT, tf);
+    }
+}
+
+/// A fault is handed to the debugger if the gdb stub is active. otherwise
+/// a kernel thread that faulted in its own code is killed, and any other
+/// fault panics.
+///
+/// everything runs in ring 0, so the interrupt flag tells the cases apart:
+/// with interrupts enabled the thread was neither in an interrupt handler
+/// nor holding a lock that disables them, and the rest of the kernel is
+/// left consistent when it goes
+fn fault(vector: usize, tf: &mut TrapFrame) {
+    dump(vector, tf);
+    if gdb::is_active() {
+        gdb::handle_trap(vector, tf);
+        return;
+    }
+    let in_thread = tf.rflags & RFLAGS_IF != 0;
+    let killable = current().map_or(false, |p| !is_idle(&p));
+    if in_thread && killable && *SCHEDULE.read() {
+        let _ = writeln!(Polled, "killing process {}", current().unwrap().pid());
+        kill_current();
+        schedule(tf);
+        return;
+    }
+    panic!("EXCEPTION: {} in kernel mode", EXCEPTION_NAMES[vector]);
+}
+
+/// print the exception, its error code, every register and the faulting instruction,
+/// and the call chain for a fault in the kernel
+///
+/// it goes to COM1 polled, without the console, heap or serial queue
+/// locks, any of which the faulting code may hold
+fn dump(vector: usize, tf: &TrapFrame) {
+    let _ = write_dump(&mut Polled, vector, tf);
+}
+
+fn write_dump(out: &mut dyn Write, vector: usize, tf: &TrapFrame) -> core::fmt::Result {
+    writeln!(out, "EXCEPTION: {} (#{}) on cpu {}", EXCEPTION_NAMES[vector], vector, cpu_id())?;
+    if has_error_code(vector) {
+        write!(out, "error code: {:#x} ", tf.err)?;
+        describe_error(out, vector, tf)?;
+        writeln!(out)?;
+    }
+    writeln!(out, "rip {:#018x} cs  {:#06x}             rflags {:#018x}", tf.ip, tf.cs, tf.rflags)?;
+    writeln!(out, "rsp {:#018x} ss  {:#06x}             cr2    {:#018x}", tf.rsp, tf.ss, tf.cr2)?;
+    writeln!(out, "rax {:#018x} rbx {:#018x} rcx {:#018x}", tf.rax, tf.rbx, tf.rcx)?;
+    writeln!(out, "rdx {:#018x} rsi {:#018x} rdi {:#018x}", tf.rdx, tf.rsi, tf.rdi)?;
+    writeln!(out, "rbp {:#018x} r8  {:#018x} r9  {:#018x}", tf.rbp, tf.r8, tf.r9)?;
+    writeln!(out, "r10 {:#018x} r11 {:#018x} r12 {:#018x}", tf.r10, tf.r11, tf.r12)?;
+    writeln!(out, "r13 {:#018x} r14 {:#018x} r15 {:#018x}", tf.r13, tf.r14, tf.r15)?;
+    write!(out, "code: ")?;
+    code_bytes(out, tf.ip as usize)?;
+    writeln!(out)?;
+    if tf.cs & 3 == 0 {
+        write_backtrace_from(out, tf.ip as usize, tf.rbp as usize);
+    }
+    Ok(())
+}
+
+fn describe_error(out: &mut dyn Write, vector: usize, tf: &TrapFrame) -> core::fmt::Result {
+    match vector {
+        PAGE_FAULT => {
+            let code = PageFaultErrorCode::from_bits_truncate(tf.err);
+            write!(out, "({:?}) accessing {:#x}", code, tf.cr2)
+        }
+        // the error code is a segment selector index
+        10..=13 if tf.err != 0 => {
+            let table = match (tf.err >> 1) & 3 {
+                0 => "gdt",
+                2 => "ldt",
+                _ => "idt",
+            };
+            let external = if tf.err & 1 != 0 { " external" } else { "" };
+            write!(out, "({} index {}{})", table, (tf.err >> 3) & 0x1fff, external)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// the bytes at `ip`, read only if mapped so a bad `ip` does not fault again
+fn code_bytes(out: &mut dyn Write, ip: usize) -> core::fmt::Result {
+    if !is_mapped(ip) || !is_mapped(ip.wrapping_add(CODE_DUMP_LEN - 1)) {
+        return write!(out, "<unmapped>");
+    }
+    for i in 0..CODE_DUMP_LEN {
+        let byte = unsafe { core::ptr::read_volatile((ip + i) as *const u8) };
+        write!(out, "{:02x} ", byte)?;
+    }
+    Ok(())
+}
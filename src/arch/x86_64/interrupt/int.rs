@@ -1,25 +1,89 @@
 
-use core::{intrinsics::volatile_copy_nonoverlapping_memory, mem::size_of};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::{arch::{cpu::{cpu_id, disable_pic}, gdt::{DOUBLE_FAULT_IST_INDEX, IRQ_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX}, lapic::{RESCHEDULE_VECTOR, SPURIOUS_VECTOR, eoi, init_lapic}, timer::{TIMER_VECTOR, init_timer, on_timer_interrupt}}, drivers::irq::handle_irq, process::{SCHEDULE, runqueue::tick}};
 
-use crate::{arch::cpu::disable_pic, process::SCHEDULE};
-
-use apic::LocalApic;
 use lazy_static::lazy_static;
 
-use crate::process::proc::PROCESSES;
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable};
+
+use super::{exception::handle_exception, trap::TrapFrame};
+
+global_asm!(include_str!("vector.S"));
+
+extern "C" {
+    fn vector_stubs_start();
+}
+
+/// first vector of external interrupts, irq n arrives on vector `IRQ0 + n`
+pub const IRQ0: usize = 32;
 
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+/// irqs handed out by `alloc_irq`, vectors 64 to 0xef: above the isa irqs
+/// and the local apic's error (51) and spurious (63) vectors, below the ipis
+const DYNAMIC_IRQS: core::ops::Range<usize> = 32..0xf0 - IRQ0;
 
-use super::{ctx::Context, trap::TrapFrame};
+static NEXT_IRQ: AtomicUsize = AtomicUsize::new(DYNAMIC_IRQS.start);
+
+/// An irq number of its own for a device, none once the vectors ran out.
+pub fn alloc_irq() -> Option<usize> {
+    let irq = NEXT_IRQ.fetch_add(1, Ordering::Relaxed);
+    Some(irq).filter(|irq| DYNAMIC_IRQS.contains(irq))
+}
+
+/// size of each stub in vector.S
+const VECTOR_STUB_SIZE: usize = 16;
+
+/// entry stub of `vector`
+fn stub(vector: usize) -> usize {
+    vector_stubs_start as usize + vector * VECTOR_STUB_SIZE
+}
+
+/// point an exception entry at its stub, the entry types differ
+/// between exceptions with and without an error code. without an ist slot
+/// the handler runs on the stack of the code that faulted
+macro_rules! set_exception {
+    ($entry:expr, $vector:expr) => {
+        unsafe {
+            $entry.set_handler_fn(core::mem::transmute(stub($vector)));
+        }
+    };
+    ($entry:expr, $vector:expr, $ist:expr) => {
+        unsafe {
+            $entry.set_handler_fn(core::mem::transmute(stub($vector)))
+                .set_stack_index($ist);
+        }
+    };
+}
 
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
-        idt.double_fault.set_handler_fn(double_fault_handler);
-        idt.page_fault.set_handler_fn(page_fault_handler);
-        idt[32].set_handler_fn(unsafe {core::mem::transmute(irq0 as extern "C" fn())});
+        set_exception!(idt.divide_error, 0);
+        set_exception!(idt.debug, 1);
+        set_exception!(idt.non_maskable_interrupt, 2, NMI_IST_INDEX);
+        set_exception!(idt.breakpoint, 3);
+        set_exception!(idt.overflow, 4);
+        set_exception!(idt.bound_range_exceeded, 5);
+        set_exception!(idt.invalid_opcode, 6);
+        set_exception!(idt.device_not_available, 7);
+        set_exception!(idt.double_fault, 8, DOUBLE_FAULT_IST_INDEX);
+        set_exception!(idt.invalid_tss, 10);
+        set_exception!(idt.segment_not_present, 11);
+        set_exception!(idt.stack_segment_fault, 12);
+        set_exception!(idt.general_protection_fault, 13);
+        set_exception!(idt.page_fault, 14);
+        set_exception!(idt.x87_floating_point, 16);
+        set_exception!(idt.alignment_check, 17);
+        set_exception!(idt.machine_check, 18, MACHINE_CHECK_IST_INDEX);
+        set_exception!(idt.simd_floating_point, 19);
+        set_exception!(idt.virtualization, 20);
+        set_exception!(idt.security_exception, 30);
+        for vector in IRQ0..256 {
+            unsafe {
+                idt[vector].set_handler_fn(core::mem::transmute::<usize, HandlerFunc>(stub(vector)))
+                    .set_stack_index(IRQ_IST_INDEX);
+            }
+        }
         
         idt
     };
@@ -33,47 +97,22 @@ pub fn init_idt() {
     disable_pic();
     x86_64::instructions::interrupts::enable();
 
-    let mut me = unsafe { apic::XApic::new(0xfee00000) };
-    me.cpu_init();
+    init_lapic();
+    init_timer();
     //println!("hello world");
 }
 
-
-
-
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
-}
-
-
-
-extern "x86-interrupt" fn double_fault_handler(
-    stack_frame: &mut InterruptStackFrame, _error_code: u64) -> !
-{
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+/// load the shared idt and enable the local apic on an application processor
+pub fn init_ap_idt() {
+    IDT.load();
+    init_lapic();
+    init_timer();
+    x86_64::instructions::interrupts::enable();
 }
 
-extern "x86-interrupt" fn page_fault_handler(_stack_frame: &mut InterruptStackFrame, _error_code: PageFaultErrorCode) {
-    println!("{:?}", _error_code);
-    panic!("page fault");
-    
-}
 
 
 
-#[naked]
-extern "C" fn irq0() {
-    unsafe {
-    asm!(
-        "push rsp",
-        "push rax",
-        "mov rax, 0",
-        "call irq_common",
-        options(noreturn)
-    )
-    }
-}
-
 /// adopted from https://gist.github.com/mark-i-m/361cbcc39769f965b1c419091b9cbf4f#file-machine-rs-L248
 #[no_mangle]
 #[naked]
@@ -96,6 +135,8 @@ unsafe extern "C" fn irq_common() {
         "push r15",
         "mov rbp, cr2",
         "push rbp",
+        // end the frame pointer chain, the trap frame holds the interrupted rbp
+        "xor ebp, ebp",
         "mov rdi, rax",
         "mov rsi, rsp",
         "call myfun",
@@ -125,36 +166,44 @@ unsafe extern "C" fn irq_common() {
 
 /// Handle interrupt
 #[no_mangle]
-extern "sysv64" fn myfun(_irq: u64, context_ptr: *mut TrapFrame) {
+extern "sysv64" fn myfun(vector: u64, context_ptr: *mut TrapFrame) {
     
     //print!(".");
     //println!("irq is {:#x}", irq);
     //unsafe { println!("{:?}", *context_ptr) };
-    let mut me = unsafe { apic::XApic::new(0xfee00000) };
+    let vector = vector as usize;
+    if vector < IRQ0 {
+        handle_exception(vector, unsafe { &mut *context_ptr });
+        return;
+    }
+    if vector == SPURIOUS_VECTOR as usize {
+        // not a real interrupt, must not be acknowledged
+        return;
+    }
+    if vector == TIMER_VECTOR as usize {
+        timer_interrupt(unsafe { &mut *context_ptr });
+    } else if vector != RESCHEDULE_VECTOR as usize {
+        // the reschedule ipi only wakes the cpu, the idle loop does the rest
+        handle_irq(vector - IRQ0);
+    }
+    eoi();
+}
+
+fn timer_interrupt(ctx: &mut TrapFrame) {
+    let ticks = on_timer_interrupt();
+    if cpu_id() == 0 {
+        crate::time::advance(ticks);
+    }
     match SCHEDULE.try_read() {
         Some(d) => {
             if *d {
-                //println!("lf7j83b56tn37gr2n76ty");
-                let ps = PROCESSES.read();
-                //println!("lf7j83b56tn37gr2n76ty");
-                let p = ps.get(&1).unwrap();
-                let ctx = p.ctx;
-                let ptr = & ctx as *const Context;
-                //unsafe { println!("first {:?}, second {:?}", ptr, context_ptr) };
-                
-                unsafe { volatile_copy_nonoverlapping_memory(context_ptr, ptr, 1); };
-                
-                
+                tick(ctx);
             }
             
         }
 
         None => {}
     }
-    
-    me.eoi();
-    
-    
 }
 
 #[inline]
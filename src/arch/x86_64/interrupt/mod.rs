@@ -1,3 +1,4 @@
 pub mod ctx;
+pub mod exception;
 pub mod int;
 pub mod trap;
\ No newline at end of file
@@ -1,4 +1,4 @@
-
+use crate::arch::gdt::KERNEL_CS;
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, Default)]
@@ -19,6 +19,27 @@ pub struct TrapFrame {
     pub(crate) rcx: u64,
     pub(crate) rbx: u64,
     pub(crate) rax: u64,
+    /// slot pushed by the entry stub, skipped on return
+    pub(crate) err: u64,
+    // pushed by the cpu, popped by iretq
+    pub(crate) ip: u64,
+    pub(crate) cs: u64,
+    pub(crate) rflags: u64,
     pub(crate) rsp: u64,
-    pub(crate) ip: u64
-}
\ No newline at end of file
+    pub(crate) ss: u64,
+}
+
+impl TrapFrame {
+    /// Context of a kernel thread starting at `entry` with the stack pointer `sp`,
+    /// interrupts enabled.
+    pub fn new_kernel(entry: usize, sp: usize) -> Self {
+        TrapFrame {
+            ip: entry as u64,
+            cs: KERNEL_CS as u64,
+            rflags: 0x202,
+            rsp: sp as u64,
+            ss: 0,
+            ..TrapFrame::default()
+        }
+    }
+}
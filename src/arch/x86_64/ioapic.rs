@@ -0,0 +1,287 @@
+use alloc::{collections::BTreeMap, format, string::String, sync::Arc, vec::Vec};
+use core::ptr::{read_volatile, write_volatile};
+use lazy_static::lazy_static;
+use spin::RwLock;
+
+use crate::{drivers::{DRIVERS, DeviceType, Driver, pci::PCIDevice}, sync::mutex::SpinNoIrqLock};
+
+use super::{cpu::cpu, interrupt::int::{IRQ0, alloc_irq}, memory::map_mmio};
+use log::info;
+
+/// physical address of the first ioapic on pc compatible machines,
+/// used when no firmware table says otherwise
+pub const IOAPIC_ADDR: usize = 0xfec00000;
+
+/// number of isa irqs, the ones an interrupt source override may remap
+pub const ISA_IRQ_COUNT: u8 = 16;
+
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const REG_VER: u32 = 0x01;
+const REG_REDTBL: u32 = 0x10;
+
+const RTE_ACTIVE_LOW: u64 = 1 << 13;
+const RTE_LEVEL: u64 = 1 << 15;
+const RTE_MASKED: u64 = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+/// Where an isa irq is wired to, and how it signals.
+#[derive(Debug, Clone, Copy)]
+struct IsaRoute {
+    gsi: u32,
+    polarity: Polarity,
+    trigger: Trigger,
+}
+
+/// One I/O apic, serving the global system interrupts
+/// `gsi_base..gsi_base + entries`.
+pub struct IoApic {
+    id: u8,
+    base: usize,
+    gsi_base: u32,
+    entries: u32,
+    /// the index and data registers must be used as a pair
+    lock: SpinNoIrqLock<()>,
+}
+
+impl IoApic {
+    fn new(id: u8, base: usize, gsi_base: u32) -> Self {
+        let mut ioapic = IoApic {
+            id,
+            base,
+            gsi_base,
+            entries: 0,
+            lock: SpinNoIrqLock::new(()),
+        };
+        ioapic.entries = ((ioapic.read(REG_VER) >> 16) & 0xff) + 1;
+        for pin in 0..ioapic.entries {
+            ioapic.write_entry(pin, RTE_MASKED);
+        }
+        ioapic
+    }
+
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// number of redirection entries
+    pub fn entries(&self) -> u32 {
+        self.entries
+    }
+
+    pub fn handles(&self, gsi: u32) -> bool {
+        gsi >= self.gsi_base && gsi < self.gsi_base + self.entries
+    }
+
+    fn read(&self, reg: u32) -> u32 {
+        let _guard = self.lock.lock();
+        unsafe {
+            write_volatile((self.base + IOREGSEL) as *mut u32, reg);
+            read_volatile((self.base + IOWIN) as *const u32)
+        }
+    }
+
+    fn write_entry(&self, pin: u32, entry: u64) {
+        let _guard = self.lock.lock();
+        unsafe {
+            // high half first, the entry takes effect when the low half is written
+            write_volatile((self.base + IOREGSEL) as *mut u32, REG_REDTBL + pin * 2 + 1);
+            write_volatile((self.base + IOWIN) as *mut u32, (entry >> 32) as u32);
+            write_volatile((self.base + IOREGSEL) as *mut u32, REG_REDTBL + pin * 2);
+            write_volatile((self.base + IOWIN) as *mut u32, entry as u32);
+        }
+    }
+
+    fn read_entry(&self, pin: u32) -> u64 {
+        let low = self.read(REG_REDTBL + pin * 2) as u64;
+        let high = self.read(REG_REDTBL + pin * 2 + 1) as u64;
+        high << 32 | low
+    }
+
+    /// Deliver `gsi` as `vector` to the local apic `dest`, left masked if `masked`.
+    fn route(&self, gsi: u32, vector: u8, dest: u32, polarity: Polarity, trigger: Trigger, masked: bool) {
+        let mut entry = vector as u64 | (dest as u64) << 56;
+        if masked {
+            entry |= RTE_MASKED;
+        }
+        if polarity == Polarity::Low {
+            entry |= RTE_ACTIVE_LOW;
+        }
+        if trigger == Trigger::Level {
+            entry |= RTE_LEVEL;
+        }
+        self.write_entry(gsi - self.gsi_base, entry);
+    }
+
+    fn mask(&self, gsi: u32) {
+        let pin = gsi - self.gsi_base;
+        self.write_entry(pin, self.read_entry(pin) | RTE_MASKED);
+    }
+
+    fn unmask(&self, gsi: u32) {
+        let pin = gsi - self.gsi_base;
+        self.write_entry(pin, self.read_entry(pin) & !RTE_MASKED);
+    }
+}
+
+impl Driver for IoApic {
+    // the interrupts it routes are handled by the devices raising them
+    fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+        false
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Intc
+    }
+
+    fn get_id(&self) -> String {
+        format!("ioapic{}", self.id)
+    }
+}
+
+lazy_static! {
+    static ref IOAPICS: RwLock<Vec<Arc<IoApic>>> = RwLock::new(Vec::new());
+    /// isa irqs that are not identity mapped to a gsi
+    static ref ISA_OVERRIDES: RwLock<BTreeMap<u8, IsaRoute>> = RwLock::new(BTreeMap::new());
+    /// irq given to each gsi a pci interrupt line is on, shared by the devices on it
+    static ref PCI_IRQS: RwLock<BTreeMap<u32, usize>> = RwLock::new(BTreeMap::new());
+}
+
+/// Add the ioapic with `id` whose registers are at physical `addr`.
+pub fn register_ioapic(id: u8, addr: usize, gsi_base: u32) {
+    map_mmio(addr);
+    let ioapic = Arc::new(IoApic::new(id, addr, gsi_base));
+    info!("ioapic {}: {:#x} gsi {}..{}", id, addr, gsi_base, gsi_base + ioapic.entries());
+    IOAPICS.write().push(ioapic.clone());
+    DRIVERS.write().push(ioapic);
+}
+
+/// Record an interrupt source override, isa `irq` is wired to `gsi`.
+pub fn add_isa_override(irq: u8, gsi: u32, polarity: Polarity, trigger: Trigger) {
+    ISA_OVERRIDES.write().insert(irq, IsaRoute { gsi, polarity, trigger });
+}
+
+/// Fall back to the standard ioapic if the firmware tables listed none.
+pub fn init_ioapic() {
+    if IOAPICS.read().is_empty() {
+        register_ioapic(0, IOAPIC_ADDR, 0);
+    }
+}
+
+fn ioapic_for(gsi: u32) -> Option<Arc<IoApic>> {
+    IOAPICS.read().iter().find(|io| io.handles(gsi)).cloned()
+}
+
+/// Deliver `gsi` to the bsp as irq `irq`, unmasked unless `masked`.
+fn enable_gsi(gsi: u32, irq: usize, polarity: Polarity, trigger: Trigger, masked: bool) -> Option<usize> {
+    let ioapic = ioapic_for(gsi)?;
+    ioapic.route(gsi, (IRQ0 + irq) as u8, cpu(0).apic_id(), polarity, trigger, masked);
+    Some(irq)
+}
+
+pub fn disable_gsi(gsi: u32) {
+    if let Some(ioapic) = ioapic_for(gsi) {
+        ioapic.mask(gsi);
+    }
+}
+
+/// Where isa `irq` is wired to: its override in `overrides`, or by default
+/// the gsi of the same number, active high and edge triggered.
+fn isa_route(overrides: &BTreeMap<u8, IsaRoute>, irq: u8) -> IsaRoute {
+    overrides.get(&irq).copied().unwrap_or(IsaRoute {
+        gsi: irq as u32,
+        polarity: Polarity::High,
+        trigger: Trigger::Edge,
+    })
+}
+
+/// Route isa `irq` to the bsp, returns the irq number its interrupts arrive as.
+///
+/// isa devices keep their usual numbers whatever gsi they are wired to,
+/// irq 0 is taken by the local apic timer
+pub fn enable_isa_irq(irq: u8) -> Option<usize> {
+    assert!(irq != 0 && irq < ISA_IRQ_COUNT, "not a usable isa irq: {}", irq);
+    let route = isa_route(&ISA_OVERRIDES.read(), irq);
+    enable_gsi(route.gsi, irq as usize, route.polarity, route.trigger, false)
+}
+
+/// Route the INTx pin of `dev` to the bsp, returns its irq number. the line
+/// stays masked until `unmask_pci_irq`, it is level triggered and would fire
+/// forever before a driver is there to clear it.
+///
+/// without parsing the acpi routing tables the line the firmware programmed
+/// for the pic is the best guess. lines below 16 go through the chipset's isa
+/// routing: they are isa irqs, on the gsi and with the polarity an interrupt
+/// source override gives them (active high without one), and keep their isa
+/// irq number. dedicated ioapic pins are active low and get an irq from
+/// `alloc_irq`, gsi n as irq n could land on a vector the local apic uses
+pub fn route_pci_irq(dev: &PCIDevice) -> Option<usize> {
+    dev.interrupt_pin.as_ref()?;
+    let line = match dev.pic_interrupt_line {
+        0 | 0xff => return None,
+        line => line,
+    };
+    let isa = (line < ISA_IRQ_COUNT).then(|| isa_route(&ISA_OVERRIDES.read(), line));
+    let gsi = isa.map_or(line as u32, |route| route.gsi);
+    let mut irqs = PCI_IRQS.write();
+    if let Some(&irq) = irqs.get(&gsi) {
+        // shared with a device routed before, maybe already unmasked
+        return Some(irq);
+    }
+    let (irq, polarity) = match isa {
+        Some(route) => (line as usize, route.polarity),
+        None => (alloc_irq()?, Polarity::Low),
+    };
+    let irq = enable_gsi(gsi, irq, polarity, Trigger::Level, true)?;
+    irqs.insert(gsi, irq);
+    Some(irq)
+}
+
+/// Let the interrupts of a line from `route_pci_irq` through, once its
+/// drivers are registered for `irq`.
+pub fn unmask_pci_irq(irq: usize) {
+    let gsi = PCI_IRQS.read().iter().find(|&(_, &i)| i == irq).map(|(&gsi, _)| gsi);
+    if let Some((gsi, ioapic)) = gsi.and_then(|gsi| Some((gsi, ioapic_for(gsi)?))) {
+        ioapic.unmask(gsi);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn isa_override_lookup() {
+        let mut overrides = BTreeMap::new();
+        // what qemu's MADT lists: the pit on gsi 2 and level triggered pci lines
+        overrides.insert(0, IsaRoute { gsi: 2, polarity: Polarity::High, trigger: Trigger::Edge });
+        overrides.insert(9, IsaRoute { gsi: 9, polarity: Polarity::High, trigger: Trigger::Level });
+        overrides.insert(11, IsaRoute { gsi: 20, polarity: Polarity::Low, trigger: Trigger::Level });
+
+        let route = isa_route(&overrides, 0);
+        assert_eq!((route.gsi, route.polarity, route.trigger), (2, Polarity::High, Trigger::Edge));
+        let route = isa_route(&overrides, 9);
+        assert_eq!((route.gsi, route.polarity, route.trigger), (9, Polarity::High, Trigger::Level));
+        let route = isa_route(&overrides, 11);
+        assert_eq!((route.gsi, route.polarity, route.trigger), (20, Polarity::Low, Trigger::Level));
+        // the rest are identity mapped, active high and edge triggered
+        let route = isa_route(&overrides, 4);
+        assert_eq!((route.gsi, route.polarity, route.trigger), (4, Polarity::High, Trigger::Edge));
+    }
+}
@@ -0,0 +1,217 @@
+use alloc::{boxed::Box, string::String, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use pc_keyboard::{DecodedKey, Error, HandleControl, KeyEvent, Keyboard, KeyboardLayout, ScancodeSet, ScancodeSet1, ScancodeSet2, layouts};
+
+use crate::{consts::{KEYBOARD_LAYOUT, KEYBOARD_SCANCODE_SET}, drivers::{DRIVERS, DeviceType, Driver, input::{self, EventKind}, irq::register_irq}, sync::mutex::SpinNoIrqLock};
+
+use super::{i8042::{self, CONFIG_KBD_CLOCK_OFF, CONFIG_KBD_IRQ, CONFIG_TRANSLATE, KEYBOARD_IRQ, Ps2Port}, ioapic::enable_isa_irq};
+
+const KBD_SET_SCANCODE_SET: u8 = 0xf0;
+const KBD_ENABLE_SCANNING: u8 = 0xf4;
+
+/// keyboard layouts, picked at boot and changed with `Ps2Keyboard::set_layout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us104,
+    Uk105,
+    Dvorak104,
+    Azerty,
+}
+
+impl Layout {
+    /// `us104`, `uk105`, `dvorak104` or `azerty`
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "us104" | "us" => Layout::Us104,
+            "uk105" | "uk" => Layout::Uk105,
+            "dvorak104" | "dvorak" => Layout::Dvorak104,
+            "azerty" | "fr" => Layout::Azerty,
+            _ => return None,
+        })
+    }
+}
+
+/// `pc_keyboard::Keyboard` with the layout and scancode set chosen at run time.
+trait Decoder: Send {
+    fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error>;
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey>;
+}
+
+impl<L: KeyboardLayout + Send, S: ScancodeSet + Send> Decoder for Keyboard<L, S> {
+    fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        Keyboard::add_byte(self, byte)
+    }
+
+    fn process_keyevent(&mut self, event: KeyEvent) -> Option<DecodedKey> {
+        Keyboard::process_keyevent(self, event)
+    }
+}
+
+fn decoder(layout: Layout, set2: bool) -> Box<dyn Decoder> {
+    macro_rules! keyboard {
+        ($layout:expr) => {
+            if set2 {
+                Box::new(Keyboard::new($layout, ScancodeSet2, HandleControl::MapLettersToUnicode)) as Box<dyn Decoder>
+            } else {
+                Box::new(Keyboard::new($layout, ScancodeSet1, HandleControl::MapLettersToUnicode))
+            }
+        };
+    }
+    match layout {
+        Layout::Us104 => keyboard!(layouts::Us104Key),
+        Layout::Uk105 => keyboard!(layouts::Uk105Key),
+        Layout::Dvorak104 => keyboard!(layouts::Dvorak104Key),
+        Layout::Azerty => keyboard!(layouts::Azerty),
+    }
+}
+
+pub struct Ps2Keyboard {
+    irq: AtomicUsize,
+    /// input device id
+    device: AtomicUsize,
+    layout: SpinNoIrqLock<Layout>,
+    /// scancode set 2 untranslated, set 1 otherwise
+    set2: AtomicBool,
+    decoder: SpinNoIrqLock<Option<Box<dyn Decoder>>>,
+}
+
+impl Ps2Keyboard {
+    /// Decode `byte` and publish the key event it completes, if any.
+    fn receive(&self, byte: u8) {
+        let kind = {
+            let mut decoder = self.decoder.lock();
+            let decoder = match decoder.as_mut() {
+                Some(decoder) => decoder,
+                None => return,
+            };
+            match decoder.add_byte(byte) {
+                Ok(Some(event)) => {
+                    let (code, state) = (event.code, event.state);
+                    EventKind::Key { code, state, key: decoder.process_keyevent(event) }
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    warn!("bad scancode {:#x}: {:?}", byte, e);
+                    return;
+                }
+            }
+        };
+        let device = self.device.load(Ordering::Relaxed);
+        input::publish(device, kind);
+        input::publish(device, EventKind::Sync);
+    }
+
+    pub fn layout(&self) -> Layout {
+        *self.layout.lock()
+    }
+
+    /// 1 or 2
+    pub fn scancode_set(&self) -> u8 {
+        if self.set2.load(Ordering::Relaxed) { 2 } else { 1 }
+    }
+
+    /// Decode with `layout` from now on, the scancode set stays.
+    pub fn set_layout(&self, layout: Layout) {
+        let mut decoder = self.decoder.lock();
+        *self.layout.lock() = layout;
+        *decoder = Some(self::decoder(layout, self.set2.load(Ordering::Relaxed)));
+    }
+
+    /// Switch to scancode set 1 or 2, false if the keyboard refused.
+    ///
+    /// the keyboard interrupt is off meanwhile, so its handler does not take
+    /// the replies
+    pub fn set_scancode_set(&self, set: u8) -> bool {
+        let irq = self.irq.load(Ordering::Relaxed) != 0;
+        if irq {
+            i8042::update_config(|c| c & !CONFIG_KBD_IRQ);
+        }
+        let ok = select_scancode_set(set == 2);
+        let mut decoder = self.decoder.lock();
+        self.set2.store(set == 2, Ordering::Relaxed);
+        *decoder = Some(self::decoder(*self.layout.lock(), set == 2));
+        drop(decoder);
+        if irq {
+            i8042::update_config(|c| c | CONFIG_KBD_IRQ);
+        }
+        ok
+    }
+}
+
+/// Have the keyboard send set 2 and the controller translate it to set 1
+/// unless `set2`, false if the keyboard refused.
+fn select_scancode_set(set2: bool) -> bool {
+    i8042::update_config(|c| if set2 { c & !CONFIG_TRANSLATE } else { c | CONFIG_TRANSLATE });
+    i8042::send_command(Ps2Port::Keyboard, &[KBD_SET_SCANCODE_SET, 2])
+}
+
+impl Driver for Ps2Keyboard {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        if irq.is_some() && irq != Some(self.irq.load(Ordering::Relaxed)) {
+            return false;
+        }
+        match i8042::read_pending(Ps2Port::Keyboard) {
+            Some(byte) => {
+                self.receive(byte);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Input
+    }
+
+    fn get_id(&self) -> String {
+        String::from("i8042_keyboard")
+    }
+}
+
+lazy_static! {
+    pub static ref KEYBOARD: Arc<Ps2Keyboard> = Arc::new(Ps2Keyboard {
+        irq: AtomicUsize::new(0),
+        device: AtomicUsize::new(0),
+        layout: SpinNoIrqLock::new(Layout::Us104),
+        set2: AtomicBool::new(false),
+        decoder: SpinNoIrqLock::new(None),
+    });
+}
+
+/// Set up the first i8042 port with the layout and scancode set of
+/// `KEYBOARD_LAYOUT` and `KEYBOARD_SCANCODE_SET`, and take its irq.
+///
+/// the keyboard always sends set 2, which the controller translates into
+/// set 1 unless set 2 is asked for
+pub fn init_keyboard() {
+    let layout = Layout::from_name(KEYBOARD_LAYOUT).unwrap_or_else(|| {
+        warn!("unknown layout {}, using us104", KEYBOARD_LAYOUT);
+        Layout::Us104
+    });
+    let set2 = KEYBOARD_SCANCODE_SET == 2;
+
+    i8042::disable_ports();
+    // no interrupts yet, they would take the replies to our commands
+    i8042::update_config(|c| c & !(CONFIG_KBD_IRQ | CONFIG_KBD_CLOCK_OFF));
+    i8042::enable_port(Ps2Port::Keyboard);
+    if !select_scancode_set(set2) {
+        warn!("keyboard refused scancode set 2");
+    }
+    if !i8042::send_command(Ps2Port::Keyboard, &[KBD_ENABLE_SCANNING]) {
+        warn!("no keyboard");
+        return;
+    }
+    KEYBOARD.set2.store(set2, Ordering::Relaxed);
+    KEYBOARD.set_layout(layout);
+    KEYBOARD.device.store(input::register_device("i8042 keyboard"), Ordering::Relaxed);
+
+    DRIVERS.write().push(KEYBOARD.clone());
+    if let Some(irq) = enable_isa_irq(KEYBOARD_IRQ) {
+        KEYBOARD.irq.store(irq, Ordering::Relaxed);
+        register_irq(irq, KEYBOARD.clone());
+        i8042::update_config(|c| c | CONFIG_KBD_IRQ);
+    }
+    info!("{:?} layout, scancode set {}", layout, if set2 { 2 } else { 1 });
+}
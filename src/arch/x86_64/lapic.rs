@@ -0,0 +1,109 @@
+use core::{ptr::{read_volatile, write_volatile}, sync::atomic::{AtomicUsize, Ordering}};
+
+use apic::{LocalApic, XApic};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::memory::map_mmio;
+
+/// architectural default physical address of the local apic registers,
+/// used when the MADT does not say otherwise
+pub const LAPIC_DEFAULT_ADDR: usize = 0xfee00000;
+
+/// physical address of the local apic registers, identity mapped
+static LAPIC_BASE: AtomicUsize = AtomicUsize::new(LAPIC_DEFAULT_ADDR);
+
+/// vector of the ipi that wakes an idle cpu when work is queued for it
+pub const RESCHEDULE_VECTOR: u8 = 0xf1;
+
+/// spurious interrupt vector, as set up by `cpu_init`
+pub const SPURIOUS_VECTOR: u8 = 63;
+
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+/// delivery mode nmi, the vector is ignored
+const ICR_NMI: u32 = 4 << 8;
+
+/// Map the local apic registers found at physical `addr`, shared by all cpus.
+pub fn set_lapic_base(addr: usize) {
+    map_mmio(addr);
+    LAPIC_BASE.store(addr, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn lapic_base() -> usize {
+    LAPIC_BASE.load(Ordering::Relaxed)
+}
+
+/// Get a handle to the local apic of the calling cpu.
+///
+/// every cpu sees its own local apic at the same address,
+/// so the handle is only meaningful on the cpu that created it
+#[inline]
+pub fn lapic() -> XApic {
+    unsafe { XApic::new(lapic_base()) }
+}
+
+/// apic id of the calling cpu
+#[inline]
+pub fn lapic_id() -> u32 {
+    lapic().id()
+}
+
+/// enable the local apic of the calling cpu
+pub fn init_lapic() {
+    let mut me = lapic();
+    me.cpu_init();
+}
+
+/// signal end of interrupt to the local apic of the calling cpu
+#[inline]
+pub fn eoi() {
+    lapic().eoi();
+}
+
+/// read a local apic register, for the ones `XApic` does not expose
+#[inline]
+pub fn lapic_read(reg: usize) -> u32 {
+    unsafe { read_volatile((lapic_base() + reg) as *const u32) }
+}
+
+#[inline]
+pub fn lapic_write(reg: usize, val: u32) {
+    unsafe { write_volatile((lapic_base() + reg) as *mut u32, val) }
+}
+
+/// Send a fixed interrupt `vector` to the cpu with apic id `apic_id`.
+pub fn send_ipi(apic_id: u32, vector: u8) {
+    send_icr(apic_id, vector as u32);
+}
+
+/// Send a non maskable interrupt to the cpu with apic id `apic_id`.
+pub fn send_nmi(apic_id: u32) {
+    send_icr(apic_id, ICR_NMI);
+}
+
+/// Write the interrupt command register, the destination first.
+///
+/// an interrupt between the two writes could send an ipi of its own and
+/// leave another destination in ICR_HIGH, so they are done with interrupts
+/// off. gdb sends its nmis from exception context, which interrupts do not
+/// keep out and which would deadlock on a lock held by the code it stopped,
+/// so instead every sender waits for the register to be idle and puts back
+/// the destination it found
+fn send_icr(apic_id: u32, low: u32) {
+    without_interrupts(|| {
+        wait_icr_idle();
+        let high = lapic_read(ICR_HIGH);
+        lapic_write(ICR_HIGH, apic_id << 24);
+        lapic_write(ICR_LOW, low);
+        wait_icr_idle();
+        lapic_write(ICR_HIGH, high);
+    });
+}
+
+fn wait_icr_idle() {
+    while lapic_read(ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
@@ -1,34 +1,34 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 
-use bootloader::{BootInfo, bootinfo::MemoryRegionType};
-use x86_64::{PhysAddr, VirtAddr, structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB}};
+use bootloader::{BootInfo, boot_info::MemoryRegionKind};
+use x86_64::{PhysAddr, VirtAddr, structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate, mapper::MapToError}};
 
 use crate::memory::bitalloc::{BitAlloc, BitAlloc1M};
 
-use super::consts::{KERNEL_HEAP_SIZE, KERNEL_HEAP_START};
+use super::consts::{KERNEL_HEAP_SIZE, KERNEL_HEAP_START, PHYSICAL_MEMORY_OFFSET};
 
 use super::page::init_page_table;
 
 
 use crate::memory::HEAP_ALLOCATOR;
 use crate::memory::BITMAP_ALLOCATOR;
+use log::{debug, info};
 
 /// init frame allocator and heap 
-pub fn mem_init(bootinfo: &'static BootInfo) {
+pub fn mem_init(bootinfo: &BootInfo) {
+    assert_eq!(
+        bootinfo.physical_memory_offset.into_option(), Some(PHYSICAL_MEMORY_OFFSET as u64),
+        "physical memory is not mapped at PHYSICAL_MEMORY_OFFSET",
+    );
     bitalloc_init(bootinfo);
-    let mut table = unsafe { init_page_table(VirtAddr::new(0xFFFF800000000000)) };
-    let page: Page<Size4KiB> = Page::from_start_address(VirtAddr::new(0xfee00000)).unwrap();
-    let frame: PhysFrame<Size4KiB> = PhysFrame::from_start_address(PhysAddr::new(0xfee00000)).unwrap();
-    unsafe {
-        let mut b = BITMAP_ALLOCATOR.lock();
-        table.map_to(page, frame, PageTableFlags::NO_CACHE | PageTableFlags::WRITABLE | PageTableFlags::PRESENT, &mut *b).expect("msg").flush();
-    }
+    let mut table = unsafe { init_page_table(VirtAddr::new(PHYSICAL_MEMORY_OFFSET as u64)) };
 
 
     unsafe {
         let mut b = BITMAP_ALLOCATOR.lock();
         let phyaddr  = b.alloc_contiguous(KERNEL_HEAP_SIZE / 0x1000, 10).unwrap() * 0x1000;
-        println!("heap physics address: {:#x}", phyaddr);
+        debug!("heap physical address: {:#x}", phyaddr);
         for i in 0..KERNEL_HEAP_SIZE / 0x1000 {
             let addr: u64 = KERNEL_HEAP_START as u64 + i as u64 * 0x1000;
             let page: Page<Size4KiB> = Page::from_start_address(
@@ -43,20 +43,69 @@ pub fn mem_init(bootinfo: &'static BootInfo) {
     }
     unsafe { HEAP_ALLOCATOR.lock().init(KERNEL_HEAP_START, KERNEL_HEAP_SIZE) };
 
-    println!("successfully init heap\nheap start addr: {:#x}\nheap size: {:#x}", KERNEL_HEAP_START, KERNEL_HEAP_SIZE);
+    info!("heap at {:#x}, size {:#x}", KERNEL_HEAP_START, KERNEL_HEAP_SIZE);
+
+}
+
+
+/// Map the page at physical `addr` to the same virtual address.
+pub fn map_identity(addr: usize) {
+    map_page(addr, PageTableFlags::PRESENT | PageTableFlags::WRITABLE);
+}
 
+/// Identity map a page of memory mapped registers, uncached.
+pub fn map_mmio(addr: usize) {
+    map_page(addr, PageTableFlags::NO_CACHE | PageTableFlags::WRITABLE | PageTableFlags::PRESENT);
 }
 
+/// whether `addr` can be read without faulting
+pub fn is_mapped(addr: usize) -> bool {
+    let table = unsafe { init_page_table(VirtAddr::new(PHYSICAL_MEMORY_OFFSET as u64)) };
+    VirtAddr::try_new(addr as u64)
+        .map_or(false, |addr| table.translate_addr(addr).is_some())
+}
+
+fn map_page(addr: usize, flags: PageTableFlags) {
+    let mut table = unsafe { init_page_table(VirtAddr::new(PHYSICAL_MEMORY_OFFSET as u64)) };
+    let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(addr as u64));
+    let frame: PhysFrame<Size4KiB> = PhysFrame::containing_address(PhysAddr::new(addr as u64));
+    let mut b = BITMAP_ALLOCATOR.lock();
+    match unsafe { table.map_to(page, frame, flags, &mut *b) } {
+        Ok(flush) => flush.flush(),
+        // already mapped, e.g. two devices sharing a page
+        Err(MapToError::PageAlreadyMapped(_)) => {}
+        Err(e) => panic!("failed to map {:#x}: {:?}", addr, e),
+    }
+}
+
+/// frames handed to the frame allocator at boot
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Free and total frames of `BITMAP_ALLOCATOR`, free ones are counted
+/// so this is slow.
+pub fn frame_stats() -> (usize, usize) {
+    let alloc = BITMAP_ALLOCATOR.lock();
+    let mut free = 0;
+    let mut key = 0;
+    while let Some(frame) = alloc.next(key) {
+        free += 1;
+        key = frame + 1;
+    }
+    (free, TOTAL_FRAMES.load(Ordering::Relaxed))
+}
 
-pub fn bitalloc_init(bootinfo: &'static BootInfo) {
-    let j =  bootinfo.memory_map.iter()
-        .filter(|r| r.region_type == MemoryRegionType::Usable)
-        .map(|r| r.range.start_addr() as usize / 0x1000..r.range.end_addr() as usize / 0x1000);
+pub fn bitalloc_init(bootinfo: &BootInfo) {
+    // regions are not page aligned, only whole frames are handed out
+    let j =  bootinfo.memory_regions.iter()
+        .filter(|r| r.kind == MemoryRegionKind::Usable)
+        .map(|r| (r.start as usize + 0xfff) / 0x1000..r.end as usize / 0x1000)
+        .filter(|r| r.start < r.end);
          
     {
         let mut block = BITMAP_ALLOCATOR.lock();
         for i in j {
-            println!("bit allocator find block {:#x}~{:#x}", i.start, i.end);
+            debug!("usable frames {:#x}~{:#x}", i.start, i.end);
+            TOTAL_FRAMES.fetch_add(i.end - i.start, Ordering::Relaxed);
             block.insert(i);
         }
 
@@ -1,12 +1,15 @@
-use bootloader::{BootInfo, entry_point};
-use cpu::halt;
+use bootloader::{BootInfo, boot_info::{FrameBuffer, PixelFormat as BootPixelFormat}, entry_point};
 use interrupt::int::init_idt;
+use log::warn;
 use x86_64::structures::paging::{OffsetPageTable, PageTable};
-use crate::{memory::{BITMAP_ALLOCATOR, addr::phys_to_virt, bitalloc::BitAlloc}, process::proc::do_print_hello};
-use crate::process::proc::{create_kernel_process, create_kernel_process2, init_kernel_process};
+use crate::process::proc::do_print_hello;
+use crate::process::{idle::idle_loop, proc::{init_kernel_process, spawn_kernel_thread}};
 
-use self::{interrupt::ctx::Context, memory::mem_init, pci::init_pci};
+use self::{acpi::init_acpi, gdb::init_gdb, keyboard::init_keyboard, mouse::init_mouse, hpet::init_hpet, tsc::init_tsc, ioapic::init_ioapic, memory::mem_init, pci::init_pci, rtc::init_rtc, serial::{init_serial, init_serial_irq}, smp::{init_bsp, start_aps}};
 use crate::process::SCHEDULE;
+use crate::drivers::framebuffer::{FrameBufferInfo, PixelFormat, init_framebuffer};
+use crate::{console::vt::init_vt, shell::{serial_shell_main, shell_main}};
+use crate::{consts::{FRAMEBUFFER_CONSOLE, LOG_FILTER}, logging::{apply_filter_spec, init_logger}};
 
 pub mod partition;
 pub mod consts;
@@ -17,50 +20,87 @@ pub mod interrupt;
 pub mod page;
 pub mod pci;
 pub mod ahci;
+pub mod lapic;
+pub mod gdt;
+pub mod smp;
+pub mod timer;
+pub mod ioapic;
+pub mod acpi;
+pub mod hpet;
+pub mod tsc;
+pub mod rtc;
+pub mod serial;
+pub mod backtrace;
+pub mod gdb;
+pub mod i8042;
+pub mod keyboard;
+pub mod mouse;
+pub mod power;
+pub mod bga;
 
 
 entry_point!(kernel_main);
 
-fn kernel_main(bootinfo: &'static BootInfo) -> ! {
+fn kernel_main(bootinfo: &'static mut BootInfo) -> ! {
     
+    init_serial();
+    init_logger();
     mem_init(bootinfo);
+    // module filters need the heap
+    apply_filter_spec(LOG_FILTER).expect("bad LOG_FILTER");
+    // the bootloader finds it under UEFI too, where there is no bios area to scan
+    init_acpi(bootinfo.rsdp_addr.into_option().map(|addr| addr as usize));
+    init_hpet();
+    init_tsc();
+    init_bsp();
     init_kernel_process();
     init_idt();
+    init_gdb();
+    start_aps();
+    init_ioapic();
+    init_rtc();
+    init_serial_irq();
+    init_vt();
+    if FRAMEBUFFER_CONSOLE {
+        if let Some(fb) = bootinfo.framebuffer.as_mut() {
+            init_boot_framebuffer(fb);
+        }
+    }
+    init_keyboard();
+    init_mouse();
     init_pci();
-    create_kernel_process(1);
-    let addr = BITMAP_ALLOCATOR.lock().alloc().unwrap();
-    let addr2 = BITMAP_ALLOCATOR.lock().alloc().unwrap();
-    let addrv = phys_to_virt(addr2);
-    let ctx = Context {
-        cr2: 0,
-        r15: 0,
-        r14: 0,
-        r13: 0,
-        r12: 0,
-        r11: 0,
-        r10: 0,
-        r9: 0,
-        r8: 0,
-        rbp: 0,
-        rdi: 0,
-        rsi: 0,
-        rdx: 0,
-        rcx: 0,
-        rbx: 0,
-        rax: 0,
-        rsp: addrv as u64,
-        ip: do_print_hello as u64,
-    };
-    
-    //let proc = PROCESSES.write();
-    create_kernel_process2(1, ctx);
-    println!("{:?}", ctx);
+    #[cfg(test)]
+    crate::test_main();
+    spawn_kernel_thread(do_print_hello);
+    spawn_kernel_thread(shell_main);
+    spawn_kernel_thread(serial_shell_main);
     {
         let mut x = SCHEDULE.write();
         *x = true;
     }
     
-    loop {
-        halt();
-    }
+    idle_loop()
+}
+
+/// Put the terminals on the framebuffer the bootloader set up, it is
+/// already mapped.
+fn init_boot_framebuffer(fb: &'static mut FrameBuffer) {
+    let info = fb.info();
+    let pixel_format = match info.pixel_format {
+        BootPixelFormat::RGB => PixelFormat::Rgb,
+        BootPixelFormat::BGR => PixelFormat::Bgr,
+        BootPixelFormat::U8 => PixelFormat::U8,
+        format => {
+            warn!("unsupported framebuffer pixel format {:?}", format);
+            return;
+        }
+    };
+    init_framebuffer(fb.buffer_mut().as_mut_ptr() as usize, FrameBufferInfo {
+        byte_len: info.byte_len,
+        horizontal_resolution: info.horizontal_resolution,
+        vertical_resolution: info.vertical_resolution,
+        pixel_format,
+        bytes_per_pixel: info.bytes_per_pixel,
+        stride: info.stride,
+    });
 }
\ No newline at end of file
@@ -0,0 +1,276 @@
+use alloc::{string::String, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use log::{info, warn};
+
+use crate::{drivers::{DRIVERS, DeviceType, Driver, input::{self, EventKind, MouseButton}, irq::register_irq}, sync::mutex::SpinNoIrqLock};
+
+use super::{i8042::{self, ACK, CONFIG_AUX_CLOCK_OFF, CONFIG_AUX_IRQ, MOUSE_IRQ, Ps2Port}, ioapic::enable_isa_irq};
+
+const MOUSE_SET_DEFAULTS: u8 = 0xf6;
+const MOUSE_GET_ID: u8 = 0xf2;
+const MOUSE_SET_SAMPLE_RATE: u8 = 0xf3;
+const MOUSE_ENABLE_REPORTING: u8 = 0xf4;
+
+/// sample rates that switch a wheel mouse to intellimouse packets
+const INTELLIMOUSE_KNOCK: [u8; 3] = [200, 100, 80];
+const ID_STANDARD: u8 = 0;
+const ID_INTELLIMOUSE: u8 = 3;
+
+// first byte of a packet
+const PACKET_LEFT: u8 = 1 << 0;
+const PACKET_RIGHT: u8 = 1 << 1;
+const PACKET_MIDDLE: u8 = 1 << 2;
+/// always set, used to find the start of a packet
+const PACKET_SYNC: u8 = 1 << 3;
+const PACKET_X_SIGN: u8 = 1 << 4;
+const PACKET_Y_SIGN: u8 = 1 << 5;
+const PACKET_X_OVERFLOW: u8 = 1 << 6;
+const PACKET_Y_OVERFLOW: u8 = 1 << 7;
+
+const BUTTONS: [(u8, MouseButton); 3] = [
+    (PACKET_LEFT, MouseButton::Left),
+    (PACKET_RIGHT, MouseButton::Right),
+    (PACKET_MIDDLE, MouseButton::Middle),
+];
+
+struct PacketState {
+    bytes: [u8; 4],
+    len: usize,
+    /// 3 for a standard mouse, 4 with a wheel
+    size: usize,
+    /// button bits of the last packet
+    buttons: u8,
+}
+
+/// A complete packet and the buttons held before it.
+struct Packet {
+    bytes: [u8; 4],
+    previous: u8,
+    wheel: bool,
+}
+
+pub struct Ps2Mouse {
+    irq: AtomicUsize,
+    /// input device id
+    device: AtomicUsize,
+    packet: SpinNoIrqLock<PacketState>,
+}
+
+/// 9 bit two's complement movement, the sign bit is in the first byte
+fn movement(value: u8, sign: bool) -> i32 {
+    if sign { value as i32 - 0x100 } else { value as i32 }
+}
+
+impl PacketState {
+    /// Add a received byte, returning the packet it completes.
+    fn push(&mut self, byte: u8) -> Option<Packet> {
+        if self.len == 0 && byte & PACKET_SYNC == 0 {
+            // out of step, wait for the start of a packet
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.size {
+            return None;
+        }
+        self.len = 0;
+        let bytes = self.bytes;
+        let previous = core::mem::replace(&mut self.buttons, bytes[0] & (PACKET_LEFT | PACKET_RIGHT | PACKET_MIDDLE));
+        Some(Packet { bytes, previous, wheel: self.size == 4 })
+    }
+}
+
+impl Packet {
+    /// Hand the events of the packet to `emit`, ending with a sync.
+    fn events(&self, emit: &mut dyn FnMut(EventKind)) {
+        let bytes = self.bytes;
+        let flags = bytes[0];
+        if flags & (PACKET_X_OVERFLOW | PACKET_Y_OVERFLOW) == 0 {
+            let dx = movement(bytes[1], flags & PACKET_X_SIGN != 0);
+            // the mouse counts y up, events count it down
+            let dy = -movement(bytes[2], flags & PACKET_Y_SIGN != 0);
+            if dx != 0 || dy != 0 {
+                emit(EventKind::Motion { dx, dy });
+            }
+        }
+        for &(bit, button) in BUTTONS.iter() {
+            if (flags ^ self.previous) & bit != 0 {
+                emit(EventKind::Button { button, pressed: flags & bit != 0 });
+            }
+        }
+        if self.wheel && bytes[3] != 0 {
+            // positive z is a scroll towards the user
+            emit(EventKind::Wheel { delta: -(bytes[3] as i8 as i32) });
+        }
+        emit(EventKind::Sync);
+    }
+}
+
+impl Ps2Mouse {
+    fn receive(&self, byte: u8) {
+        // published without the packet lock held
+        let packet = self.packet.lock().push(byte);
+        if let Some(packet) = packet {
+            let device = self.device.load(Ordering::Relaxed);
+            packet.events(&mut |kind| input::publish(device, kind));
+        }
+    }
+}
+
+impl Driver for Ps2Mouse {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        if irq.is_some() && irq != Some(self.irq.load(Ordering::Relaxed)) {
+            return false;
+        }
+        match i8042::read_pending(Ps2Port::Aux) {
+            Some(byte) => {
+                self.receive(byte);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Input
+    }
+
+    fn get_id(&self) -> String {
+        String::from("i8042_mouse")
+    }
+}
+
+lazy_static! {
+    pub static ref MOUSE: Arc<Ps2Mouse> = Arc::new(Ps2Mouse {
+        irq: AtomicUsize::new(0),
+        device: AtomicUsize::new(0),
+        packet: SpinNoIrqLock::new(PacketState { bytes: [0; 4], len: 0, size: 3, buttons: 0 }),
+    });
+}
+
+/// id the mouse reports, after the ack
+fn mouse_id() -> Option<u8> {
+    if i8042::send(Ps2Port::Aux, MOUSE_GET_ID) != Some(ACK) {
+        return None;
+    }
+    i8042::receive()
+}
+
+/// Set up the mouse on the second i8042 port and take its irq.
+///
+/// a wheel mouse only sends 4 byte packets after the sample rate
+/// sequence 200, 100, 80, and then reports id 3
+///
+/// must run after `init_keyboard`, which resets the controller configuration
+pub fn init_mouse() {
+    // no interrupts yet, they would take the replies to our commands
+    i8042::update_config(|c| c & !(CONFIG_AUX_IRQ | CONFIG_AUX_CLOCK_OFF));
+    i8042::enable_port(Ps2Port::Aux);
+    if !i8042::send_command(Ps2Port::Aux, &[MOUSE_SET_DEFAULTS]) {
+        info!("no mouse");
+        return;
+    }
+    for &rate in INTELLIMOUSE_KNOCK.iter() {
+        i8042::send_command(Ps2Port::Aux, &[MOUSE_SET_SAMPLE_RATE, rate]);
+    }
+    let id = mouse_id();
+    let size = match id {
+        Some(ID_INTELLIMOUSE) => 4,
+        Some(ID_STANDARD) => 3,
+        other => {
+            warn!("unknown mouse id {:?}, assuming a standard mouse", other);
+            3
+        }
+    };
+    MOUSE.packet.lock().size = size;
+    if !i8042::send_command(Ps2Port::Aux, &[MOUSE_ENABLE_REPORTING]) {
+        warn!("mouse does not report");
+        return;
+    }
+    MOUSE.device.store(input::register_device("i8042 mouse"), Ordering::Relaxed);
+
+    DRIVERS.write().push(MOUSE.clone());
+    if let Some(irq) = enable_isa_irq(MOUSE_IRQ) {
+        MOUSE.irq.store(irq, Ordering::Relaxed);
+        register_irq(irq, MOUSE.clone());
+        i8042::update_config(|c| c | CONFIG_AUX_IRQ);
+    }
+    info!("{} mouse", if size == 4 { "intellimouse" } else { "standard" });
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn state(size: usize) -> PacketState {
+        PacketState { bytes: [0; 4], len: 0, size, buttons: 0 }
+    }
+
+    /// Events of the packets completed by `bytes`.
+    fn receive(state: &mut PacketState, bytes: &[u8]) -> Vec<EventKind> {
+        let mut events = Vec::new();
+        for &b in bytes {
+            if let Some(packet) = state.push(b) {
+                packet.events(&mut |kind| events.push(kind));
+            }
+        }
+        events
+    }
+
+    #[test_case]
+    fn movement_sign() {
+        assert_eq!(movement(5, false), 5);
+        assert_eq!(movement(0xff, true), -1);
+        assert_eq!(movement(0, true), -256);
+    }
+
+    #[test_case]
+    fn standard_packets() {
+        let mut s = state(3);
+        // right and up, y is flipped
+        assert_eq!(receive(&mut s, &[PACKET_SYNC, 3, 2]), [EventKind::Motion { dx: 3, dy: -2 }, EventKind::Sync]);
+        // left and down
+        let flags = PACKET_SYNC | PACKET_X_SIGN | PACKET_Y_SIGN;
+        assert_eq!(receive(&mut s, &[flags, 0xfe, 0xfd]), [EventKind::Motion { dx: -2, dy: 3 }, EventKind::Sync]);
+        // overflowed movement is dropped
+        let flags = PACKET_SYNC | PACKET_X_OVERFLOW;
+        assert_eq!(receive(&mut s, &[flags, 0xff, 1]), [EventKind::Sync]);
+    }
+
+    #[test_case]
+    fn button_changes() {
+        let mut s = state(3);
+        assert_eq!(receive(&mut s, &[PACKET_SYNC | PACKET_LEFT, 0, 0]), [
+            EventKind::Button { button: MouseButton::Left, pressed: true },
+            EventKind::Sync,
+        ]);
+        // held buttons are not reported again
+        assert_eq!(receive(&mut s, &[PACKET_SYNC | PACKET_LEFT | PACKET_MIDDLE, 0, 0]), [
+            EventKind::Button { button: MouseButton::Middle, pressed: true },
+            EventKind::Sync,
+        ]);
+        assert_eq!(receive(&mut s, &[PACKET_SYNC, 0, 0]), [
+            EventKind::Button { button: MouseButton::Left, pressed: false },
+            EventKind::Button { button: MouseButton::Middle, pressed: false },
+            EventKind::Sync,
+        ]);
+    }
+
+    #[test_case]
+    fn resynchronizes() {
+        let mut s = state(3);
+        // bytes without the sync bit cannot start a packet
+        assert_eq!(receive(&mut s, &[0, 1, PACKET_SYNC, 1, 0]), [EventKind::Motion { dx: 1, dy: 0 }, EventKind::Sync]);
+    }
+
+    #[test_case]
+    fn wheel_packets() {
+        let mut s = state(4);
+        assert_eq!(receive(&mut s, &[PACKET_SYNC, 0, 0, 0xff]), [EventKind::Wheel { delta: 1 }, EventKind::Sync]);
+        assert_eq!(receive(&mut s, &[PACKET_SYNC, 0, 0, 1]), [EventKind::Wheel { delta: -1 }, EventKind::Sync]);
+        assert_eq!(receive(&mut s, &[PACKET_SYNC, 0, 0, 0]), [EventKind::Sync]);
+    }
+}
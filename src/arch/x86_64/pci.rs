@@ -1,6 +1,10 @@
-use crate::drivers::pci::{BAR, ConfigSpaceAccessMethod::IO, PCIDevice, PortOps, scan_bus};
+use alloc::{sync::Arc, vec::Vec};
+use crate::{consts::FRAMEBUFFER_CONSOLE, drivers::{BLK_DRIVERS, DRIVERS, Driver, framebuffer, irq::register_irq, pci::{BAR, BusLocation, ConfigSpaceAccessMethod::IO, PCIDevice, PortOps, enable, scan_bus}}};
 use x86_64::instructions::port::Port;
 
+use super::{ahci, bga, consts::PAGE_SIZE, interrupt::int::alloc_irq, ioapic::{route_pci_irq, unmask_pci_irq}, memory::map_mmio};
+use log::{debug, info};
+
 
 struct PortOpsImpl;
 
@@ -19,13 +23,21 @@ impl PortOps for PortOpsImpl {
 }
 
 
+/// Write the configuration space byte at `offset` of the function at `loc`.
+pub fn write_config8(loc: BusLocation, offset: u16, val: u8) {
+    let shift = (offset & 0b11) * 8;
+    unsafe {
+        let old = IO.read32(&PortOpsImpl, loc, offset & !0b11);
+        let new = (old & !(0xff << shift)) | ((val as u32) << shift);
+        IO.write32(&PortOpsImpl, loc, offset & !0b11, new);
+    }
+}
+
 pub fn init_pci() {
-    println!("begin scan");
-    
     let pci = unsafe { scan_bus(&PortOpsImpl, IO) };
     for dev in pci {
-        println!(
-            "pci: {:02x}:{:02x}.{} {:#x} {:#x} ({} {}) irq: {}:{:?}",
+        info!(
+            "{:02x}:{:02x}.{} {:#x} {:#x} ({} {}) irq: {}:{:?}",
             dev.loc.bus,
             dev.loc.device,
             dev.loc.function,
@@ -41,13 +53,46 @@ pub fn init_pci() {
     }
 }
 
+/// Every function on the bus, scanned again on each call.
+pub fn pci_devices() -> Vec<PCIDevice> {
+    unsafe { scan_bus(&PortOpsImpl, IO) }.collect()
+}
+
 pub fn init_driver(dev: &PCIDevice) {
     if dev.id.class == 0x1 && dev.id.subclass == 0x6 {
         
-        if let Some(BAR::Memory(addr, len, d, c)) = dev.bars[5] {
-            println!("Found AHCI dev {:?} BAR5 {:x?}", dev, addr);
+        if let Some(BAR::Memory(addr, len, _, _)) = dev.bars[5] {
+            debug!("ahci {:?} BAR5 {:#x}", dev.loc, addr);
+            let msi = unsafe { enable(dev.loc, &PortOpsImpl, alloc_irq) };
+            let irq = msi.or_else(|| route_pci_irq(dev));
+            let (addr, len) = (addr as usize, len as usize);
+            for page in (addr..addr + len).step_by(PAGE_SIZE) {
+                map_mmio(page);
+            }
+            if let Some(driver) = ahci::init(irq, addr, len) {
+                BLK_DRIVERS.write().push(driver.clone());
+                register_driver(driver, irq);
+                // the driver is there to clear the level triggered line now
+                if let (None, Some(irq)) = (msi, irq) {
+                    unmask_pci_irq(irq);
+                }
+            }
         }
     }
+    // only when the bootloader handed over no framebuffer
+    if FRAMEBUFFER_CONSOLE && !framebuffer::is_active()
+        && dev.id.vendor_id == bga::VENDOR_ID && dev.id.device_id == bga::DEVICE_ID
+    {
+        bga::init(dev);
+    }
+}
+
+/// Publish `driver` and route its `irq` to it.
+fn register_driver(driver: Arc<dyn Driver>, irq: Option<usize>) {
+    DRIVERS.write().push(driver.clone());
+    if let Some(irq) = irq {
+        register_irq(irq, driver);
+    }
 }
 
 
@@ -0,0 +1,202 @@
+//! Stopping the machine: reboot, ACPI S5 power off, and exiting qemu through
+//! its isa-debug-exit device.
+
+use core::ptr::write_volatile;
+use log::{info, warn};
+use x86_64::{VirtAddr, instructions::{interrupts, port::Port, tables::{DescriptorTablePointer, lidt}}};
+
+use crate::drivers::pci::BusLocation;
+
+use super::{acpi::{GenericAddress, acpi}, cpu::halt, hpet, i8042, memory::map_mmio, pci::write_config8, serial, timer::pit_delay};
+
+/// time given to a reset method before the next one is tried
+const RESET_WAIT_US: u64 = 100_000;
+/// time the firmware gets to switch to ACPI mode, in `POLL_US` steps
+const ACPI_ENABLE_POLLS: usize = 300;
+const POLL_US: u64 = 10_000;
+
+// PM1 control register
+const PM1_SCI_EN: u16 = 1 << 0;
+const PM1_SLP_TYP_SHIFT: u16 = 10;
+const PM1_SLP_TYP: u16 = 7 << PM1_SLP_TYP_SHIFT;
+const PM1_SLP_EN: u16 = 1 << 13;
+
+/// where `-device isa-debug-exit,iobase=0xf4` listens, see `TEST_ARGS` in tools/run.py
+const QEMU_EXIT_PORT: u16 = 0xf4;
+
+/// What qemu exits with, as `(code << 1) | 1`: 33 for success, which
+/// `tools/run.py` reports as a passed test run, and 35 for failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+impl QemuExitCode {
+    /// what qemu exits with after `exit_qemu(self)`
+    pub fn status(self) -> u32 {
+        ((self as u32) << 1) | 1
+    }
+}
+
+fn delay(us: u64) {
+    if hpet::is_present() {
+        hpet::udelay(us);
+    } else {
+        pit_delay(us);
+    }
+}
+
+/// Halt with interrupts off, for good.
+fn halt_forever() -> ! {
+    interrupts::disable();
+    loop {
+        halt();
+    }
+}
+
+/// Load an empty idt and raise an exception, the cpu cannot deliver it
+/// and resets on the triple fault.
+fn triple_fault() -> ! {
+    let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+    unsafe {
+        lidt(&empty);
+        asm!("int3", options(nomem, nostack));
+    }
+    loop {}
+}
+
+/// Write the FADT reset value to the reset register, returns false if there
+/// is none or it is in an address space we cannot write.
+fn acpi_reset() -> bool {
+    let (reg, value) = match acpi().and_then(|info| info.fadt).and_then(|fadt| fadt.reset) {
+        Some(reset) => reset,
+        None => return false,
+    };
+    let address = reg.address;
+    match reg.space_id {
+        GenericAddress::SYSTEM_IO => unsafe { Port::<u8>::new(address as u16).write(value) },
+        GenericAddress::SYSTEM_MEMORY => {
+            map_mmio(address as usize);
+            unsafe { write_volatile(address as *mut u8, value) };
+        }
+        // bus 0, device and function in the upper words, offset in the lowest
+        GenericAddress::PCI_CONFIG => {
+            let loc = BusLocation { bus: 0, device: (address >> 32) as u8, function: (address >> 16) as u8 };
+            write_config8(loc, address as u16, value);
+        }
+        space => {
+            warn!("reset register in address space {}", space);
+            return false;
+        }
+    }
+    true
+}
+
+/// Restart the machine: the ACPI reset register first, then the i8042 reset
+/// line, a triple fault if neither did anything.
+pub fn reboot() -> ! {
+    info!("rebooting");
+    interrupts::disable();
+    if acpi_reset() {
+        delay(RESET_WAIT_US);
+        warn!("acpi reset failed");
+    }
+    i8042::pulse_reset();
+    delay(RESET_WAIT_US);
+    warn!("i8042 reset failed, triple faulting");
+    triple_fault()
+}
+
+/// Have the firmware hand power management to us if it still has it, by
+/// writing `acpi_enable` to the SMI command port and waiting for `SCI_EN`.
+fn enable_acpi_mode(smi_cmd: u32, acpi_enable: u8, pm1a_control: u16) -> bool {
+    let mut pm1a = Port::<u16>::new(pm1a_control);
+    if unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+        return true;
+    }
+    if smi_cmd == 0 || acpi_enable == 0 {
+        // hardware reduced, or no legacy mode to leave
+        return true;
+    }
+    unsafe { Port::<u8>::new(smi_cmd as u16).write(acpi_enable) };
+    for _ in 0..ACPI_ENABLE_POLLS {
+        if unsafe { pm1a.read() } & PM1_SCI_EN != 0 {
+            return true;
+        }
+        delay(POLL_US);
+    }
+    false
+}
+
+/// Set `SLP_TYP` to `sleep_type` and `SLP_EN` in the PM1 control register at `port`.
+fn enter_sleep(port: u16, sleep_type: u8) {
+    let mut control = Port::<u16>::new(port);
+    unsafe {
+        let old = control.read();
+        control.write((old & !PM1_SLP_TYP) | ((sleep_type as u16) << PM1_SLP_TYP_SHIFT) | PM1_SLP_EN);
+    }
+}
+
+/// Enter S5 through the PM1 control block of the FADT, with the sleep
+/// types of `_S5_`. returns if the machine is still on.
+fn acpi_shutdown() {
+    let info = match acpi() {
+        Some(info) => info,
+        None => return,
+    };
+    let (fadt, (slp_typ_a, slp_typ_b)) = match (info.fadt, info.s5) {
+        (Some(fadt), Some(s5)) => (fadt, s5),
+        _ => {
+            warn!("no fadt or _S5_, cannot power off");
+            return;
+        }
+    };
+    if fadt.pm1a_control == 0 {
+        return;
+    }
+    if !enable_acpi_mode(fadt.smi_cmd, fadt.acpi_enable, fadt.pm1a_control as u16) {
+        warn!("firmware did not enable acpi");
+        return;
+    }
+    enter_sleep(fadt.pm1a_control as u16, slp_typ_a);
+    if fadt.pm1b_control != 0 {
+        enter_sleep(fadt.pm1b_control as u16, slp_typ_b);
+    }
+    delay(RESET_WAIT_US);
+    warn!("acpi power off failed");
+}
+
+/// Power the machine off, or halt if that is not possible.
+pub fn shutdown() -> ! {
+    info!("powering off");
+    interrupts::disable();
+    acpi_shutdown();
+    warn!("halting, the machine can be turned off");
+    halt_forever()
+}
+
+/// Make qemu exit with `code`. Without the isa-debug-exit device the write
+/// does nothing, and the machine is powered off instead.
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    interrupts::disable();
+    // queued output would be lost with the machine
+    serial::flush();
+    unsafe { Port::<u32>::new(QEMU_EXIT_PORT).write(code as u32) };
+    warn!("no isa-debug-exit device at {:#x}", QEMU_EXIT_PORT);
+    shutdown()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QemuExitCode;
+
+    #[test_case]
+    fn exit_statuses() {
+        // what TEST_SUCCESS_EXIT_CODE in tools/run.py expects, cargo test
+        // only passes when the test kernel exits with it
+        assert_eq!(QemuExitCode::Success.status(), 33);
+        assert_eq!(QemuExitCode::Failed.status(), 35);
+    }
+}
@@ -0,0 +1,238 @@
+use alloc::{string::String, sync::Arc};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+use x86_64::instructions::port::Port;
+
+use crate::{drivers::{DRIVERS, DeviceType, Driver, irq::register_irq}, sync::mutex::SpinNoIrqLock, time::realtime::{DateTime, set_realtime}};
+
+use super::{acpi::acpi, ioapic::enable_isa_irq};
+use log::info;
+
+const CMOS_INDEX: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+const REG_STATUS_C: u8 = 0x0c;
+
+/// an update is in progress, the time registers are not stable
+const STATUS_A_UIP: u8 = 1 << 7;
+const STATUS_A_RATE_MASK: u8 = 0x0f;
+const STATUS_B_PIE: u8 = 1 << 6;
+const STATUS_B_UIE: u8 = 1 << 4;
+const STATUS_B_BINARY: u8 = 1 << 2;
+const STATUS_B_24H: u8 = 1 << 1;
+const STATUS_C_PF: u8 = 1 << 6;
+const STATUS_C_UF: u8 = 1 << 4;
+const HOUR_PM: u8 = 1 << 7;
+
+/// isa irq of the rtc
+pub const RTC_IRQ: u8 = 8;
+
+/// century assumed when the FADT names no century register
+const DEFAULT_CENTURY: u16 = 20;
+
+lazy_static! {
+    /// index and data port, the index must not change between the two accesses
+    static ref CMOS: SpinNoIrqLock<(Port<u8>, Port<u8>)> =
+        SpinNoIrqLock::new((Port::new(CMOS_INDEX), Port::new(CMOS_DATA)));
+}
+
+fn cmos_read(reg: u8) -> u8 {
+    let mut cmos = CMOS.lock();
+    unsafe {
+        cmos.0.write(reg);
+        cmos.1.read()
+    }
+}
+
+fn cmos_write(reg: u8, val: u8) {
+    let mut cmos = CMOS.lock();
+    unsafe {
+        cmos.0.write(reg);
+        cmos.1.write(val);
+    }
+}
+
+fn from_bcd(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0x0f)
+}
+
+/// raw registers: seconds, minutes, hours, day, month, year, century
+fn read_raw(century_reg: u8) -> [u8; 7] {
+    while cmos_read(REG_STATUS_A) & STATUS_A_UIP != 0 {
+        core::hint::spin_loop();
+    }
+    [
+        cmos_read(REG_SECONDS),
+        cmos_read(REG_MINUTES),
+        cmos_read(REG_HOURS),
+        cmos_read(REG_DAY),
+        cmos_read(REG_MONTH),
+        cmos_read(REG_YEAR),
+        if century_reg != 0 { cmos_read(century_reg) } else { 0 },
+    ]
+}
+
+/// Read the date and time from the rtc.
+///
+/// the registers are read until two reads agree, so an update
+/// starting in the middle of a read is not mistaken for the time
+pub fn read_rtc() -> DateTime {
+    let century_reg = acpi().and_then(|a| a.fadt).map_or(0, |f| f.century);
+    let mut raw = read_raw(century_reg);
+    loop {
+        let again = read_raw(century_reg);
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+    decode(raw, cmos_read(REG_STATUS_B), century_reg != 0)
+}
+
+/// The date in registers `raw`, in the format `status_b` says.
+fn decode(raw: [u8; 7], status_b: u8, has_century: bool) -> DateTime {
+    let [mut sec, mut min, hour_raw, mut day, mut month, mut year, mut century] = raw;
+    let pm = hour_raw & HOUR_PM != 0;
+    let mut hour = hour_raw & !HOUR_PM;
+    if status_b & STATUS_B_BINARY == 0 {
+        sec = from_bcd(sec);
+        min = from_bcd(min);
+        hour = from_bcd(hour);
+        day = from_bcd(day);
+        month = from_bcd(month);
+        year = from_bcd(year);
+        century = from_bcd(century);
+    }
+    if status_b & STATUS_B_24H == 0 {
+        // 12 AM is midnight, 12 PM is noon
+        hour %= 12;
+        if pm {
+            hour += 12;
+        }
+    }
+    let century = if has_century && century != 0 { century as u16 } else { DEFAULT_CENTURY };
+    DateTime {
+        year: century * 100 + year as u16,
+        month,
+        day,
+        hour,
+        minute: min,
+        second: sec,
+    }
+}
+
+/// Set the wall clock from the rtc, `DateTime` has second granularity.
+fn sync_realtime() {
+    set_realtime(read_rtc().to_unix() * 1_000_000_000);
+}
+
+pub struct RtcDriver {
+    irq: AtomicUsize,
+    /// periodic interrupts so far
+    ticks: AtomicU64,
+}
+
+impl RtcDriver {
+    /// periodic interrupts received since `set_periodic`
+    pub fn periodic_ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Relaxed)
+    }
+
+    /// Enable the periodic interrupt at `32768 >> (rate - 1)` Hz, `rate` in 3..=15,
+    /// or disable it with `None`.
+    pub fn set_periodic(&self, rate: Option<u8>) {
+        let status_a = cmos_read(REG_STATUS_A);
+        let status_b = cmos_read(REG_STATUS_B);
+        match rate {
+            Some(rate) => {
+                assert!((3..=15).contains(&rate), "bad rtc rate {}", rate);
+                cmos_write(REG_STATUS_A, (status_a & !STATUS_A_RATE_MASK) | rate);
+                cmos_write(REG_STATUS_B, status_b | STATUS_B_PIE);
+            }
+            None => cmos_write(REG_STATUS_B, status_b & !STATUS_B_PIE),
+        }
+        // a pending flag would keep the line from firing again
+        cmos_read(REG_STATUS_C);
+    }
+}
+
+impl Driver for RtcDriver {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        if irq.is_some() && irq != Some(self.irq.load(Ordering::Relaxed)) {
+            return false;
+        }
+        // reading status c acknowledges the interrupt
+        let status = cmos_read(REG_STATUS_C);
+        if status & STATUS_C_UF != 0 {
+            // the seconds just rolled over, so the time read now is exact
+            sync_realtime();
+        }
+        if status & STATUS_C_PF != 0 {
+            self.ticks.fetch_add(1, Ordering::Relaxed);
+        }
+        status & (STATUS_C_UF | STATUS_C_PF) != 0
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Rtc
+    }
+
+    fn get_id(&self) -> String {
+        String::from("rtc_cmos")
+    }
+}
+
+lazy_static! {
+    pub static ref RTC: Arc<RtcDriver> = Arc::new(RtcDriver {
+        irq: AtomicUsize::new(0),
+        ticks: AtomicU64::new(0),
+    });
+}
+
+/// Set the wall clock from the rtc and keep it in sync.
+///
+/// the update ended interrupt fires on every second boundary, which is
+/// when the wall clock is resynchronized with the monotonic clock
+pub fn init_rtc() {
+    sync_realtime();
+    info!("{} UTC", read_rtc());
+    DRIVERS.write().push(RTC.clone());
+    if let Some(irq) = enable_isa_irq(RTC_IRQ) {
+        RTC.irq.store(irq, Ordering::Relaxed);
+        register_irq(irq, RTC.clone());
+        let status_b = cmos_read(REG_STATUS_B);
+        cmos_write(REG_STATUS_B, status_b | STATUS_B_UIE);
+        cmos_read(REG_STATUS_C);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn bcd_12_hour() {
+        let raw = [0x59, 0x30, HOUR_PM | 0x12, 0x31, 0x12, 0x99, 0x19];
+        assert_eq!(decode(raw, 0, true), DateTime::new(1999, 12, 31, 12, 30, 59));
+        // 12 AM is midnight
+        let raw = [0x00, 0x00, 0x12, 0x01, 0x01, 0x00, 0x20];
+        assert_eq!(decode(raw, 0, true), DateTime::new(2000, 1, 1, 0, 0, 0));
+        let raw = [0x00, 0x00, HOUR_PM | 0x01, 0x01, 0x01, 0x00, 0x20];
+        assert_eq!(decode(raw, 0, true).hour, 13);
+    }
+
+    #[test_case]
+    fn binary_24_hour() {
+        let raw = [5, 4, 23, 1, 2, 24, 0];
+        // no century register, the default one
+        assert_eq!(decode(raw, STATUS_B_BINARY | STATUS_B_24H, false), DateTime::new(2024, 2, 1, 23, 4, 5));
+    }
+}
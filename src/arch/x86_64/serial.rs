@@ -0,0 +1,402 @@
+use alloc::{boxed::Box, format, string::String, sync::Arc};
+use core::{fmt, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use spin::Once;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::{consts::GDB_COM, drivers::{DRIVERS, DeviceType, Driver, irq::register_irq, tty::{Tty, TtyDriver, register_tty}}, panicking, sync::{mutex::SpinNoIrqLock, ring::Ring}};
+
+use super::{gdb, ioapic::enable_isa_irq};
+use log::info;
+
+// register offsets from the base port
+const DATA: u16 = 0;
+const IER: u16 = 1;
+/// interrupt identification on read, fifo control on write
+const IIR_FCR: u16 = 2;
+const LCR: u16 = 3;
+const MCR: u16 = 4;
+const LSR: u16 = 5;
+const MSR: u16 = 6;
+/// divisor latch, while LCR_DLAB is set
+const DLL: u16 = 0;
+const DLM: u16 = 1;
+
+const IER_RX: u8 = 1 << 0;
+const IER_TX_EMPTY: u8 = 1 << 1;
+
+const IIR_NONE_PENDING: u8 = 1 << 0;
+const IIR_CAUSE_MASK: u8 = 0x0e;
+const IIR_MODEM_STATUS: u8 = 0x00;
+const IIR_TX_EMPTY: u8 = 0x02;
+const IIR_RX_DATA: u8 = 0x04;
+const IIR_LINE_STATUS: u8 = 0x06;
+const IIR_RX_TIMEOUT: u8 = 0x0c;
+
+/// enable and clear both fifos, rx interrupt at 14 bytes
+const FCR_ENABLE_CLEAR_14: u8 = 0xc7;
+const LCR_8N1: u8 = 0x03;
+const LCR_DLAB: u8 = 0x80;
+/// DTR, RTS and OUT2, which gates the interrupt line
+const MCR_NORMAL: u8 = 0x0b;
+const MCR_LOOPBACK_TEST: u8 = 0x1e;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+/// the last byte left the shift register too
+const LSR_TX_IDLE: u8 = 1 << 6;
+
+/// the uart clock divided by 16
+const BASE_BAUD: u32 = 115200;
+pub const DEFAULT_BAUD: u32 = 115200;
+
+const FIFO_SIZE: usize = 16;
+const BUFFER_SIZE: usize = 1024;
+
+struct Buffers {
+    rx: Ring<BUFFER_SIZE>,
+    tx: Ring<BUFFER_SIZE>,
+}
+
+/// One 16550 compatible uart.
+///
+/// until its irq is set up transmission is polled, afterwards bytes are queued
+/// and sent from the transmitter empty interrupt, except with interrupts off
+/// or in a panic, when that interrupt may never come
+pub struct SerialPort {
+    base: u16,
+    isa_irq: u8,
+    present: AtomicBool,
+    /// interrupts are routed, 0 otherwise
+    irq: AtomicUsize,
+    buffers: SpinNoIrqLock<Buffers>,
+    /// terminal received bytes go to instead of `Buffers::rx`
+    tty: Once<Arc<Tty>>,
+}
+
+impl SerialPort {
+    const fn new(base: u16, isa_irq: u8) -> Self {
+        SerialPort {
+            base,
+            isa_irq,
+            present: AtomicBool::new(false),
+            irq: AtomicUsize::new(0),
+            buffers: SpinNoIrqLock::new(Buffers { rx: Ring::new(), tx: Ring::new() }),
+            tty: Once::new(),
+        }
+    }
+
+    fn inb(&self, reg: u16) -> u8 {
+        unsafe { Port::new(self.base + reg).read() }
+    }
+
+    fn outb(&self, reg: u16, val: u8) {
+        unsafe { Port::new(self.base + reg).write(val) }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present.load(Ordering::Relaxed)
+    }
+
+    fn irq_enabled(&self) -> bool {
+        self.irq.load(Ordering::Relaxed) != 0
+    }
+
+    /// Program `baud` 8N1 and check the uart is there with a loopback test.
+    fn init(&self, baud: u32) -> bool {
+        let divisor = (BASE_BAUD / baud.max(1)).max(1) as u16;
+        self.outb(IER, 0);
+        self.outb(LCR, LCR_DLAB);
+        self.outb(DLL, divisor as u8);
+        self.outb(DLM, (divisor >> 8) as u8);
+        self.outb(LCR, LCR_8N1);
+        self.outb(IIR_FCR, FCR_ENABLE_CLEAR_14);
+        self.outb(MCR, MCR_LOOPBACK_TEST);
+        self.outb(DATA, 0xae);
+        if self.inb(DATA) != 0xae {
+            return false;
+        }
+        self.outb(MCR, MCR_NORMAL);
+        self.present.store(true, Ordering::Relaxed);
+        true
+    }
+
+    fn send_polled(&self, b: u8) {
+        while self.inb(LSR) & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        self.outb(DATA, b);
+    }
+
+    /// Fill the transmit fifo from the queue, and ask for an interrupt
+    /// when it drains if bytes are left.
+    fn start_tx(&self, buffers: &mut Buffers) {
+        if self.inb(LSR) & LSR_THR_EMPTY != 0 {
+            for _ in 0..FIFO_SIZE {
+                match buffers.tx.pop() {
+                    Some(b) => self.outb(DATA, b),
+                    None => break,
+                }
+            }
+        }
+        let ier = if buffers.tx.is_empty() { IER_RX } else { IER_RX | IER_TX_EMPTY };
+        self.outb(IER, ier);
+    }
+
+    pub fn write_byte(&self, b: u8) {
+        if !self.is_present() {
+            return;
+        }
+        // checked before locking, which disables interrupts
+        if !interrupts::are_enabled() || panicking() {
+            return self.write_byte_polled(b);
+        }
+        let mut buffers = self.buffers.lock();
+        if !self.irq_enabled() {
+            self.send_polled(b);
+            return;
+        }
+        if buffers.tx.is_full() {
+            // better to wait than to lose output
+            while let Some(c) = buffers.tx.pop() {
+                self.send_polled(c);
+            }
+        }
+        buffers.tx.push(b);
+        self.start_tx(&mut buffers);
+    }
+
+    pub fn write_str(&self, s: &str) {
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(b);
+        }
+    }
+
+    /// next received byte, if any
+    pub fn read_byte(&self) -> Option<u8> {
+        let mut buffers = self.buffers.lock();
+        if !self.irq_enabled() && self.is_present() && self.inb(LSR) & LSR_DATA_READY != 0 {
+            return Some(self.inb(DATA));
+        }
+        buffers.rx.pop()
+    }
+
+    /// Blocking read that does not rely on the receive interrupt, for code
+    /// running with interrupts disabled such as the gdb stub.
+    pub fn read_byte_polled(&self) -> u8 {
+        loop {
+            let mut buffers = self.buffers.lock();
+            if let Some(b) = buffers.rx.pop() {
+                return b;
+            }
+            if self.inb(LSR) & LSR_DATA_READY != 0 {
+                return self.inb(DATA);
+            }
+            drop(buffers);
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Send `b` right away, after whatever is still queued.
+    pub fn write_byte_polled(&self, b: u8) {
+        let mut buffers = self.buffers.lock();
+        while let Some(c) = buffers.tx.pop() {
+            self.send_polled(c);
+        }
+        self.send_polled(b);
+    }
+
+    /// Send `s` polled without taking the queue lock, for a fault dump that
+    /// has to get out even when the fault hit with the lock held. it may
+    /// interleave with bytes still queued
+    pub fn write_str_unlocked(&self, s: &str) {
+        if !self.is_present() {
+            return;
+        }
+        for b in s.bytes() {
+            if b == b'\n' {
+                self.send_polled(b'\r');
+            }
+            self.send_polled(b);
+        }
+    }
+
+    /// Send what is queued and wait until it is out on the line.
+    pub fn flush(&self) {
+        if !self.is_present() {
+            return;
+        }
+        let mut buffers = self.buffers.lock();
+        while let Some(c) = buffers.tx.pop() {
+            self.send_polled(c);
+        }
+        while self.inb(LSR) & LSR_TX_IDLE == 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Serve every pending interrupt cause, true if there was one.
+    fn handle_interrupt(&self) -> bool {
+        let mut handled = false;
+        let tty = self.tty.get();
+        loop {
+            let mut received = [0; FIFO_SIZE];
+            let mut count = 0;
+            {
+                let mut buffers = self.buffers.lock();
+                let iir = self.inb(IIR_FCR);
+                if iir & IIR_NONE_PENDING != 0 {
+                    break;
+                }
+                handled = true;
+                match iir & IIR_CAUSE_MASK {
+                    IIR_RX_DATA | IIR_RX_TIMEOUT => {
+                        while count < FIFO_SIZE && self.inb(LSR) & LSR_DATA_READY != 0 {
+                            let b = self.inb(DATA);
+                            if tty.is_some() {
+                                received[count] = b;
+                                count += 1;
+                            } else {
+                                // dropped when nobody reads
+                                buffers.rx.push(b);
+                            }
+                        }
+                    }
+                    IIR_TX_EMPTY => self.start_tx(&mut buffers),
+                    IIR_LINE_STATUS => {
+                        self.inb(LSR);
+                    }
+                    IIR_MODEM_STATUS => {
+                        self.inb(MSR);
+                    }
+                    _ => break,
+                }
+            }
+            // the tty echoes through `write_byte`, so without the buffers locked
+            if let Some(tty) = tty {
+                if count != 0 {
+                    tty.receive(&received[..count]);
+                }
+            }
+        }
+        handled
+    }
+
+    /// the terminal on this port, `ttyS0` for COM1
+    pub fn tty(&self) -> Option<&Arc<Tty>> {
+        self.tty.get()
+    }
+}
+
+/// COM1 to COM4 at their standard ports and isa irqs.
+pub static COM: [SerialPort; 4] = [
+    SerialPort::new(0x3f8, 4),
+    SerialPort::new(0x2f8, 3),
+    SerialPort::new(0x3e8, 4),
+    SerialPort::new(0x2e8, 3),
+];
+
+/// COM1, where the console is mirrored
+pub fn com1() -> &'static SerialPort {
+    &COM[0]
+}
+
+/// Send the output queued on every port, before the machine stops or
+/// interrupts go away for good.
+pub fn flush() {
+    for port in COM.iter() {
+        port.flush();
+    }
+}
+
+/// `TtyDriver` of a port, output is sent as it is.
+struct SerialTty {
+    index: usize,
+}
+
+impl TtyDriver for SerialTty {
+    fn write(&self, bytes: &[u8]) {
+        for &b in bytes {
+            COM[self.index].write_byte(b);
+        }
+    }
+}
+
+/// `Driver` view of a port, the ports themselves are statics.
+pub struct SerialDriver {
+    index: usize,
+}
+
+impl Driver for SerialDriver {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        let port = &COM[self.index];
+        if irq.is_some() && irq != Some(port.irq.load(Ordering::Relaxed)) {
+            return false;
+        }
+        port.handle_interrupt()
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Serial
+    }
+
+    fn get_id(&self) -> String {
+        format!("com{}", self.index + 1)
+    }
+}
+
+/// Probe the four ports and program the ones present, output is polled for now.
+///
+/// runs first thing at boot so even early messages reach the serial console
+pub fn init_serial() {
+    for port in COM.iter() {
+        port.init(DEFAULT_BAUD);
+    }
+}
+
+/// Switch the present ports to interrupt driven operation and register them,
+/// with a tty on each but the gdb port.
+pub fn init_serial_irq() {
+    for (index, port) in COM.iter().enumerate().filter(|(_, p)| p.is_present()) {
+        let driver = Arc::new(SerialDriver { index });
+        DRIVERS.write().push(driver.clone());
+        if let Some(irq) = enable_isa_irq(port.isa_irq) {
+            register_irq(irq, driver);
+            // the gdb stub's port is not a terminal
+            if !(gdb::is_active() && index == GDB_COM) {
+                port.tty.call_once(|| register_tty(format!("ttyS{}", index), Box::new(SerialTty { index })));
+            }
+            // no byte may be sent polled once `irq` says they are queued
+            let _buffers = port.buffers.lock();
+            port.irq.store(irq, Ordering::Relaxed);
+            port.outb(IER, IER_RX);
+        }
+        info!("com{} at {:#x}", index + 1, port.base);
+    }
+}
+
+struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        com1().write_str(s);
+        Ok(())
+    }
+}
+
+/// COM1 written with `write_str_unlocked`, which takes no locks.
+pub struct Polled;
+
+impl fmt::Write for Polled {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        com1().write_str_unlocked(s);
+        Ok(())
+    }
+}
+
+/// Mirror of `console::_print` on COM1.
+pub fn _print(args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut Console, args);
+}
@@ -0,0 +1,138 @@
+use alloc::vec::Vec;
+use core::{hint::spin_loop, ptr, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+
+use apic::LocalApic;
+use x86_64::registers::control::Cr3;
+
+use crate::{consts::MAX_CPU_NUM, process::{idle::idle_loop, init_cpu, proc::create_idle_process}, memory::{BITMAP_ALLOCATOR, addr::phys_to_virt, bitalloc::BitAlloc}};
+
+use super::{acpi::acpi, consts::PAGE_SIZE, cpu::init_percpu, gdt::init_gdt, interrupt::int::init_ap_idt, lapic::{lapic, lapic_id}, memory::map_identity};
+use log::info;
+
+global_asm!(include_str!("trampoline.S"));
+
+extern "C" {
+    fn ap_trampoline_start();
+    fn ap_trampoline_args();
+    fn ap_trampoline_end();
+}
+
+/// physical address the application processors start at,
+/// must be page aligned and below 1M to be reachable from a startup ipi
+pub const AP_TRAMPOLINE: usize = 0x8000;
+
+/// boot stack of each application processor, in 4k frames
+const AP_STACK_FRAMES: usize = 4;
+
+/// how long to spin waiting for an application processor to check in
+const AP_BOOT_TIMEOUT: usize = 10_000_000;
+
+/// argument block at the end of the trampoline, layout shared with trampoline.S
+#[repr(C)]
+struct TrampolineArgs {
+    cr3: u64,
+    stack_top: u64,
+    entry: u64,
+    cpu_id: u64,
+}
+
+/// set by an application processor once it no longer needs the trampoline
+static AP_BOOTED: AtomicBool = AtomicBool::new(false);
+
+/// number of cpus that finished booting, including the bsp
+static CPU_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+pub fn cpu_count() -> usize {
+    CPU_COUNT.load(Ordering::Acquire)
+}
+
+/// Set up the per-cpu state of the bsp, must run before anything calls `this_cpu`.
+pub fn init_bsp() {
+    init_percpu(0, lapic_id());
+    init_gdt();
+}
+
+/// Wake up the application processors with INIT-SIPI-SIPI.
+///
+/// the cpus come from the MADT, without it apic ids are probed one by one
+/// and a missing cpu simply never checks in
+pub fn start_aps() {
+    let start = ap_trampoline_start as usize;
+    let len = ap_trampoline_end as usize - start;
+    assert!(len <= PAGE_SIZE);
+    unsafe {
+        ptr::copy_nonoverlapping(start as *const u8, phys_to_virt(AP_TRAMPOLINE) as *mut u8, len);
+    }
+    // the trampoline keeps running from the same address right after paging is on
+    map_identity(AP_TRAMPOLINE);
+
+    let bsp = lapic_id();
+    let apic_ids: Vec<u32> = match acpi() {
+        Some(info) if !info.processors.is_empty() => {
+            info.processors.iter().map(|p| p.apic_id).collect()
+        }
+        _ => (0..MAX_CPU_NUM as u32).collect(),
+    };
+    for apic_id in apic_ids {
+        // the xapic can only send startup ipis to 8 bit ids
+        if apic_id == bsp || apic_id > u8::MAX as u32 {
+            continue;
+        }
+        let id = cpu_count();
+        if id >= MAX_CPU_NUM {
+            break;
+        }
+        if boot_ap(id, apic_id) {
+            CPU_COUNT.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+    info!("{} cpus online", cpu_count());
+}
+
+fn boot_ap(cpu_id: usize, apic_id: u32) -> bool {
+    let frame = match BITMAP_ALLOCATOR.lock().alloc_contiguous(AP_STACK_FRAMES, 0) {
+        Some(f) => f,
+        None => return false,
+    };
+    let stack_top = phys_to_virt((frame + AP_STACK_FRAMES) * PAGE_SIZE);
+    let (p4, _) = Cr3::read();
+    let cr3 = p4.start_address().as_u64();
+    assert!(cr3 < (1 << 32), "page table unreachable from protected mode");
+
+    let offset = ap_trampoline_args as usize - ap_trampoline_start as usize;
+    let args = phys_to_virt(AP_TRAMPOLINE + offset) as *mut TrampolineArgs;
+    unsafe {
+        ptr::write_volatile(args, TrampolineArgs {
+            cr3,
+            stack_top: stack_top as u64,
+            entry: ap_main as usize as u64,
+            cpu_id: cpu_id as u64,
+        });
+    }
+
+    AP_BOOTED.store(false, Ordering::Release);
+    unsafe { lapic().start_ap(apic_id as u8, AP_TRAMPOLINE as u32) };
+    for _ in 0..AP_BOOT_TIMEOUT {
+        if AP_BOOTED.load(Ordering::Acquire) {
+            return true;
+        }
+        spin_loop();
+    }
+
+    let mut b = BITMAP_ALLOCATOR.lock();
+    for i in 0..AP_STACK_FRAMES {
+        b.dealloc(frame + i);
+    }
+    false
+}
+
+/// Rust entry of the application processors, called by the trampoline.
+extern "C" fn ap_main(cpu_id: usize) -> ! {
+    init_percpu(cpu_id, lapic_id());
+    init_gdt();
+    init_cpu(create_idle_process());
+    init_ap_idt();
+    AP_BOOTED.store(true, Ordering::Release);
+    info!("cpu {} (apic {}) online", cpu_id, lapic_id());
+    idle_loop()
+}
@@ -0,0 +1,242 @@
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering, fence};
+
+use raw_cpuid::CpuId;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+use x86_64::registers::model_specific::Msr;
+
+use crate::consts::{MAX_CPU_NUM, USEC_PER_TICK};
+
+use super::{cpu::{cpu, cpu_id}, hpet, tsc::{rdtsc, tsc_frequency}, lapic::{RESCHEDULE_VECTOR, lapic_read, lapic_write, send_ipi}};
+use log::info;
+
+/// vector of the local apic timer
+pub const TIMER_VECTOR: u32 = 32;
+
+const LVT_TIMER: usize = 0x320;
+const INITIAL_COUNT: usize = 0x380;
+const CURRENT_COUNT: usize = 0x390;
+const DIVIDE_CONFIG: usize = 0x3e0;
+
+const DIVIDE_BY_16: u32 = 0x3;
+const LVT_MASKED: u32 = 1 << 16;
+
+const MODE_ONESHOT: u32 = 0;
+const MODE_PERIODIC: u32 = 1 << 17;
+const MODE_TSC_DEADLINE: u32 = 2 << 17;
+
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+const PIT_CHANNEL2: u16 = 0x42;
+const PIT_COMMAND: u16 = 0x43;
+/// gate of pit channel 2 and its output, through the keyboard controller port b
+const PIT_GATE_PORT: u16 = 0x61;
+const PIT_GATE: u8 = 1 << 0;
+const PIT_SPEAKER: u8 = 1 << 1;
+const PIT_OUT2: u8 = 1 << 5;
+/// channel 2, low then high byte, mode 0 (interrupt on terminal count)
+const PIT_CH2_ONESHOT: u8 = 0b1011_0000;
+
+/// length of the calibration window
+const CALIBRATE_US: u64 = 10_000;
+
+/// local apic timer counts per second, after the divider
+static TIMER_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+/// local apic timer counts in one tick, 0 until calibrated
+static COUNT_PER_TICK: AtomicU32 = AtomicU32::new(0);
+
+/// tsc cycles in one tick, 0 until measured
+static TSC_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// one-shot state of the timer of one cpu
+struct CpuTimer {
+    /// the periodic tick is stopped
+    oneshot: AtomicBool,
+    /// ticks until the one-shot interrupt
+    armed: AtomicU64,
+    start_tsc: AtomicU64,
+}
+
+impl CpuTimer {
+    const INIT: CpuTimer = CpuTimer {
+        oneshot: AtomicBool::new(false),
+        armed: AtomicU64::new(0),
+        start_tsc: AtomicU64::new(0),
+    };
+}
+
+static TIMERS: [CpuTimer; MAX_CPU_NUM] = [CpuTimer::INIT; MAX_CPU_NUM];
+
+fn has_tsc_deadline() -> bool {
+    CpuId::new().get_feature_info().map_or(false, |f| f.has_tsc_deadline())
+}
+
+/// Program pit channel 2 to count `us` microseconds once its gate opens.
+fn pit_arm(us: u64) {
+    let count = (PIT_FREQUENCY * us / 1_000_000).min(u16::MAX as u64) as u16;
+    unsafe {
+        let mut gate: Port<u8> = Port::new(PIT_GATE_PORT);
+        let v = gate.read() & !(PIT_GATE | PIT_SPEAKER);
+        gate.write(v);
+        Port::<u8>::new(PIT_COMMAND).write(PIT_CH2_ONESHOT);
+        let mut ch2: Port<u8> = Port::new(PIT_CHANNEL2);
+        ch2.write(count as u8);
+        ch2.write((count >> 8) as u8);
+    }
+}
+
+/// Open the gate of pit channel 2 and wait for it to count down.
+fn pit_wait() {
+    unsafe {
+        let mut gate: Port<u8> = Port::new(PIT_GATE_PORT);
+        let v = gate.read();
+        gate.write(v | PIT_GATE);
+        while gate.read() & PIT_OUT2 == 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Busy wait for `us` microseconds on pit channel 2, at most about 50ms.
+pub(super) fn pit_delay(us: u64) {
+    pit_arm(us);
+    pit_wait();
+}
+
+/// Measure the local apic timer against the hpet, or the pit without one.
+///
+/// the tsc is measured along, in case `init_tsc` could not tell its frequency
+fn calibrate() {
+    let (counts, cycles) = without_interrupts(|| {
+        lapic_write(DIVIDE_CONFIG, DIVIDE_BY_16);
+        lapic_write(LVT_TIMER, LVT_MASKED | MODE_ONESHOT | TIMER_VECTOR);
+        let use_hpet = hpet::is_present();
+        if !use_hpet {
+            pit_arm(CALIBRATE_US);
+        }
+        lapic_write(INITIAL_COUNT, u32::MAX);
+        let start = rdtsc();
+        if use_hpet {
+            hpet::udelay(CALIBRATE_US);
+        } else {
+            pit_wait();
+        }
+        (u32::MAX - lapic_read(CURRENT_COUNT), rdtsc() - start)
+    });
+    let frequency = counts as u64 * 1_000_000 / CALIBRATE_US;
+    let tsc_frequency = match tsc_frequency() {
+        0 => cycles * 1_000_000 / CALIBRATE_US,
+        hz => hz,
+    };
+    TIMER_FREQUENCY.store(frequency, Ordering::Relaxed);
+    TSC_PER_TICK.store(tsc_frequency * USEC_PER_TICK as u64 / 1_000_000, Ordering::Relaxed);
+    let per_tick = (frequency * USEC_PER_TICK as u64 / 1_000_000).clamp(1, u32::MAX as u64);
+    COUNT_PER_TICK.store(per_tick as u32, Ordering::Relaxed);
+    info!(
+        "lapic timer: {} Hz against {}, {} per {}us tick, tsc {} Hz",
+        frequency,
+        if hpet::is_present() { "hpet" } else { "pit" },
+        per_tick,
+        USEC_PER_TICK,
+        tsc_frequency,
+    );
+}
+
+/// Start the periodic tick of the calling cpu, `USEC_PER_TICK` long.
+///
+/// the first cpu to get here calibrates the timer, the others reuse the result
+/// since all local apic timers run off the same bus clock
+pub fn init_timer() {
+    if COUNT_PER_TICK.load(Ordering::Relaxed) == 0 {
+        calibrate();
+    }
+    lapic_write(DIVIDE_CONFIG, DIVIDE_BY_16);
+    set_periodic();
+}
+
+/// local apic timer counts per second, as calibrated at boot
+pub fn timer_frequency() -> u64 {
+    TIMER_FREQUENCY.load(Ordering::Relaxed)
+}
+
+fn set_periodic() {
+    lapic_write(LVT_TIMER, MODE_PERIODIC | TIMER_VECTOR);
+    lapic_write(INITIAL_COUNT, COUNT_PER_TICK.load(Ordering::Relaxed));
+}
+
+/// Stop the periodic tick of the calling cpu and arm one interrupt `ticks` from now.
+///
+/// uses the tsc deadline mode when available and the tsc rate is known,
+/// the one-shot count mode otherwise. must run with interrupts disabled
+pub fn enter_tickless(ticks: u64) {
+    let t = &TIMERS[cpu_id()];
+    let tsc_per_tick = TSC_PER_TICK.load(Ordering::Relaxed);
+    let now = rdtsc();
+    let ticks = if tsc_per_tick != 0 && has_tsc_deadline() {
+        lapic_write(LVT_TIMER, MODE_TSC_DEADLINE | TIMER_VECTOR);
+        unsafe { Msr::new(IA32_TSC_DEADLINE).write(now + ticks * tsc_per_tick) };
+        ticks
+    } else {
+        let per_tick = COUNT_PER_TICK.load(Ordering::Relaxed).max(1) as u64;
+        let ticks = ticks.min(u32::MAX as u64 / per_tick);
+        lapic_write(LVT_TIMER, MODE_ONESHOT | TIMER_VECTOR);
+        lapic_write(INITIAL_COUNT, (ticks * per_tick) as u32);
+        ticks
+    };
+    t.start_tsc.store(now, Ordering::Relaxed);
+    t.armed.store(ticks, Ordering::Relaxed);
+    t.oneshot.store(true, Ordering::Release);
+}
+
+/// ticks that passed since `enter_tickless`
+fn elapsed(t: &CpuTimer) -> u64 {
+    let armed = t.armed.load(Ordering::Relaxed);
+    let tsc_per_tick = TSC_PER_TICK.load(Ordering::Relaxed);
+    if tsc_per_tick != 0 {
+        ((rdtsc() - t.start_tsc.load(Ordering::Relaxed)) / tsc_per_tick).min(armed)
+    } else {
+        let per_tick = COUNT_PER_TICK.load(Ordering::Relaxed).max(1) as u64;
+        let remaining = lapic_read(CURRENT_COUNT) as u64;
+        (armed * per_tick - remaining) / per_tick
+    }
+}
+
+/// Restart the periodic tick after an early wakeup,
+/// returns the ticks that passed while it was stopped.
+pub fn exit_tickless() -> u64 {
+    let t = &TIMERS[cpu_id()];
+    if !t.oneshot.swap(false, Ordering::AcqRel) {
+        // the one-shot interrupt came first and did it already
+        return 0;
+    }
+    let e = elapsed(t);
+    set_periodic();
+    e
+}
+
+pub fn is_tickless(cpu: usize) -> bool {
+    TIMERS[cpu].oneshot.load(Ordering::Acquire)
+}
+
+/// Called on every timer interrupt, returns the ticks it stands for.
+pub fn on_timer_interrupt() -> u64 {
+    let t = &TIMERS[cpu_id()];
+    if t.oneshot.swap(false, Ordering::AcqRel) {
+        let e = elapsed(t).max(1);
+        set_periodic();
+        return e;
+    }
+    1
+}
+
+/// Wake cpu `id` from a tickless sleep so it notices new work.
+///
+/// the caller queued the work first, the fence pairs with the one in
+/// `idle_loop` so that one of the two sees what the other did
+pub fn kick(id: usize) {
+    fence(Ordering::SeqCst);
+    if is_tickless(id) {
+        send_ipi(cpu(id).apic_id(), RESCHEDULE_VECTOR);
+    }
+}
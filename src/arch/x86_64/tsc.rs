@@ -0,0 +1,98 @@
+use core::{arch::x86_64::_rdtsc, sync::atomic::{AtomicU64, Ordering}};
+
+use raw_cpuid::CpuId;
+
+use crate::time::clocksource::{ClockSource, register_clocksource};
+
+use super::{hpet, timer::pit_delay};
+use log::info;
+
+/// length of the calibration window when cpuid does not give the frequency
+const CALIBRATE_US: u64 = 50_000;
+
+/// tsc cycles per second, 0 until `init_tsc`
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+#[inline]
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// tsc cycles per second, 0 if unknown
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// the tsc ticks at a constant rate through frequency and sleep state changes
+pub fn has_invariant_tsc() -> bool {
+    CpuId::new()
+        .get_advanced_power_mgmt_info()
+        .map_or(false, |info| info.has_invariant_tsc())
+}
+
+/// Frequency reported by cpuid, from the crystal ratio of leaf 0x15
+/// or else the base frequency of leaf 0x16.
+fn cpuid_frequency() -> Option<u64> {
+    let cpuid = CpuId::new();
+    if let Some(info) = cpuid.get_tsc_info() {
+        let (num, den) = (info.numerator() as u64, info.denominator() as u64);
+        if num != 0 && den != 0 {
+            let crystal = info.nominal_frequency() as u64;
+            if crystal != 0 {
+                return Some(crystal * num / den);
+            }
+        }
+    }
+    cpuid.get_processor_frequency_info()
+        .map(|info| info.processor_base_frequency() as u64 * 1_000_000)
+        .filter(|&hz| hz != 0)
+}
+
+/// Count tsc cycles over a fixed window of the hpet, or the pit without one.
+fn calibrate() -> u64 {
+    let start = rdtsc();
+    if hpet::is_present() {
+        hpet::udelay(CALIBRATE_US);
+    } else {
+        pit_delay(CALIBRATE_US);
+    }
+    (rdtsc() - start) * 1_000_000 / CALIBRATE_US
+}
+
+/// Find the tsc frequency and use the tsc as clocksource when it is invariant.
+///
+/// must run after `init_hpet`, so the hpet can be calibrated against
+pub fn init_tsc() {
+    let (frequency, from) = match cpuid_frequency() {
+        Some(hz) => (hz, "cpuid"),
+        None => (calibrate(), if hpet::is_present() { "hpet" } else { "pit" }),
+    };
+    TSC_FREQUENCY.store(frequency, Ordering::Relaxed);
+    let invariant = has_invariant_tsc();
+    info!("{} Hz from {}, {}invariant", frequency, from, if invariant { "" } else { "not " });
+    if invariant && frequency != 0 {
+        register_clocksource(&TscClock);
+    }
+}
+
+/// The time stamp counter, cheap to read and synchronized across cpus
+/// when invariant.
+struct TscClock;
+
+impl ClockSource for TscClock {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn read(&self) -> u64 {
+        rdtsc()
+    }
+
+    fn frequency(&self) -> u64 {
+        tsc_frequency()
+    }
+
+    fn rating(&self) -> u32 {
+        300
+    }
+}
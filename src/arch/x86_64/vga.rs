@@ -1,169 +1,94 @@
-use core::fmt;
-use fmt::Write;
-use lazy_static::lazy_static;
-use spin::Mutex;
 use volatile::Volatile;
+use x86_64::instructions::port::Port;
 
+use crate::{console::{ScreenChar, TextDisplay}, memory::addr::phys_to_virt, sync::mutex::SpinNoIrqLock};
 
+pub use crate::console::Color;
 
-
-lazy_static! {
-    /// A global `Writer` instance that can be used for printing to the VGA text buffer.
-    ///
-    /// Used by the `print!` and `println!` macros.
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
-}
-
-
-/// The standard color palette in VGA text mode.
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum Color {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-    LightGray = 7,
-    DarkGray = 8,
-    LightBlue = 9,
-    LightGreen = 10,
-    LightCyan = 11,
-    LightRed = 12,
-    Pink = 13,
-    Yellow = 14,
-    White = 15,
-}
-
-/// A combination of a foreground and a background color.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(transparent)]
-struct ColorCode(u8);
-
-
-impl ColorCode {
-    /// Create a new `ColorCode` with the given foreground and background colors.
-    fn new(foreground: Color, background: Color) -> ColorCode {
-        ColorCode((background as u8) << 4 | (foreground as u8))
-    }
-}
-
-/// A screen character in the VGA text buffer, consisting of an ASCII character and a `ColorCode`.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-struct ScreenChar {
-    ascii_character: u8,
-    color_code: ColorCode,
-}
+/// The VGA text buffer, the terminals draw on it until there is a framebuffer.
+///
+/// The keyboard interrupt switches terminals, so it is locked with interrupts off.
+pub static DISPLAY: SpinNoIrqLock<VgaText> = SpinNoIrqLock::new(VgaText);
 
 /// The height of the text buffer (normally 25 lines).
 const BUFFER_HEIGHT: usize = 25;
 /// The width of the text buffer (normally 80 columns).
 const BUFFER_WIDTH: usize = 80;
+/// Physical address of the text buffer, reached through the physical memory map.
+const BUFFER_ADDRESS: usize = 0xb8000;
+
+// crt controller registers for the hardware cursor
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+const CURSOR_DISABLE: u8 = 1 << 5;
 
 #[repr(transparent)]
 struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+fn crtc_write(register: u8, value: u8) {
+    unsafe {
+        Port::new(CRTC_INDEX).write(register);
+        Port::new(CRTC_DATA).write(value);
+    }
+}
 
-
-/// A writer type that allows writing ASCII bytes and strings to an underlying `Buffer`.
-///
-/// Wraps lines at `BUFFER_WIDTH`. Supports newline characters and implements the
-/// `core::fmt::Write` trait.
-pub struct Writer {
-    column_position: usize,
-    color_code: ColorCode,
-    buffer: &'static mut Buffer,
+fn crtc_read(register: u8) -> u8 {
+    unsafe {
+        Port::new(CRTC_INDEX).write(register);
+        Port::new(CRTC_DATA).read()
+    }
 }
 
-impl Writer {
-    /// Writes an ASCII byte to the buffer.
-    ///
-    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character.
-    pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
+/// The 80x25 VGA text buffer, with the cursor of the crt controller.
+pub struct VgaText;
 
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
+impl VgaText {
+    /// The text buffer; only touched with `DISPLAY` locked.
+    fn buffer(&self) -> &'static mut Buffer {
+        unsafe { &mut *(phys_to_virt(BUFFER_ADDRESS) as *mut Buffer) }
+    }
+}
 
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
-        }
+impl TextDisplay for VgaText {
+    fn size(&self) -> (usize, usize) {
+        (BUFFER_HEIGHT, BUFFER_WIDTH)
     }
 
-    /// Writes the given ASCII string to the buffer.
-    ///
-    /// Wraps lines at `BUFFER_WIDTH`. Supports the `\n` newline character. Does **not**
-    /// support strings with non-ASCII characters, since they can't be printed in the VGA text
-    /// mode.
-    fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
-            }
-        }
+    fn draw(&mut self, row: usize, col: usize, c: ScreenChar) {
+        self.buffer().chars[row][col].write(c);
+    }
+
+    fn read(&self, row: usize, col: usize) -> ScreenChar {
+        self.buffer().chars[row][col].read()
     }
 
-    /// Shifts all lines one line up and clears the last row.
-    fn new_line(&mut self) {
+    fn scroll_up(&mut self) {
+        let buffer = self.buffer();
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+                let character = buffer.chars[row][col].read();
+                buffer.chars[row - 1][col].write(character);
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
     }
 
-    /// Clears a row by overwriting it with blank characters.
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        let start = crtc_read(CRTC_CURSOR_START);
+        let (row, col) = match position {
+            Some(position) => position,
+            None => {
+                crtc_write(CRTC_CURSOR_START, start | CURSOR_DISABLE);
+                return;
+            }
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
-        }
-    }
-}
-
-impl fmt::Write for Writer {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        self.write_string(s);
-        Ok(())
+        let position = row * BUFFER_WIDTH + col;
+        crtc_write(CRTC_CURSOR_HIGH, (position >> 8) as u8);
+        crtc_write(CRTC_CURSOR_LOW, position as u8);
+        crtc_write(CRTC_CURSOR_START, start & !CURSOR_DISABLE);
     }
 }
-
-pub fn _print(args: fmt::Arguments) {
-    //use core::fmt::Write;
-    //use x86_64::instructions::interrupts;   // new
-
-    //interrupts::without_interrupts(|| {     // new
-    WRITER.lock().write_fmt(args).unwrap();
-        //WRITER.lock().write_fmt(args).unwrap();
-    //});
-}
-
@@ -0,0 +1,781 @@
+//! Text console shared by the vga text mode and the framebuffer: a grid of
+//! character cells with scrollback, a cursor and ANSI escape handling, drawn
+//! by the virtual terminal that is shown.
+
+use alloc::sync::Arc;
+use core::{fmt, ops::Range, str};
+
+use crate::{arch::{cpu::try_cpu_id, serial, vga}, consts::LOG_VT, drivers::{framebuffer, tty::Tty}, process::current};
+
+pub mod vt;
+
+/// The standard color palette in VGA text mode.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+/// The ANSI colors 0 to 7 (black, red, green, yellow, blue, magenta, cyan, white),
+/// their bright variants have the intensity bit set.
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black,
+    Color::Red,
+    Color::Green,
+    Color::Brown,
+    Color::Blue,
+    Color::Magenta,
+    Color::Cyan,
+    Color::LightGray,
+];
+const INTENSITY: u8 = 8;
+
+const DEFAULT_FOREGROUND: Color = Color::Yellow;
+const DEFAULT_BACKGROUND: Color = Color::Black;
+
+/// A combination of a foreground and a background color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ColorCode(u8);
+
+impl ColorCode {
+    /// Create a new `ColorCode` with the given foreground and background colors.
+    pub const fn new(foreground: Color, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+
+    /// palette index of the foreground
+    pub fn foreground(self) -> usize {
+        (self.0 & 0xf) as usize
+    }
+
+    /// palette index of the background
+    pub fn background(self) -> usize {
+        (self.0 >> 4) as usize
+    }
+}
+
+/// A screen character, consisting of a code page 437 character and a `ColorCode`,
+/// laid out like the cells of the VGA text buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ScreenChar {
+    pub ascii_character: u8,
+    pub color_code: ColorCode,
+}
+
+impl ScreenChar {
+    pub const BLANK: ScreenChar = ScreenChar {
+        ascii_character: b' ',
+        color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+    };
+
+    fn is_blank(&self) -> bool {
+        self.ascii_character == b' ' || self.ascii_character == 0
+    }
+}
+
+/// Lines kept in memory, the screen and the scrollback above it.
+const HISTORY_LINES: usize = 256;
+/// Displays with more columns or rows only use this many.
+pub const MAX_COLUMNS: usize = 128;
+pub const MAX_ROWS: usize = 64;
+
+/// moves the cursor one column left, without erasing
+const BACKSPACE: char = '\u{8}';
+const ESCAPE: char = '\u{1b}';
+const TAB_WIDTH: usize = 8;
+/// shown for characters code page 437 has no glyph for here
+const UNKNOWN_CHARACTER: u8 = 0xfe;
+
+/// Parameters of a control sequence beyond this are dropped.
+const MAX_PARAMS: usize = 8;
+
+/// A grid of character cells a `Writer` draws on.
+pub trait TextDisplay {
+    /// rows and columns
+    fn size(&self) -> (usize, usize);
+    fn draw(&mut self, row: usize, col: usize, c: ScreenChar);
+    /// the cell at `row`, `col`, to take over what is already shown
+    fn read(&self, row: usize, col: usize) -> ScreenChar;
+    /// Moves every row up by one, the last row is drawn again by the caller.
+    fn scroll_up(&mut self);
+    /// Shows the cursor at (row, column), or hides it.
+    fn set_cursor(&mut self, position: Option<(usize, usize)>);
+}
+
+/// Where the writer is in an escape sequence.
+#[derive(Debug, Clone, Copy)]
+enum EscapeState {
+    Normal,
+    /// after ESC
+    Escape,
+    /// after ESC [, collecting numeric parameters; `private` after a leading '?'
+    Csi { params: [u16; MAX_PARAMS], count: usize, private: bool },
+}
+
+/// Character attributes set by SGR sequences.
+#[derive(Debug, Clone, Copy)]
+struct Attributes {
+    foreground: u8,
+    background: u8,
+    bold: bool,
+    reverse: bool,
+}
+
+impl Attributes {
+    const DEFAULT: Attributes = Attributes {
+        foreground: DEFAULT_FOREGROUND as u8,
+        background: DEFAULT_BACKGROUND as u8,
+        bold: false,
+        reverse: false,
+    };
+
+    fn color_code(&self) -> ColorCode {
+        let foreground = if self.bold { self.foreground | INTENSITY } else { self.foreground };
+        let (foreground, background) = if self.reverse {
+            (self.background, foreground)
+        } else {
+            (foreground, self.background)
+        };
+        ColorCode(background << 4 | foreground)
+    }
+}
+
+/// A writer type that allows writing strings to a `TextDisplay`.
+///
+/// Wraps lines at the width of the display, keeps the lines that scroll off the
+/// top for `scroll_view`, moves the cursor, and understands a subset of the
+/// ANSI/VT100 escape sequences. Implements the `core::fmt::Write` trait.
+pub struct Writer<D> {
+    display: D,
+    /// size of the display, known once it is attached
+    rows: usize,
+    columns: usize,
+    attached: bool,
+    /// ring of lines, the screen is the `rows` lines from `top`
+    lines: [[ScreenChar; MAX_COLUMNS]; HISTORY_LINES],
+    top: usize,
+    /// lines above `top` that can be scrolled back to
+    history: usize,
+    /// how far the view is scrolled back, 0 shows the screen
+    view_offset: usize,
+    row: usize,
+    column_position: usize,
+    /// cursor position saved by `ESC 7` and `CSI s`
+    saved_position: (usize, usize),
+    cursor_visible: bool,
+    attributes: Attributes,
+    color_code: ColorCode,
+    escape: EscapeState,
+}
+
+impl<D> Writer<D> {
+    pub const fn new(display: D) -> Writer<D> {
+        Writer {
+            display,
+            rows: 0,
+            columns: 0,
+            attached: false,
+            lines: [[ScreenChar::BLANK; MAX_COLUMNS]; HISTORY_LINES],
+            top: 0,
+            history: 0,
+            view_offset: 0,
+            row: 0,
+            column_position: 0,
+            saved_position: (0, 0),
+            cursor_visible: true,
+            attributes: Attributes::DEFAULT,
+            color_code: ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND),
+            escape: EscapeState::Normal,
+        }
+    }
+}
+
+impl<D: TextDisplay> Writer<D> {
+    /// Starts writing to the display, taking over what is on it so that it
+    /// scrolls up like our own output. Output continues below the last used row.
+    pub fn attach(&mut self) {
+        self.start();
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                self.lines[row][col] = self.display.read(row, col);
+            }
+        }
+        let used = (0..self.rows)
+            .rev()
+            .find(|&row| !self.lines[row][..self.columns].iter().all(ScreenChar::is_blank))
+            .map_or(0, |row| row + 1);
+        self.row = used.saturating_sub(1);
+        self.column_position = 0;
+        if used != 0 {
+            self.new_line();
+        }
+        if self.columns != 0 {
+            self.update_cursor();
+        }
+    }
+
+    /// Takes the size of the display, with an empty screen and no history.
+    fn start(&mut self) {
+        let (rows, columns) = self.display.size();
+        self.rows = rows.min(MAX_ROWS);
+        self.columns = columns.min(MAX_COLUMNS);
+        self.top = 0;
+        self.history = 0;
+        self.view_offset = 0;
+        self.row = 0;
+        self.column_position = 0;
+        self.attached = true;
+    }
+
+    /// Draws the screen and the cursor again, after the display showed
+    /// something else. A writer that was never used starts out empty.
+    pub fn refresh(&mut self) {
+        if !self.attached {
+            self.start();
+        }
+        if self.rows != 0 && self.columns != 0 {
+            self.redraw();
+            self.update_cursor();
+        }
+    }
+
+    /// Follows a new size of the display, keeping the written lines and the
+    /// cursor on screen, and draws everything again.
+    pub fn resize(&mut self) {
+        if !self.attached {
+            return;
+        }
+        let (rows, columns) = self.display.size();
+        let (rows, columns) = (rows.min(MAX_ROWS), columns.min(MAX_COLUMNS));
+        if columns > self.columns {
+            for line in self.lines.iter_mut() {
+                for c in &mut line[self.columns..columns] {
+                    *c = ScreenChar::BLANK;
+                }
+            }
+        }
+        if rows != 0 && self.row >= rows {
+            // the top lines go to the history
+            let lost = self.row + 1 - rows;
+            self.top = (self.top + lost) % HISTORY_LINES;
+            self.history += lost;
+            self.row = rows - 1;
+        } else {
+            // lines coming into view below were history that wrapped around
+            for row in self.rows..rows {
+                let index = self.line_index(row);
+                self.lines[index] = [ScreenChar::BLANK; MAX_COLUMNS];
+            }
+        }
+        self.rows = rows;
+        self.columns = columns;
+        self.history = self.history.min(HISTORY_LINES - rows);
+        self.view_offset = 0;
+        self.column_position = self.column_position.min(columns);
+        self.refresh();
+    }
+
+    /// Writes a byte to the display.
+    ///
+    /// Wraps lines at the display width. Control characters and escape sequences
+    /// are interpreted, the cursor is moved by `write_str`.
+    pub fn write_byte(&mut self, byte: u8) {
+        if self.ready() {
+            self.write_char_raw(byte as char);
+        }
+    }
+
+    /// Attaches the display on first use, false if it has no room for text.
+    fn ready(&mut self) -> bool {
+        if !self.attached {
+            self.attach();
+        }
+        self.rows != 0 && self.columns != 0
+    }
+
+    fn write_char_raw(&mut self, c: char) {
+        match self.escape {
+            EscapeState::Normal => self.write_plain(c),
+            EscapeState::Escape => self.escape_char(c),
+            EscapeState::Csi { .. } => self.csi_char(c),
+        }
+    }
+
+    fn write_plain(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column_position = 0,
+            '\t' => self.column_position = ((self.column_position / TAB_WIDTH + 1) * TAB_WIDTH).min(self.columns - 1),
+            BACKSPACE => self.column_position = self.column_position.min(self.columns - 1).saturating_sub(1),
+            ESCAPE => self.escape = EscapeState::Escape,
+            // printable ASCII
+            ' '..='~' => self.put(c as u8),
+            // other control characters do nothing
+            c if c.is_control() => {}
+            // not part of printable ASCII range
+            _ => self.put(UNKNOWN_CHARACTER),
+        }
+    }
+
+    /// Puts a character at the cursor and advances it, wrapping first if the
+    /// previous character filled the line.
+    fn put(&mut self, byte: u8) {
+        if self.column_position >= self.columns {
+            self.new_line();
+        }
+        let (row, col) = (self.row, self.column_position);
+        self.set(row, col, ScreenChar {
+            ascii_character: byte,
+            color_code: self.color_code,
+        });
+        self.column_position += 1;
+    }
+
+    /// ring index of screen row `row`
+    fn line_index(&self, row: usize) -> usize {
+        (self.top + row) % HISTORY_LINES
+    }
+
+    fn set(&mut self, row: usize, col: usize, c: ScreenChar) {
+        let index = self.line_index(row);
+        self.lines[index][col] = c;
+        if self.view_offset == 0 {
+            self.display.draw(row, col, c);
+        }
+    }
+
+    /// Moves to the start of the next line, scrolling the screen up at the bottom.
+    fn new_line(&mut self) {
+        self.column_position = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        self.top = (self.top + 1) % HISTORY_LINES;
+        self.history = (self.history + 1).min(HISTORY_LINES - self.rows);
+        if self.view_offset == 0 {
+            self.display.scroll_up();
+        }
+        self.clear_row(self.rows - 1, 0..self.columns);
+    }
+
+    /// Clears part of a row with the current background.
+    fn clear_row(&mut self, row: usize, cols: Range<usize>) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in cols {
+            self.set(row, col, blank);
+        }
+    }
+
+    /// Draws the viewed lines again.
+    fn redraw(&mut self) {
+        for row in 0..self.rows {
+            let index = (self.top + HISTORY_LINES - self.view_offset + row) % HISTORY_LINES;
+            for col in 0..self.columns {
+                self.display.draw(row, col, self.lines[index][col]);
+            }
+        }
+    }
+
+    /// Moves the display's cursor to the writer's position, hiding it while
+    /// the view is scrolled back or a program turned it off.
+    fn update_cursor(&mut self) {
+        let position = if self.cursor_visible && self.view_offset == 0 {
+            Some((self.row, self.column_position.min(self.columns - 1)))
+        } else {
+            None
+        };
+        self.display.set_cursor(position);
+    }
+
+    /// Scrolls the view `lines` further back into the history, or forward for
+    /// negative counts. Writing scrolls back to the screen.
+    pub fn scroll_view(&mut self, lines: isize) {
+        if !self.attached {
+            return;
+        }
+        let offset = (self.view_offset as isize + lines).max(0) as usize;
+        let offset = offset.min(self.history);
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+            self.update_cursor();
+        }
+    }
+
+    /// Scrolls back by half a screen, for Shift+PgUp.
+    pub fn scroll_back(&mut self) {
+        self.scroll_view(self.rows as isize / 2);
+    }
+
+    /// Scrolls forward by half a screen, for Shift+PgDn.
+    pub fn scroll_forward(&mut self) {
+        self.scroll_view(-(self.rows as isize / 2));
+    }
+
+    fn escape_char(&mut self, c: char) {
+        self.escape = EscapeState::Normal;
+        match c {
+            '[' => self.escape = EscapeState::Csi { params: [0; MAX_PARAMS], count: 0, private: false },
+            '7' => self.saved_position = (self.row, self.column_position),
+            '8' => self.restore_position(),
+            'c' => self.reset(),
+            _ => {}
+        }
+    }
+
+    fn csi_char(&mut self, c: char) {
+        let (mut params, mut count, mut private) = match self.escape {
+            EscapeState::Csi { params, count, private } => (params, count, private),
+            _ => return,
+        };
+        match c {
+            '0'..='9' => {
+                if let Some(p) = params.get_mut(count) {
+                    *p = p.saturating_mul(10).saturating_add(c as u16 - '0' as u16);
+                }
+            }
+            ';' => count += 1,
+            '?' => private = true,
+            // a final byte ends the sequence
+            '@'..='~' => {
+                self.escape = EscapeState::Normal;
+                let len = (count + 1).min(MAX_PARAMS);
+                self.csi(c, &params[..len], private);
+                return;
+            }
+            // intermediate bytes are not used by anything we support
+            _ => {}
+        }
+        self.escape = EscapeState::Csi { params, count, private };
+    }
+
+    /// Runs the control sequence ending in `command`.
+    fn csi(&mut self, command: char, params: &[u16], private: bool) {
+        // missing and zero counts mean 1
+        let count = params[0].max(1) as usize;
+        let (row, col) = (self.row, self.column_position.min(self.columns - 1));
+        match command {
+            'A' => self.move_to(row.saturating_sub(count), col),
+            'B' => self.move_to(row + count, col),
+            'C' => self.move_to(row, col + count),
+            'D' => self.move_to(row, col.saturating_sub(count)),
+            'E' => self.move_to(row + count, 0),
+            'F' => self.move_to(row.saturating_sub(count), 0),
+            'G' => self.move_to(row, count - 1),
+            'd' => self.move_to(count - 1, col),
+            'H' | 'f' => {
+                let col = params.get(1).map_or(1, |&c| c.max(1) as usize);
+                self.move_to(count - 1, col - 1);
+            }
+            'J' => self.erase_display(params[0]),
+            'K' => self.erase_line(params[0]),
+            'm' => self.select_graphic_rendition(params),
+            's' => self.saved_position = (self.row, self.column_position),
+            'u' => self.restore_position(),
+            'h' | 'l' if private && params[0] == 25 => self.cursor_visible = command == 'h',
+            _ => {}
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.row = row.min(self.rows - 1);
+        self.column_position = col.min(self.columns - 1);
+    }
+
+    fn restore_position(&mut self) {
+        let (row, col) = self.saved_position;
+        self.move_to(row, col);
+    }
+
+    /// `CSI J`: 0 erases to the end of the screen, 1 from its start, 2 all of it
+    /// and 3 the scrollback as well.
+    fn erase_display(&mut self, mode: u16) {
+        let (row, col) = (self.row, self.column_position.min(self.columns - 1));
+        match mode {
+            0 => {
+                self.clear_row(row, col..self.columns);
+                for r in row + 1..self.rows {
+                    self.clear_row(r, 0..self.columns);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.clear_row(r, 0..self.columns);
+                }
+                self.clear_row(row, 0..col + 1);
+            }
+            2 | 3 => {
+                for r in 0..self.rows {
+                    self.clear_row(r, 0..self.columns);
+                }
+                if mode == 3 {
+                    self.history = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// `CSI K`: 0 erases to the end of the line, 1 from its start, 2 all of it.
+    fn erase_line(&mut self, mode: u16) {
+        let (row, col) = (self.row, self.column_position.min(self.columns - 1));
+        match mode {
+            0 => self.clear_row(row, col..self.columns),
+            1 => self.clear_row(row, 0..col + 1),
+            2 => self.clear_row(row, 0..self.columns),
+            _ => {}
+        }
+    }
+
+    /// `CSI m`, colors map onto the 16 VGA colors.
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let attributes = &mut self.attributes;
+        for &p in params {
+            match p {
+                0 => *attributes = Attributes::DEFAULT,
+                1 => attributes.bold = true,
+                22 => attributes.bold = false,
+                7 => attributes.reverse = true,
+                27 => attributes.reverse = false,
+                30..=37 => attributes.foreground = ANSI_COLORS[p as usize - 30] as u8,
+                39 => attributes.foreground = DEFAULT_FOREGROUND as u8,
+                40..=47 => attributes.background = ANSI_COLORS[p as usize - 40] as u8,
+                49 => attributes.background = DEFAULT_BACKGROUND as u8,
+                90..=97 => attributes.foreground = ANSI_COLORS[p as usize - 90] as u8 | INTENSITY,
+                100..=107 => attributes.background = ANSI_COLORS[p as usize - 100] as u8 | INTENSITY,
+                _ => {}
+            }
+        }
+        self.color_code = attributes.color_code();
+    }
+
+    /// `ESC c`: default attributes, a visible cursor and a clear screen.
+    fn reset(&mut self) {
+        self.attributes = Attributes::DEFAULT;
+        self.color_code = self.attributes.color_code();
+        self.cursor_visible = true;
+        self.erase_display(2);
+        self.move_to(0, 0);
+    }
+
+    /// Writes UTF-8 encoded `bytes`, invalid sequences show as a square.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            match str::from_utf8(rest) {
+                Ok(s) => {
+                    self.write_string(s);
+                    break;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    self.write_string(str::from_utf8(valid).unwrap());
+                    self.write_string(char::REPLACEMENT_CHARACTER.encode_utf8(&mut [0; 4]));
+                    rest = &invalid[e.error_len().unwrap_or(invalid.len())..];
+                }
+            }
+        }
+    }
+
+    /// Writes the given string to the display.
+    ///
+    /// Wraps lines at the display width. Characters outside of printable ASCII,
+    /// which have no glyph in the console font here, show as a square.
+    fn write_string(&mut self, s: &str) {
+        if !self.ready() {
+            return;
+        }
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+        for c in s.chars() {
+            self.write_char_raw(c);
+        }
+        self.update_cursor();
+    }
+}
+
+impl<D: TextDisplay> fmt::Write for Writer<D> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+/// Runs `f` on the screen: the framebuffer once there is one, the vga text
+/// buffer before.
+pub fn with_display<R>(f: impl FnOnce(&mut dyn TextDisplay) -> R) -> R {
+    if framebuffer::is_active() {
+        f(&mut *framebuffer::DISPLAY.lock())
+    } else {
+        f(&mut *vga::DISPLAY.lock())
+    }
+}
+
+/// controlling terminal of the calling process
+fn ctty() -> Option<Arc<Tty>> {
+    try_cpu_id()?;
+    current()?.ctty()
+}
+
+/// `print!` writes to the controlling terminal, without one to the log
+/// terminal and COM1.
+pub fn _print(args: fmt::Arguments) {
+    match ctty() {
+        Some(tty) => tty.write_fmt(args),
+        None => {
+            vt::VTS[LOG_VT].write_fmt(args);
+            serial::_print(args);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::String};
+
+    use super::*;
+
+    const ROWS: usize = 4;
+    const COLUMNS: usize = 10;
+
+    /// A small display that keeps what is drawn on it.
+    struct MockDisplay {
+        cells: [[ScreenChar; COLUMNS]; ROWS],
+        cursor: Option<(usize, usize)>,
+    }
+
+    impl TextDisplay for MockDisplay {
+        fn size(&self) -> (usize, usize) {
+            (ROWS, COLUMNS)
+        }
+
+        fn draw(&mut self, row: usize, col: usize, c: ScreenChar) {
+            self.cells[row][col] = c;
+        }
+
+        fn read(&self, row: usize, col: usize) -> ScreenChar {
+            self.cells[row][col]
+        }
+
+        fn scroll_up(&mut self) {
+            self.cells.copy_within(1.., 0);
+        }
+
+        fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+            self.cursor = position;
+        }
+    }
+
+    fn writer() -> Writer<MockDisplay> {
+        Writer::new(MockDisplay { cells: [[ScreenChar::BLANK; COLUMNS]; ROWS], cursor: None })
+    }
+
+    /// row `row` of the display, without trailing blanks
+    fn text(writer: &Writer<MockDisplay>, row: usize) -> String {
+        let line: String = writer.display.cells[row].iter().map(|c| c.ascii_character as char).collect();
+        String::from(line.trim_end())
+    }
+
+    #[test_case]
+    fn cursor_moves() {
+        let mut w = writer();
+        w.write_bytes(b"\x1b[3;5H");
+        assert_eq!(w.display.cursor, Some((2, 4)));
+        w.write_bytes(b"\x1b[A\x1b[2C");
+        assert_eq!(w.display.cursor, Some((1, 6)));
+        // counts stop at the edges, zero and missing counts mean 1
+        w.write_bytes(b"\x1b[99D\x1b[0B");
+        assert_eq!(w.display.cursor, Some((2, 0)));
+        w.write_bytes(b"\x1b[99999999B\x1b[99C");
+        assert_eq!(w.display.cursor, Some((ROWS - 1, COLUMNS - 1)));
+        w.write_bytes(b"\x1b[2F\x1b[4G");
+        assert_eq!(w.display.cursor, Some((1, 3)));
+        w.write_bytes(b"\x1b[s\x1b[H\x1b[u");
+        assert_eq!(w.display.cursor, Some((1, 3)));
+        w.write_bytes(b"\x1b[?25l");
+        assert_eq!(w.display.cursor, None);
+        w.write_bytes(b"\x1b[?25h\x1b[1d");
+        assert_eq!(w.display.cursor, Some((0, 3)));
+    }
+
+    #[test_case]
+    fn erase_modes() {
+        let mut w = writer();
+        w.write_bytes(b"abcdef\r\nghijkl\r\nmnopqr");
+        w.write_bytes(b"\x1b[2;3H\x1b[K");
+        assert_eq!(text(&w, 1), "gh");
+        w.write_bytes(b"\x1b[1;3H\x1b[1K");
+        assert_eq!(text(&w, 0), "   def");
+        w.write_bytes(b"\x1b[3;2H\x1b[2K");
+        assert_eq!(text(&w, 2), "");
+        w.write_bytes(b"\x1b[3;1Hxyz\x1b[1;5H\x1b[J");
+        assert_eq!((text(&w, 0).as_str(), text(&w, 1).as_str(), text(&w, 2).as_str()), ("   d", "", ""));
+        w.write_bytes(b"\x1b[2;1Hxyz\x1b[2;2H\x1b[1J");
+        assert_eq!((text(&w, 0).as_str(), text(&w, 1).as_str()), ("", "  z"));
+        w.write_bytes(b"\x1b[2J");
+        assert!((0..ROWS).all(|row| text(&w, row).is_empty()));
+    }
+
+    #[test_case]
+    fn select_graphic_rendition() {
+        let mut w = writer();
+        w.write_bytes(b"\x1b[1;31;44ma\x1b[7mb\x1b[27;22;39mc\x1b[0md\x1b[92;103me");
+        let color = |col: usize| w.display.cells[0][col].color_code;
+        // bold brightens the foreground, reverse swaps it with the background
+        assert_eq!(color(0), ColorCode::new(Color::LightRed, Color::Blue));
+        assert_eq!(color(1), ColorCode::new(Color::Blue, Color::LightRed));
+        assert_eq!(color(2), ColorCode::new(DEFAULT_FOREGROUND, Color::Blue));
+        assert_eq!(color(3), ColorCode::new(DEFAULT_FOREGROUND, DEFAULT_BACKGROUND));
+        assert_eq!(color(4), ColorCode::new(Color::LightGreen, Color::Yellow));
+        // erasing uses the current background
+        w.write_bytes(b"\x1b[0;41m\x1b[2K");
+        assert_eq!(w.display.cells[0][0].color_code, ColorCode::new(DEFAULT_FOREGROUND, Color::Red));
+    }
+
+    #[test_case]
+    fn scrollback_wraps_around() {
+        let mut w = writer();
+        let lines = HISTORY_LINES + 20;
+        for i in 0..lines {
+            w.write_bytes(format!("{:03}\n", i).as_bytes());
+        }
+        // the last line written is above the empty cursor row
+        assert_eq!(text(&w, ROWS - 2), format!("{:03}", lines - 1));
+        assert_eq!(w.history, HISTORY_LINES - ROWS);
+        // back to the oldest line the ring still has
+        w.scroll_view(isize::MAX / 2);
+        assert_eq!(w.display.cursor, None);
+        assert_eq!(text(&w, 0), format!("{:03}", lines - (ROWS - 1) - (HISTORY_LINES - ROWS)));
+        w.scroll_view(-1);
+        assert_eq!(text(&w, 0), format!("{:03}", lines - (ROWS - 1) - (HISTORY_LINES - ROWS) + 1));
+        // writing scrolls down to the screen again
+        w.write_bytes(b"x");
+        assert_eq!(text(&w, ROWS - 1), "x");
+        assert_eq!(text(&w, 0), format!("{:03}", lines - (ROWS - 1)));
+        assert_eq!(w.display.cursor, Some((ROWS - 1, 1)));
+    }
+}
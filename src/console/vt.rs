@@ -0,0 +1,265 @@
+//! Virtual terminals: `VT_COUNT` consoles sharing the screen, one shown at a
+//! time and picked with Alt+F1 to Alt+F6. Each has its own lines and cursor,
+//! and is the driver of a `Tty` that gets the keys typed on it; tty1 is `VTS[0]`.
+
+use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
+use core::{fmt::{self, Write}, sync::atomic::{AtomicU8, AtomicUsize, Ordering}};
+use lazy_static::lazy_static;
+use log::info;
+use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+use crate::{consts::{LOG_VT, VT_COUNT}, drivers::{input::{self, EventKind, InputEvent}, tty::{Tty, TtyDriver, register_tty, termios::WinSize}}, sync::mutex::SpinNoIrqLock};
+
+use super::{MAX_COLUMNS, MAX_ROWS, ScreenChar, TextDisplay, Writer, with_display};
+
+// modifier keys held down, `pc_keyboard` keeps its own copy private
+const MOD_SHIFT_LEFT: u8 = 1 << 0;
+const MOD_SHIFT_RIGHT: u8 = 1 << 1;
+const MOD_ALT_LEFT: u8 = 1 << 2;
+const MOD_ALT_RIGHT: u8 = 1 << 3;
+const MOD_SHIFT: u8 = MOD_SHIFT_LEFT | MOD_SHIFT_RIGHT;
+const MOD_ALT: u8 = MOD_ALT_LEFT | MOD_ALT_RIGHT;
+
+/// index of the terminal on screen, only changed with the display locked
+static ACTIVE: AtomicUsize = AtomicUsize::new(LOG_VT);
+/// `MOD_*` bits
+static MODIFIERS: AtomicU8 = AtomicU8::new(0);
+
+/// The screen as one terminal sees it, drawn on only while that terminal is shown.
+pub struct VtDisplay {
+    index: usize,
+}
+
+impl VtDisplay {
+    fn shown(&self, f: impl FnOnce(&mut dyn TextDisplay)) {
+        with_display(|display| {
+            if ACTIVE.load(Ordering::Relaxed) == self.index {
+                f(display);
+            }
+        });
+    }
+}
+
+impl TextDisplay for VtDisplay {
+    fn size(&self) -> (usize, usize) {
+        with_display(|display| display.size())
+    }
+
+    fn draw(&mut self, row: usize, col: usize, c: ScreenChar) {
+        self.shown(|display| display.draw(row, col, c));
+    }
+
+    /// what is on screen for the shown terminal, the others start out empty
+    fn read(&self, row: usize, col: usize) -> ScreenChar {
+        let mut c = ScreenChar::BLANK;
+        self.shown(|display| c = display.read(row, col));
+        c
+    }
+
+    fn scroll_up(&mut self) {
+        self.shown(|display| display.scroll_up());
+    }
+
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        self.shown(|display| display.set_cursor(position));
+    }
+}
+
+pub struct VirtualTerminal {
+    writer: SpinNoIrqLock<Writer<VtDisplay>>,
+}
+
+impl VirtualTerminal {
+    const fn new(index: usize) -> Self {
+        VirtualTerminal {
+            writer: SpinNoIrqLock::new(Writer::new(VtDisplay { index })),
+        }
+    }
+
+    pub fn write_str(&self, s: &str) {
+        let _ = self.writer.lock().write_str(s);
+    }
+
+    /// Formats `args` onto the terminal, locking it for each piece so that
+    /// a `Debug` impl may print too.
+    pub fn write_fmt(&self, args: fmt::Arguments) {
+        let _ = Output(self).write_fmt(args);
+    }
+}
+
+struct Output<'a>(&'a VirtualTerminal);
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s);
+        Ok(())
+    }
+}
+
+/// the terminals, kernel messages go to `LOG_VT`
+pub static VTS: [VirtualTerminal; VT_COUNT] = [
+    VirtualTerminal::new(0),
+    VirtualTerminal::new(1),
+    VirtualTerminal::new(2),
+    VirtualTerminal::new(3),
+    VirtualTerminal::new(4),
+    VirtualTerminal::new(5),
+];
+
+/// `TtyDriver` of a terminal, its output goes to its `Writer`.
+struct VtDriver {
+    index: usize,
+}
+
+impl TtyDriver for VtDriver {
+    fn write(&self, bytes: &[u8]) {
+        VTS[self.index].writer.lock().write_bytes(bytes);
+    }
+}
+
+lazy_static! {
+    /// `tty1` and up, the `Tty` of each terminal
+    static ref TTYS: Vec<Arc<Tty>> = (0..VT_COUNT)
+        .map(|index| register_tty(format!("tty{}", index + 1), Box::new(VtDriver { index })))
+        .collect();
+}
+
+/// the `Tty` of terminal `index`
+pub fn tty(index: usize) -> Arc<Tty> {
+    TTYS[index].clone()
+}
+
+/// index of the terminal on screen
+pub fn active() -> usize {
+    ACTIVE.load(Ordering::Relaxed)
+}
+
+/// Show terminal `index`, drawing its screen again.
+pub fn switch_to(index: usize) {
+    if index >= VT_COUNT || index == active() {
+        return;
+    }
+    // the old terminal stops drawing before the new one redraws
+    with_display(|_| ACTIVE.store(index, Ordering::Relaxed));
+    VTS[index].writer.lock().refresh();
+}
+
+/// Have every terminal follow a new size of the screen, as when the
+/// framebuffer takes over from vga text mode.
+pub fn resize_all() {
+    for vt in VTS.iter() {
+        vt.writer.lock().resize();
+    }
+    set_winsizes();
+}
+
+/// Give the ttys the size of the screen, as much of it as a `Writer` uses.
+fn set_winsizes() {
+    let (rows, cols) = with_display(|display| display.size());
+    let winsize = WinSize {
+        rows: rows.min(MAX_ROWS) as u16,
+        cols: cols.min(MAX_COLUMNS) as u16,
+        ..WinSize::default()
+    };
+    for tty in TTYS.iter() {
+        tty.set_winsize(winsize);
+    }
+}
+
+fn track_modifiers(code: KeyCode, state: KeyState) {
+    let bit = match code {
+        KeyCode::ShiftLeft => MOD_SHIFT_LEFT,
+        KeyCode::ShiftRight => MOD_SHIFT_RIGHT,
+        KeyCode::AltLeft => MOD_ALT_LEFT,
+        KeyCode::AltRight => MOD_ALT_RIGHT,
+        _ => return,
+    };
+    match state {
+        KeyState::Down => MODIFIERS.fetch_or(bit, Ordering::Relaxed),
+        KeyState::Up => MODIFIERS.fetch_and(!bit, Ordering::Relaxed),
+    };
+}
+
+/// terminal picked by Alt and a function key
+fn function_key_vt(code: KeyCode) -> Option<usize> {
+    let index = match code {
+        KeyCode::F1 => 0,
+        KeyCode::F2 => 1,
+        KeyCode::F3 => 2,
+        KeyCode::F4 => 3,
+        KeyCode::F5 => 4,
+        KeyCode::F6 => 5,
+        KeyCode::F7 => 6,
+        KeyCode::F8 => 7,
+        KeyCode::F9 => 8,
+        KeyCode::F10 => 9,
+        KeyCode::F11 => 10,
+        KeyCode::F12 => 11,
+        _ => return None,
+    };
+    Some(index).filter(|&index| index < VT_COUNT)
+}
+
+/// what a vt100 sends for keys without a character, and the few we send
+/// something else for than `pc_keyboard`
+fn key_sequence(code: KeyCode) -> Option<&'static [u8]> {
+    Some(match code {
+        KeyCode::Enter | KeyCode::NumpadEnter => b"\r",
+        KeyCode::Backspace => b"\x7f",
+        KeyCode::ArrowUp => b"\x1b[A",
+        KeyCode::ArrowDown => b"\x1b[B",
+        KeyCode::ArrowRight => b"\x1b[C",
+        KeyCode::ArrowLeft => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::Insert => b"\x1b[2~",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        _ => return None,
+    })
+}
+
+/// Key events of every keyboard: Alt+Fn switches terminals, Shift+PgUp/PgDn
+/// scrolls the shown one, other presses are typed into it.
+fn handle_event(event: &InputEvent) {
+    let (code, state, key) = match &event.kind {
+        EventKind::Key { code, state, key } => (*code, *state, key),
+        _ => return,
+    };
+    track_modifiers(code, state);
+    if state != KeyState::Down {
+        return;
+    }
+    let modifiers = MODIFIERS.load(Ordering::Relaxed);
+    if modifiers & MOD_ALT != 0 {
+        if let Some(index) = function_key_vt(code) {
+            switch_to(index);
+            return;
+        }
+    }
+    let index = active();
+    if modifiers & MOD_SHIFT != 0 {
+        match code {
+            KeyCode::PageUp => return VTS[index].writer.lock().scroll_back(),
+            KeyCode::PageDown => return VTS[index].writer.lock().scroll_forward(),
+            _ => {}
+        }
+    }
+    let mut utf8 = [0; 4];
+    let bytes = match (key_sequence(code), key) {
+        (Some(sequence), _) => sequence,
+        (None, Some(DecodedKey::Unicode(c))) => c.encode_utf8(&mut utf8).as_bytes(),
+        _ => return,
+    };
+    TTYS[index].receive(bytes);
+}
+
+/// Create the ttys and take the keyboard input, the log terminal is shown
+/// until another is picked.
+pub fn init_vt() {
+    lazy_static::initialize(&TTYS);
+    set_winsizes();
+    input::register_handler(handle_event);
+    info!("tty1 to tty{}, switched with Alt+F1 to Alt+F{}", VT_COUNT, VT_COUNT);
+}
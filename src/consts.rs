@@ -4,6 +4,50 @@ pub const MAX_CPU_NUM: usize = 64;
 /// support up to 512 processes
 pub const MAX_PROCESS_NUM: usize = 512;
 
+/// length of a timer tick
 pub const USEC_PER_TICK: usize = 10000;
 
-pub const INFORM_PER_MSEC: usize = 50;
\ No newline at end of file
+/// how often each cpu compares its run queue with the others, in ms
+pub const INFORM_PER_MSEC: usize = 50;
+
+/// default log level, modules can be tuned with `logging::set_module_level`
+pub const LOG_LEVEL: log::LevelFilter = log::LevelFilter::Info;
+
+/// filter spec applied once the heap is up, see `logging::apply_filter_spec`
+pub const LOG_FILTER: &str = "info";
+
+/// size of the kernel message ring read by `logging::dmesg`
+pub const DMESG_SIZE: usize = 64 * 1024;
+
+/// run the gdb stub, which takes over breakpoints and faults: without a
+/// debugger attached a fault then waits for one forever
+pub const GDB_ENABLED: bool = false;
+
+/// index in `COM` of the uart the gdb stub talks on, the stub is
+/// only enabled if that port is present
+pub const GDB_COM: usize = 1;
+
+/// stop in the gdb stub at boot, until a debugger connects and continues
+pub const GDB_WAIT_AT_BOOT: bool = false;
+
+/// keyboard layout at boot: us104, uk105, dvorak104 or azerty
+pub const KEYBOARD_LAYOUT: &str = "us104";
+
+/// scancode set at boot, 1 (translated by the i8042) or 2
+pub const KEYBOARD_SCANCODE_SET: u8 = 1;
+
+/// virtual terminals, switched with Alt+F1 and up
+pub const VT_COUNT: usize = 6;
+
+/// terminal the kernel log is shown on, and the one at boot
+pub const LOG_VT: usize = 0;
+
+/// terminal the debug shell runs on
+pub const SHELL_VT: usize = 1;
+
+/// put the console on the bootloader's framebuffer, or failing that switch a
+/// bochs/qemu display adapter to graphics
+pub const FRAMEBUFFER_CONSOLE: bool = true;
+
+/// mode set on the bochs display adapter, width, height and bits per pixel
+pub const FRAMEBUFFER_MODE: (usize, usize, usize) = (1024, 768, 32);
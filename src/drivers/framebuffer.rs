@@ -0,0 +1,258 @@
+use core::{ptr, sync::atomic::{AtomicBool, Ordering}};
+use log::{info, warn};
+
+use crate::{console::{Color, MAX_COLUMNS, MAX_ROWS, ScreenChar, TextDisplay, vt}, sync::mutex::SpinNoIrqLock};
+
+use super::psf::Font;
+
+/// console font, code page 437 glyphs so it draws the cells of the vga text buffer
+static FONT_DATA: &[u8] = include_bytes!("font8x16.psf");
+
+/// rgb of the 16 vga text colors, indexed like `Color`
+const PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xaa],
+    [0x00, 0xaa, 0x00],
+    [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00],
+    [0xaa, 0x00, 0xaa],
+    [0xaa, 0x55, 0x00],
+    [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xff],
+    [0x55, 0xff, 0x55],
+    [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55],
+    [0xff, 0x55, 0xff],
+    [0xff, 0xff, 0x55],
+    [0xff, 0xff, 0xff],
+];
+
+/// pixel rows of the underline cursor
+const CURSOR_HEIGHT: usize = 2;
+/// widest glyph rendered, in pixels
+const MAX_GLYPH_WIDTH: usize = 32;
+const MAX_BYTES_PER_PIXEL: usize = 4;
+
+/// Order of the color channels in a pixel, as in bootloader 0.10.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// red first, in memory
+    Rgb,
+    Bgr,
+    /// one gray level
+    U8,
+}
+
+/// Layout of a linear framebuffer, with the fields bootloader 0.10 hands over.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameBufferInfo {
+    pub byte_len: usize,
+    pub horizontal_resolution: usize,
+    pub vertical_resolution: usize,
+    pub pixel_format: PixelFormat,
+    /// 1 to 4, with 2 bytes the channels are packed 5:6:5
+    pub bytes_per_pixel: usize,
+    /// pixels from the start of one line to the next
+    pub stride: usize,
+}
+
+impl FrameBufferInfo {
+    /// Pixel bytes of `rgb`, the first `bytes_per_pixel` are used.
+    fn encode(&self, [r, g, b]: [u8; 3]) -> [u8; MAX_BYTES_PER_PIXEL] {
+        let (first, last) = match self.pixel_format {
+            PixelFormat::Rgb => (r, b),
+            PixelFormat::Bgr => (b, r),
+            PixelFormat::U8 => {
+                let gray = ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8) as u8;
+                return [gray; MAX_BYTES_PER_PIXEL];
+            }
+        };
+        if self.bytes_per_pixel == 2 {
+            // the first channel in the low bits
+            let packed = (last as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | first as u16 >> 3;
+            let [low, high] = packed.to_le_bytes();
+            [low, high, 0, 0]
+        } else {
+            [first, g, last, 0]
+        }
+    }
+
+    fn valid(&self) -> bool {
+        let bytes_per_pixel_ok = match self.pixel_format {
+            PixelFormat::U8 => self.bytes_per_pixel == 1,
+            _ => (2..=MAX_BYTES_PER_PIXEL).contains(&self.bytes_per_pixel),
+        };
+        bytes_per_pixel_ok
+            && self.stride >= self.horizontal_resolution
+            && self.stride * self.vertical_resolution * self.bytes_per_pixel <= self.byte_len
+    }
+}
+
+struct FrameBuffer {
+    /// virtual address of the first pixel
+    base: usize,
+    info: FrameBufferInfo,
+    font: Font<'static>,
+    /// encoded `PALETTE`
+    colors: [[u8; MAX_BYTES_PER_PIXEL]; 16],
+}
+
+impl FrameBuffer {
+    fn line_bytes(&self) -> usize {
+        self.info.stride * self.info.bytes_per_pixel
+    }
+
+    fn pixel_address(&self, x: usize, y: usize) -> *mut u8 {
+        (self.base + y * self.line_bytes() + x * self.info.bytes_per_pixel) as *mut u8
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: usize) {
+        let bpp = self.info.bytes_per_pixel;
+        let pixel = self.colors[color];
+        for y in y..y + height {
+            let line = self.pixel_address(x, y);
+            for i in 0..width {
+                unsafe { ptr::copy_nonoverlapping(pixel.as_ptr(), line.add(i * bpp), bpp) };
+            }
+        }
+    }
+
+    /// Renders `c` into text cell `row`, `col`, a line of pixels at a time,
+    /// with its bottom lines inverted under the cursor.
+    fn draw_cell(&mut self, row: usize, col: usize, c: ScreenChar, cursor: bool) {
+        let (width, height) = (self.font.width(), self.font.height());
+        let bpp = self.info.bytes_per_pixel;
+        let foreground = self.colors[c.color_code.foreground()];
+        let background = self.colors[c.color_code.background()];
+        let glyph = self.font.glyph(c.ascii_character as usize);
+        let mut line = [0u8; MAX_GLYPH_WIDTH * MAX_BYTES_PER_PIXEL];
+        for y in 0..height {
+            let bits = &glyph[y * self.font.bytes_per_row()..];
+            for x in 0..width {
+                let set = bits[x / 8] & (0x80 >> (x % 8)) != 0;
+                let pixel = if set { &foreground } else { &background };
+                line[x * bpp..(x + 1) * bpp].copy_from_slice(&pixel[..bpp]);
+            }
+            if cursor && y >= height - CURSOR_HEIGHT {
+                line[..width * bpp].iter_mut().for_each(|b| *b = !*b);
+            }
+            let target = self.pixel_address(col * width, row * height + y);
+            unsafe { ptr::copy_nonoverlapping(line.as_ptr(), target, width * bpp) };
+        }
+    }
+}
+
+/// A framebuffer seen as a grid of text cells in the console font.
+///
+/// what the cells hold is kept in memory as well, so reading them and
+/// scrolling never read the framebuffer back, which is slow on most cards
+pub struct FbText {
+    fb: Option<FrameBuffer>,
+    cells: [[ScreenChar; MAX_COLUMNS]; MAX_ROWS],
+    cursor: Option<(usize, usize)>,
+}
+
+impl FbText {
+    const fn new() -> Self {
+        FbText { fb: None, cells: [[ScreenChar::BLANK; MAX_COLUMNS]; MAX_ROWS], cursor: None }
+    }
+
+    /// Renders the cell from `cells`, with the cursor if it is there.
+    fn redraw(&mut self, row: usize, col: usize) {
+        let cursor = self.cursor == Some((row, col));
+        if let Some(fb) = self.fb.as_mut() {
+            fb.draw_cell(row, col, self.cells[row][col], cursor);
+        }
+    }
+}
+
+impl TextDisplay for FbText {
+    fn size(&self) -> (usize, usize) {
+        match &self.fb {
+            Some(fb) => (
+                (fb.info.vertical_resolution / fb.font.height()).min(MAX_ROWS),
+                (fb.info.horizontal_resolution / fb.font.width()).min(MAX_COLUMNS),
+            ),
+            None => (0, 0),
+        }
+    }
+
+    fn draw(&mut self, row: usize, col: usize, c: ScreenChar) {
+        if row >= MAX_ROWS || col >= MAX_COLUMNS {
+            return;
+        }
+        self.cells[row][col] = c;
+        self.redraw(row, col);
+    }
+
+    fn read(&self, row: usize, col: usize) -> ScreenChar {
+        self.cells.get(row).and_then(|line| line.get(col)).copied().unwrap_or(ScreenChar::BLANK)
+    }
+
+    /// Shifts the cells up and renders only those that changed, the cursor
+    /// is left where it is.
+    fn scroll_up(&mut self) {
+        let (rows, columns) = self.size();
+        for row in 0..rows.saturating_sub(1) {
+            for col in 0..columns {
+                let below = self.cells[row + 1][col];
+                if self.cells[row][col] != below {
+                    self.cells[row][col] = below;
+                    self.redraw(row, col);
+                }
+            }
+        }
+    }
+
+    fn set_cursor(&mut self, position: Option<(usize, usize)>) {
+        let old = core::mem::replace(&mut self.cursor, position);
+        let (rows, columns) = self.size();
+        for (row, col) in old.into_iter().chain(position) {
+            if row < rows && col < columns {
+                self.redraw(row, col);
+            }
+        }
+    }
+}
+
+/// The framebuffer the terminals draw on once `init_framebuffer` found one.
+pub static DISPLAY: SpinNoIrqLock<FbText> = SpinNoIrqLock::new(FbText::new());
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::Acquire)
+}
+
+/// Put the terminals on the linear framebuffer mapped at `base`.
+pub fn init_framebuffer(base: usize, info: FrameBufferInfo) {
+    if !info.valid() {
+        warn!("unsupported framebuffer {:?}", info);
+        return;
+    }
+    let font = match Font::parse(FONT_DATA) {
+        Some(font) if font.width() <= MAX_GLYPH_WIDTH => font,
+        _ => {
+            warn!("bad console font");
+            return;
+        }
+    };
+    let mut colors = [[0; MAX_BYTES_PER_PIXEL]; 16];
+    for (color, rgb) in colors.iter_mut().zip(PALETTE.iter()) {
+        *color = info.encode(*rgb);
+    }
+    let mut fb = FrameBuffer { base, info, font, colors };
+    fb.fill(0, 0, info.horizontal_resolution, info.vertical_resolution, Color::Black as usize);
+    {
+        let mut display = DISPLAY.lock();
+        display.fb = Some(fb);
+        display.cells = [[ScreenChar::BLANK; MAX_COLUMNS]; MAX_ROWS];
+        display.cursor = None;
+    }
+    ACTIVE.store(true, Ordering::Release);
+    vt::resize_all();
+    info!(
+        "framebuffer console {}x{} {:?} {} bytes per pixel",
+        info.horizontal_resolution, info.vertical_resolution, info.pixel_format, info.bytes_per_pixel,
+    );
+}
@@ -0,0 +1,127 @@
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use pc_keyboard::{DecodedKey, KeyCode, KeyState};
+
+use crate::{sync::{condvar::Condvar, mutex::SpinNoIrqLock}, time::monotonic_ns};
+
+/// events a reader keeps before it starts dropping new ones
+pub const READER_QUEUE_LEN: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// What happened, in the spirit of the evdev event types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventKind {
+    /// a key press or release, with the character or key it stands for on presses
+    Key { code: KeyCode, state: KeyState, key: Option<DecodedKey> },
+    /// relative pointer motion, x to the right and y down
+    Motion { dx: i32, dy: i32 },
+    Button { button: MouseButton, pressed: bool },
+    /// wheel detents, positive is away from the user
+    Wheel { delta: i32 },
+    /// the events since the last `Sync` form one report of the device
+    Sync,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputEvent {
+    /// monotonic time the device reported it, in ns
+    pub time_ns: u64,
+    /// id from `register_device`
+    pub device: usize,
+    pub kind: EventKind,
+}
+
+struct ReaderQueue {
+    /// capacity reserved up front, so publishing never allocates
+    events: SpinNoIrqLock<VecDeque<InputEvent>>,
+    dropped: AtomicUsize,
+    readers: Condvar,
+}
+
+/// An open input stream, it sees every event published after it was opened
+/// in its own buffer, independent of other readers.
+pub struct InputReader {
+    queue: Arc<ReaderQueue>,
+}
+
+impl InputReader {
+    /// Next event, blocking until there is one.
+    pub fn read(&self) -> InputEvent {
+        self.queue.readers.wait_until(&self.queue.events, |q| q.pop_front())
+    }
+
+    pub fn try_read(&self) -> Option<InputEvent> {
+        self.queue.events.lock().pop_front()
+    }
+
+    /// events lost so far because this reader fell behind
+    pub fn dropped(&self) -> usize {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for InputReader {
+    fn drop(&mut self) {
+        // the queue is freed here and not in an interrupt handler
+        READERS.lock().retain(|q| !Arc::ptr_eq(q, &self.queue));
+    }
+}
+
+static READERS: SpinNoIrqLock<Vec<Arc<ReaderQueue>>> = SpinNoIrqLock::new(Vec::new());
+static DEVICES: SpinNoIrqLock<Vec<&'static str>> = SpinNoIrqLock::new(Vec::new());
+static HANDLERS: SpinNoIrqLock<Vec<fn(&InputEvent)>> = SpinNoIrqLock::new(Vec::new());
+
+/// Announce an input device, returns the id its events carry.
+pub fn register_device(name: &'static str) -> usize {
+    let mut devices = DEVICES.lock();
+    devices.push(name);
+    devices.len() - 1
+}
+
+/// name of every registered device, indexed by id
+pub fn devices() -> Vec<&'static str> {
+    DEVICES.lock().clone()
+}
+
+/// Start receiving input events.
+pub fn open_reader() -> InputReader {
+    let queue = Arc::new(ReaderQueue {
+        events: SpinNoIrqLock::new(VecDeque::with_capacity(READER_QUEUE_LEN)),
+        dropped: AtomicUsize::new(0),
+        readers: Condvar::new(),
+    });
+    READERS.lock().push(queue.clone());
+    InputReader { queue }
+}
+
+/// Have `handler` called with every event, in the interrupt handler that
+/// publishes it; for the kernel's own consumers such as the virtual terminals.
+pub fn register_handler(handler: fn(&InputEvent)) {
+    HANDLERS.lock().push(handler);
+}
+
+/// Hand an event of `device` to every handler and reader, time stamped now.
+///
+/// called by drivers from their interrupt handlers
+pub fn publish(device: usize, kind: EventKind) {
+    let event = InputEvent { time_ns: monotonic_ns(), device, kind };
+    for handler in HANDLERS.lock().iter() {
+        handler(&event);
+    }
+    for queue in READERS.lock().iter() {
+        let mut events = queue.events.lock();
+        if events.len() < READER_QUEUE_LEN {
+            events.push_back(event.clone());
+        } else {
+            queue.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        drop(events);
+        queue.readers.notify_all();
+    }
+}
@@ -0,0 +1,66 @@
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use lazy_static::lazy_static;
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::Driver;
+
+/// Drivers listening on each irq line, shared lines have several.
+pub struct IrqManager {
+    mapping: BTreeMap<usize, Vec<Arc<dyn Driver>>>,
+}
+
+impl IrqManager {
+    fn new() -> Self {
+        IrqManager {
+            mapping: BTreeMap::new(),
+        }
+    }
+
+    /// Offer `irq` to every driver on the line, true if one of them claimed it.
+    ///
+    /// all of them are asked, a level triggered line stays asserted
+    /// until every device on it has been served
+    pub fn try_handle_interrupt(&self, irq: usize) -> bool {
+        match self.mapping.get(&irq) {
+            Some(drivers) => drivers
+                .iter()
+                .fold(false, |handled, d| d.try_handle_interrupt(Some(irq)) | handled),
+            None => false,
+        }
+    }
+
+    pub fn is_registered(&self, irq: usize) -> bool {
+        self.mapping.get(&irq).map_or(false, |d| !d.is_empty())
+    }
+}
+
+lazy_static! {
+    /// read from interrupt context, so only taken for writing with interrupts disabled
+    pub static ref IRQ_MANAGER: RwLock<IrqManager> = RwLock::new(IrqManager::new());
+}
+
+/// Route `irq` to `driver`, in addition to the drivers already on it.
+pub fn register_irq(irq: usize, driver: Arc<dyn Driver>) {
+    without_interrupts(|| {
+        IRQ_MANAGER.write().mapping.entry(irq).or_insert_with(Vec::new).push(driver);
+    });
+}
+
+/// Stop routing `irq` to `driver`.
+pub fn unregister_irq(irq: usize, driver: &Arc<dyn Driver>) {
+    without_interrupts(|| {
+        let mut manager = IRQ_MANAGER.write();
+        if let Some(drivers) = manager.mapping.get_mut(&irq) {
+            drivers.retain(|d| !Arc::ptr_eq(d, driver));
+            if drivers.is_empty() {
+                manager.mapping.remove(&irq);
+            }
+        }
+    });
+}
+
+/// Called by the arch interrupt code for every external interrupt.
+pub fn handle_irq(irq: usize) -> bool {
+    IRQ_MANAGER.read().try_handle_interrupt(irq)
+}
@@ -8,12 +8,19 @@ use alloc::string::String;
 use self::block::BlockDriver;
 
 pub mod block;
+pub mod framebuffer;
+pub mod input;
+pub mod irq;
 pub mod pci;
+pub mod psf;
+pub mod tty;
 pub trait SomeTrait: Send + Sync {
     fn some(&self);
 }
 
 lazy_static!(
+    /// every driver that was successfully initialized
+    pub static ref DRIVERS: RwLock<Vec<Arc<dyn Driver>>> = RwLock::new(Vec::new());
     pub static ref BLK_DRIVERS: RwLock<Vec<Arc<dyn BlockDriver>>> = RwLock::new(Vec::new());
 );
 
@@ -11,6 +11,10 @@ const PCI_MSI_DATA_32: u16 = 0x08;
 const PCI_MSI_DATA_64: u16 = 0x0C;
 
 const PCI_CAP_ID_MSI: u8 = 0x05;
+
+/// msi messages are writes to this window, fixed by the architecture
+/// independently of where the local apic registers are mapped
+const MSI_ADDRESS_BASE: u32 = 0xfee00000;
 use alloc::vec::Vec;
 
 
@@ -699,13 +703,10 @@ pub unsafe fn scan_bus<T: PortOps>(
 }
 
 /// Enable the pci device and its interrupt
-/// Return assigned MSI interrupt number when applicable
-unsafe fn enable<T: PortOps>(loc: BusLocation, ops: &T) -> Option<usize> {
+/// Return assigned MSI interrupt number when applicable, taken from `alloc_irq`
+pub unsafe fn enable<T: PortOps>(loc: BusLocation, ops: &T, alloc_irq: impl Fn() -> Option<usize>) -> Option<usize> {
     let am = ConfigSpaceAccessMethod::IO;
 
-    // 23 and lower are used
-    static mut MSI_IRQ: u32 = 23;
-
     let orig = am.read16(ops, loc, PCI_COMMAND);
     // IO Space | MEM Space | Bus Mastering | Special Cycles | PCI Interrupt Disable
     am.write32(ops, loc, PCI_COMMAND, (orig | 0x40f) as u32);
@@ -716,13 +717,15 @@ unsafe fn enable<T: PortOps>(loc: BusLocation, ops: &T) -> Option<usize> {
     let mut assigned_irq = None;
     while cap_ptr > 0 {
         let cap_id = am.read8(ops, loc, cap_ptr);
-        if cap_id == PCI_CAP_ID_MSI {
+        if cap_id == PCI_CAP_ID_MSI && assigned_irq.is_none() {
+            let irq = match alloc_irq() {
+                Some(irq) => irq as u32,
+                None => break,
+            };
             let orig_ctrl = am.read32(ops, loc, cap_ptr + PCI_MSI_CTRL_CAP);
             // The manual Volume 3 Chapter 10.11 Message Signalled Interrupts
             // 0 is (usually) the apic id of the bsp.
-            am.write32(ops, loc, cap_ptr + PCI_MSI_ADDR, 0xfee00000 | (0 << 12));
-            MSI_IRQ += 1;
-            let irq = MSI_IRQ;
+            am.write32(ops, loc, cap_ptr + PCI_MSI_ADDR, MSI_ADDRESS_BASE | (0 << 12));
             assigned_irq = Some(irq as usize);
             // we offset all our irq numbers by 32
             if (orig_ctrl >> 16) & (1 << 7) != 0 {
@@ -0,0 +1,66 @@
+use core::convert::TryInto;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_HEADER_SIZE: usize = 4;
+/// 512 glyphs instead of 256
+const PSF1_MODE512: u8 = 0x01;
+const PSF1_WIDTH: usize = 8;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HEADER_SIZE: usize = 32;
+
+fn u32_at(data: &[u8], at: usize) -> usize {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+}
+
+/// A PC Screen Font, version 1 or 2, as used by the linux console.
+///
+/// glyphs are bitmaps of `height` rows, each row `(width + 7) / 8` bytes with
+/// the leftmost pixel in the top bit. the unicode table is not used, glyphs
+/// are looked up by their index
+pub struct Font<'a> {
+    glyphs: &'a [u8],
+    count: usize,
+    glyph_size: usize,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Font<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Font<'a>> {
+        let (offset, count, glyph_size, width, height) = if data.starts_with(&PSF1_MAGIC) && data.len() >= PSF1_HEADER_SIZE {
+            let count = if data[2] & PSF1_MODE512 != 0 { 512 } else { 256 };
+            let height = data[3] as usize;
+            (PSF1_HEADER_SIZE, count, height, PSF1_WIDTH, height)
+        } else if data.starts_with(&PSF2_MAGIC) && data.len() >= PSF2_HEADER_SIZE {
+            let width = u32_at(data, 28);
+            let height = u32_at(data, 24);
+            (u32_at(data, 8), u32_at(data, 16), u32_at(data, 20), width, height)
+        } else {
+            return None;
+        };
+        if width == 0 || height == 0 || glyph_size < (width + 7) / 8 * height {
+            return None;
+        }
+        let glyphs = data.get(offset..offset.checked_add(count.checked_mul(glyph_size)?)?)?;
+        Some(Font { glyphs, count, glyph_size, width, height })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bytes_per_row(&self) -> usize {
+        (self.width + 7) / 8
+    }
+
+    /// Bitmap of glyph `index`, the first glyph for indexes the font lacks.
+    pub fn glyph(&self, index: usize) -> &'a [u8] {
+        let index = if index < self.count { index } else { 0 };
+        &self.glyphs[index * self.glyph_size..][..self.glyph_size]
+    }
+}
@@ -0,0 +1,410 @@
+//! The line discipline between a terminal and its readers: input mapping,
+//! line editing in canonical mode, echo and the signal characters, as
+//! linux's n_tty does it for the flags in `termios`.
+
+use crate::{process::signal::Signal, sync::ring::Ring};
+
+use super::termios::*;
+
+/// bytes kept for readers
+const READ_BUFFER_SIZE: usize = 4096;
+/// longest line in canonical mode, more typed on it is dropped
+pub const MAX_CANON: usize = 255;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+pub struct LineDiscipline {
+    termios: Termios,
+    /// what readers get: complete lines in canonical mode, with the newline or
+    /// end of line character, or the end of file character standing for itself
+    ready: Ring<READ_BUFFER_SIZE>,
+    /// lines in `ready`, canonical mode
+    lines: usize,
+    /// the line being edited in canonical mode
+    line: [u8; MAX_CANON],
+    line_len: usize,
+    /// the other end is gone: reads see end of file, input is ignored
+    hung_up: bool,
+}
+
+impl LineDiscipline {
+    pub const fn new() -> Self {
+        LineDiscipline {
+            termios: Termios::new(),
+            ready: Ring::new(),
+            lines: 0,
+            line: [0; MAX_CANON],
+            line_len: 0,
+            hung_up: false,
+        }
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.termios
+    }
+
+    /// Switch to `termios`. Turning canonical mode on makes pending input the
+    /// start of the line being edited, turning it off makes that line readable.
+    pub fn set_termios(&mut self, termios: Termios) {
+        match (self.termios.canonical(), termios.canonical()) {
+            (false, true) => {
+                self.line_len = 0;
+                while let Some(b) = self.ready.pop() {
+                    if self.line_len < MAX_CANON {
+                        self.line[self.line_len] = b;
+                        self.line_len += 1;
+                    }
+                }
+                self.lines = 0;
+            }
+            (true, false) => {
+                for &b in &self.line[..self.line_len] {
+                    self.ready.push(b);
+                }
+                self.line_len = 0;
+                self.lines = 0;
+            }
+            _ => {}
+        }
+        self.termios = termios;
+    }
+
+    pub fn hung_up(&self) -> bool {
+        self.hung_up
+    }
+
+    /// The terminal went away, for good.
+    pub fn hang_up(&mut self) {
+        self.flush_input();
+        self.hung_up = true;
+    }
+
+    /// Drops the input no reader has taken yet.
+    pub fn flush_input(&mut self) {
+        self.ready.clear();
+        self.lines = 0;
+        self.line_len = 0;
+    }
+
+    /// Takes input for a reader: at most one line in canonical mode, all there
+    /// is in raw mode once `VMIN` bytes arrived. None if the reader has to wait
+    pub fn read(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.hung_up {
+            return Some(0);
+        }
+        if !self.termios.canonical() {
+            let min = (self.termios.cc[VMIN] as usize).min(buf.len());
+            if self.ready.len() < min {
+                return None;
+            }
+            let mut count = 0;
+            while count < buf.len() {
+                match self.ready.pop() {
+                    Some(b) => buf[count] = b,
+                    None => break,
+                }
+                count += 1;
+            }
+            return Some(count);
+        }
+        if self.lines == 0 {
+            return None;
+        }
+        let mut count = 0;
+        while count < buf.len() {
+            let b = match self.ready.pop() {
+                Some(b) => b,
+                None => break,
+            };
+            let t = &self.termios;
+            if t.is(VEOF, b) {
+                self.lines -= 1;
+                break;
+            }
+            buf[count] = b;
+            count += 1;
+            if b == b'\n' || t.is(VEOL, b) || t.is(VEOL2, b) {
+                self.lines -= 1;
+                break;
+            }
+        }
+        Some(count)
+    }
+
+    /// Processes a byte from the terminal, writing what is echoed to `echo`.
+    /// returns the signal a signal character asks for, for the foreground process
+    pub fn receive(&mut self, byte: u8, echo: &mut dyn FnMut(&[u8])) -> Option<Signal> {
+        if self.hung_up {
+            return None;
+        }
+        let t = self.termios;
+        let c = match byte {
+            b'\r' if t.iflag & IGNCR != 0 => return None,
+            b'\r' if t.iflag & ICRNL != 0 => b'\n',
+            b'\n' if t.iflag & INLCR != 0 => b'\r',
+            c => c,
+        };
+
+        if t.lflag & ISIG != 0 {
+            let signal = if t.is(VINTR, c) {
+                Some(Signal::Int)
+            } else if t.is(VQUIT, c) {
+                Some(Signal::Quit)
+            } else if t.is(VSUSP, c) {
+                Some(Signal::Tstp)
+            } else {
+                None
+            };
+            if signal.is_some() {
+                if t.lflag & NOFLSH == 0 {
+                    self.flush_input();
+                }
+                self.echo(c, echo);
+                return signal;
+            }
+        }
+
+        if !t.canonical() {
+            // dropped when nobody reads
+            self.ready.push(c);
+            self.echo(c, echo);
+            return None;
+        }
+
+        let extended = t.lflag & IEXTEN != 0;
+        if t.is(VERASE, c) {
+            self.erase(c, echo);
+        } else if extended && t.is(VWERASE, c) {
+            self.erase_word(echo);
+        } else if t.is(VKILL, c) {
+            self.kill(c, echo);
+        } else if extended && t.is(VREPRINT, c) {
+            self.echo(c, echo);
+            self.echo(b'\n', echo);
+            for &b in &self.line[..self.line_len] {
+                self.echo(b, echo);
+            }
+        } else if t.is(VEOF, c) {
+            // ends the line without adding to it, on an empty line reads return 0
+            self.finish_line(c);
+        } else if c == b'\n' || t.is(VEOL, c) || t.is(VEOL2, c) {
+            if c == b'\n' && t.lflag & ECHONL != 0 && t.lflag & ECHO == 0 {
+                echo(b"\n");
+            }
+            self.echo(c, echo);
+            self.finish_line(c);
+        } else if self.line_len < MAX_CANON {
+            self.line[self.line_len] = c;
+            self.line_len += 1;
+            self.echo(c, echo);
+        }
+        None
+    }
+
+    /// Echoes `c` if `ECHO` is on, control characters as ^X with `ECHOCTL`.
+    fn echo(&self, c: u8, echo: &mut dyn FnMut(&[u8])) {
+        let t = &self.termios;
+        if t.lflag & ECHO == 0 {
+            return;
+        }
+        if t.lflag & ECHOCTL != 0 && is_control(c) {
+            echo(&[b'^', c ^ 0x40]);
+        } else {
+            echo(&[c]);
+        }
+    }
+
+    /// Moves the edited line to the readers, terminated by `end`. a line that
+    /// does not fit is lost
+    fn finish_line(&mut self, end: u8) {
+        if READ_BUFFER_SIZE - self.ready.len() > self.line_len {
+            for &b in &self.line[..self.line_len] {
+                self.ready.push(b);
+            }
+            self.ready.push(end);
+            self.lines += 1;
+        }
+        self.line_len = 0;
+    }
+
+    /// Removes the last character of the line, all bytes of it with `IUTF8`.
+    /// returns its first byte
+    fn pop_char(&mut self) -> Option<u8> {
+        let utf8 = self.termios.iflag & IUTF8 != 0;
+        loop {
+            if self.line_len == 0 {
+                return None;
+            }
+            self.line_len -= 1;
+            let b = self.line[self.line_len];
+            // continuation bytes are 10xxxxxx
+            if !utf8 || b & 0xc0 != 0x80 {
+                return Some(b);
+            }
+        }
+    }
+
+    /// Erases the last character, from the screen too with `ECHOE`.
+    fn erase(&mut self, erase: u8, echo: &mut dyn FnMut(&[u8])) {
+        let c = match self.pop_char() {
+            Some(c) => c,
+            None => return,
+        };
+        let t = &self.termios;
+        if t.lflag & ECHO == 0 {
+            return;
+        }
+        if t.lflag & ECHOE == 0 {
+            self.echo(erase, echo);
+            return;
+        }
+        let width = if t.lflag & ECHOCTL != 0 && is_control(c) { 2 } else { 1 };
+        for _ in 0..width {
+            echo(&[BACKSPACE, b' ', BACKSPACE]);
+        }
+    }
+
+    /// `VWERASE`: the blanks before the cursor and the word before them.
+    fn erase_word(&mut self, echo: &mut dyn FnMut(&[u8])) {
+        let erase = self.termios.cc[VERASE];
+        while self.line_len != 0 && self.line[self.line_len - 1] == b' ' {
+            self.erase(erase, echo);
+        }
+        while self.line_len != 0 && self.line[self.line_len - 1] != b' ' {
+            self.erase(erase, echo);
+        }
+    }
+
+    /// `VKILL`: the whole line, erased from the screen with `ECHOKE`, or
+    /// echoed as the kill character and a newline with `ECHOK`.
+    fn kill(&mut self, kill: u8, echo: &mut dyn FnMut(&[u8])) {
+        let t = self.termios;
+        if t.lflag & ECHOKE != 0 && t.lflag & ECHOE != 0 {
+            let erase = t.cc[VERASE];
+            while self.line_len != 0 {
+                self.erase(erase, echo);
+            }
+            return;
+        }
+        self.line_len = 0;
+        self.echo(kill, echo);
+        if t.lflag & ECHOK != 0 && t.lflag & ECHO != 0 {
+            echo(b"\n");
+        }
+    }
+}
+
+/// characters `ECHOCTL` shows as ^X, tab and newline are echoed as they are
+fn is_control(c: u8) -> bool {
+    (c < b' ' && c != b'\t' && c != b'\n') || c == DELETE
+}
+
+/// Output processing of `termios` on `bytes` for `write`: with `OPOST`,
+/// `ONLCR` sends CR LF for a newline and `OCRNL` a newline for CR.
+pub fn output(termios: &Termios, bytes: &[u8], write: &mut dyn FnMut(&[u8])) {
+    if termios.oflag & OPOST == 0 {
+        write(bytes);
+        return;
+    }
+    let mut start = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        let replacement: &[u8] = match b {
+            b'\n' if termios.oflag & ONLCR != 0 => b"\r\n",
+            b'\r' if termios.oflag & OCRNL != 0 => b"\n",
+            _ => continue,
+        };
+        if start < i {
+            write(&bytes[start..i]);
+        }
+        write(replacement);
+        start = i + 1;
+    }
+    if start < bytes.len() {
+        write(&bytes[start..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Types `input`, returns what was echoed.
+    fn type_bytes(ldisc: &mut LineDiscipline, input: &[u8]) -> Vec<u8> {
+        let mut echoed = Vec::new();
+        for &b in input {
+            ldisc.receive(b, &mut |bytes| echoed.extend_from_slice(bytes));
+        }
+        echoed
+    }
+
+    fn read(ldisc: &mut LineDiscipline) -> Option<Vec<u8>> {
+        let mut buf = [0; 64];
+        ldisc.read(&mut buf).map(|n| buf[..n].to_vec())
+    }
+
+    #[test_case]
+    fn canonical_edit() {
+        let mut ldisc = LineDiscipline::new();
+        let echoed = type_bytes(&mut ldisc, b"ab\x7fc");
+        assert_eq!(echoed, b"ab\x08 \x08c");
+        // nothing to read before the end of the line
+        assert_eq!(read(&mut ldisc), None);
+        type_bytes(&mut ldisc, b"d\x15ef\r");
+        assert_eq!(read(&mut ldisc).unwrap(), b"ef\n");
+    }
+
+    #[test_case]
+    fn veof() {
+        let mut ldisc = LineDiscipline::new();
+        let echoed = type_bytes(&mut ldisc, b"hi\x04\x04");
+        // the end of file character is not echoed
+        assert_eq!(echoed, b"hi");
+        assert_eq!(read(&mut ldisc).unwrap(), b"hi");
+        assert_eq!(read(&mut ldisc).unwrap(), b"");
+        assert_eq!(read(&mut ldisc), None);
+    }
+
+    #[test_case]
+    fn echoctl_erase_width() {
+        let mut ldisc = LineDiscipline::new();
+        assert_eq!(type_bytes(&mut ldisc, b"\x01"), b"^A");
+        assert_eq!(type_bytes(&mut ldisc, b"\x7f"), b"\x08 \x08\x08 \x08");
+        assert_eq!(type_bytes(&mut ldisc, b"x\x7f"), b"x\x08 \x08");
+    }
+
+    #[test_case]
+    fn signal_characters() {
+        let mut ldisc = LineDiscipline::new();
+        type_bytes(&mut ldisc, b"abc");
+        let mut echoed = Vec::new();
+        let signal = ldisc.receive(0x03, &mut |bytes| echoed.extend_from_slice(bytes));
+        assert_eq!(signal, Some(Signal::Int));
+        assert_eq!(echoed, b"^C");
+        // the line typed so far is gone
+        type_bytes(&mut ldisc, b"\n");
+        assert_eq!(read(&mut ldisc).unwrap(), b"\n");
+    }
+
+    #[test_case]
+    fn raw_mode() {
+        let mut ldisc = LineDiscipline::new();
+        type_bytes(&mut ldisc, b"ab");
+        let mut termios = ldisc.termios();
+        termios.lflag &= !(ICANON | ECHO);
+        ldisc.set_termios(termios);
+        // the unfinished line becomes readable
+        assert_eq!(read(&mut ldisc).unwrap(), b"ab");
+        assert_eq!(type_bytes(&mut ldisc, b"\x7f"), b"");
+        assert_eq!(read(&mut ldisc).unwrap(), b"\x7f");
+    }
+
+    #[test_case]
+    fn output_newlines() {
+        let mut written = Vec::new();
+        output(&Termios::new(), b"a\nb", &mut |bytes| written.extend_from_slice(bytes));
+        assert_eq!(written, b"a\r\nb");
+    }
+}
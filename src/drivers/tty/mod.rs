@@ -0,0 +1,233 @@
+//! Terminals: a device that shows bytes and produces typed ones, with a
+//! `LineDiscipline` between it and the processes reading it.
+
+use alloc::{boxed::Box, string::String, sync::{Arc, Weak}, vec::Vec};
+use core::fmt::{self, Write};
+use lazy_static::lazy_static;
+use spin::RwLock;
+
+use crate::{process::{current, proc::{PROCESSES, Process}, signal::{Signal, send_signal}}, sync::{condvar::Condvar, mutex::SpinNoIrqLock}};
+
+use self::{ldisc::{LineDiscipline, output}, termios::*};
+
+pub mod ldisc;
+pub mod pty;
+pub mod termios;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtyError {
+    /// a signal arrived while waiting, EINTR
+    Interrupted,
+    /// nothing to read yet, EAGAIN
+    WouldBlock,
+    /// not a terminal request, ENOTTY
+    BadRequest,
+    /// no process with that pid, ESRCH
+    NoProcess,
+    /// the terminal was hung up, EIO
+    HungUp,
+    /// every pty number is taken, ENOSPC
+    NoSpace,
+}
+
+/// The device side of a terminal.
+pub trait TtyDriver: Send + Sync {
+    /// Shows output, already processed for the terminal's `Termios`.
+    ///
+    /// called with interrupts off, for echo from the device's interrupt handler
+    fn write(&self, bytes: &[u8]);
+}
+
+pub struct Tty {
+    name: String,
+    driver: Box<dyn TtyDriver>,
+    ldisc: SpinNoIrqLock<LineDiscipline>,
+    readers: Condvar,
+    /// process that signal characters are sent to
+    foreground: SpinNoIrqLock<Option<Weak<Process>>>,
+    winsize: SpinNoIrqLock<WinSize>,
+}
+
+impl Tty {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Input from the device, called by its driver, usually in the interrupt
+    /// handler: the line discipline edits and echoes it, and signal characters
+    /// go to the foreground process.
+    pub fn receive(&self, bytes: &[u8]) {
+        {
+            let mut ldisc = self.ldisc.lock();
+            let termios = ldisc.termios();
+            let mut echo = |bytes: &[u8]| output(&termios, bytes, &mut |bytes: &[u8]| self.driver.write(bytes));
+            for &b in bytes {
+                if let Some(signal) = ldisc.receive(b, &mut echo) {
+                    // sent with the line discipline locked, so a reader
+                    // about to wait sees it or is woken below
+                    if let Some(proc) = self.foreground() {
+                        send_signal(&proc, signal);
+                    }
+                }
+            }
+        }
+        self.readers.notify_all();
+    }
+
+    /// Reads typed input, blocking until the line discipline has some: a
+    /// line in canonical mode, 0 bytes at end of file and once hung up.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, TtyError> {
+        let proc = current();
+        self.readers.wait_until(&self.ldisc, |ldisc| {
+            if proc.as_ref().map_or(false, |proc| proc.pending_signals() != 0) {
+                return Some(Err(TtyError::Interrupted));
+            }
+            ldisc.read(buf).map(Ok)
+        })
+    }
+
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, TtyError> {
+        self.ldisc.lock().read(buf).ok_or(TtyError::WouldBlock)
+    }
+
+    /// Writes `bytes` to the terminal, with the output processing of its `Termios`.
+    pub fn write(&self, bytes: &[u8]) -> Result<usize, TtyError> {
+        let termios = {
+            let ldisc = self.ldisc.lock();
+            if ldisc.hung_up() {
+                return Err(TtyError::HungUp);
+            }
+            ldisc.termios()
+        };
+        output(&termios, bytes, &mut |bytes: &[u8]| self.driver.write(bytes));
+        Ok(bytes.len())
+    }
+
+    /// Formats `args` onto the terminal, for `print!`.
+    pub fn write_fmt(&self, args: fmt::Arguments) {
+        let _ = Output(self).write_fmt(args);
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.ldisc.lock().termios()
+    }
+
+    /// Changes the settings, dropping unread input first if `flush`.
+    pub fn set_termios(&self, termios: Termios, flush: bool) {
+        {
+            let mut ldisc = self.ldisc.lock();
+            if flush {
+                ldisc.flush_input();
+            }
+            ldisc.set_termios(termios);
+        }
+        // leaving canonical mode can make input readable
+        self.readers.notify_all();
+    }
+
+    pub fn foreground(&self) -> Option<Arc<Process>> {
+        self.foreground.lock().as_ref().and_then(Weak::upgrade)
+    }
+
+    /// Make `proc` the process Ctrl-C, Ctrl-\ and Ctrl-Z signal.
+    pub fn set_foreground(&self, proc: &Arc<Process>) {
+        *self.foreground.lock() = Some(Arc::downgrade(proc));
+    }
+
+    pub fn winsize(&self) -> WinSize {
+        *self.winsize.lock()
+    }
+
+    /// Records the size of the terminal, a change is signalled to the
+    /// foreground process.
+    pub fn set_winsize(&self, winsize: WinSize) {
+        let old = core::mem::replace(&mut *self.winsize.lock(), winsize);
+        if old != winsize {
+            if let Some(proc) = self.foreground() {
+                send_signal(&proc, Signal::Winch);
+            }
+        }
+    }
+
+    pub fn hung_up(&self) -> bool {
+        self.ldisc.lock().hung_up()
+    }
+
+    /// The device went away: readers see end of file, writes fail and the
+    /// foreground process gets `SIGHUP`.
+    pub fn hang_up(&self) {
+        {
+            let mut ldisc = self.ldisc.lock();
+            ldisc.hang_up();
+            if let Some(proc) = self.foreground() {
+                send_signal(&proc, Signal::Hup);
+            }
+        }
+        self.readers.notify_all();
+    }
+
+    /// Terminal control `request` with its argument at `arg`, as the system
+    /// call does it: `TCGETS`, `TCSETS`, `TCSETSW`, `TCSETSF`, `TIOCGPGRP`,
+    /// `TIOCSPGRP`, `TIOCGWINSZ` and `TIOCSWINSZ`.
+    ///
+    /// # Safety
+    ///
+    /// `arg` has to point at the `Termios`, pid or `WinSize` the request reads or writes
+    pub unsafe fn ioctl(&self, request: u32, arg: usize) -> Result<usize, TtyError> {
+        match request {
+            TCGETS => *(arg as *mut Termios) = self.termios(),
+            TCSETS | TCSETSW => self.set_termios(*(arg as *const Termios), false),
+            TCSETSF => self.set_termios(*(arg as *const Termios), true),
+            TIOCGPGRP => *(arg as *mut i32) = self.foreground().map_or(0, |proc| proc.pid() as i32),
+            TIOCSPGRP => {
+                let pid = *(arg as *const i32) as usize;
+                let proc = PROCESSES.read().get(&pid).cloned().ok_or(TtyError::NoProcess)?;
+                self.set_foreground(&proc);
+            }
+            TIOCGWINSZ => *(arg as *mut WinSize) = self.winsize(),
+            TIOCSWINSZ => self.set_winsize(*(arg as *const WinSize)),
+            _ => return Err(TtyError::BadRequest),
+        }
+        Ok(0)
+    }
+}
+
+struct Output<'a>(&'a Tty);
+
+impl fmt::Write for Output<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes()).map(drop).map_err(|_| fmt::Error)
+    }
+}
+
+lazy_static! {
+    /// every terminal, in the order they were registered
+    static ref TTYS: RwLock<Vec<Arc<Tty>>> = RwLock::new(Vec::new());
+}
+
+/// Create the terminal `name` on `driver`, with the default `Termios`.
+pub fn register_tty(name: String, driver: Box<dyn TtyDriver>) -> Arc<Tty> {
+    let tty = Arc::new(Tty {
+        name,
+        driver,
+        ldisc: SpinNoIrqLock::new(LineDiscipline::new()),
+        readers: Condvar::new(),
+        foreground: SpinNoIrqLock::new(None),
+        winsize: SpinNoIrqLock::new(WinSize::default()),
+    });
+    TTYS.write().push(tty.clone());
+    tty
+}
+
+/// Forget `tty`, `find_tty` no longer sees it and its name can be reused.
+pub fn unregister_tty(tty: &Arc<Tty>) {
+    TTYS.write().retain(|t| !Arc::ptr_eq(t, tty));
+}
+
+pub fn find_tty(name: &str) -> Option<Arc<Tty>> {
+    TTYS.read().iter().find(|tty| tty.name == name).cloned()
+}
+
+pub fn ttys() -> Vec<Arc<Tty>> {
+    TTYS.read().clone()
+}
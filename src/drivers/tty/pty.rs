@@ -0,0 +1,179 @@
+//! Pseudo terminals: the slave `pts/N` is a `Tty` like any other, but its
+//! device is a program holding the `PtyMaster`. What the master writes is
+//! typed on the slave, and what the slave shows is read from the master.
+
+use alloc::{boxed::Box, format, sync::Arc};
+
+use crate::{process::current, sync::{condvar::Condvar, mutex::SpinNoIrqLock, ring::Ring}};
+
+use super::{Tty, TtyDriver, TtyError, register_tty, termios::TIOCGPTN, unregister_tty};
+
+/// pairs that can be open at once, one bit each in `NUMBERS`
+const MAX_PTYS: usize = 64;
+/// slave output kept for the master, more is dropped until it reads
+const OUTPUT_SIZE: usize = 4096;
+
+/// pty numbers in use
+static NUMBERS: SpinNoIrqLock<u64> = SpinNoIrqLock::new(0);
+
+/// What the slave showed, waiting for the master to read it.
+struct Output {
+    buffer: SpinNoIrqLock<Ring<OUTPUT_SIZE>>,
+    readers: Condvar,
+}
+
+/// `TtyDriver` of a slave.
+struct SlaveDriver {
+    output: Arc<Output>,
+}
+
+impl TtyDriver for SlaveDriver {
+    fn write(&self, bytes: &[u8]) {
+        {
+            let mut buffer = self.output.buffer.lock();
+            for &b in bytes {
+                buffer.push(b);
+            }
+        }
+        self.output.readers.notify_all();
+    }
+}
+
+/// The controlling end of a pty. Dropping it hangs up the slave and frees
+/// its number.
+pub struct PtyMaster {
+    number: usize,
+    slave: Arc<Tty>,
+    output: Arc<Output>,
+}
+
+impl PtyMaster {
+    /// N of `pts/N`
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn slave(&self) -> &Arc<Tty> {
+        &self.slave
+    }
+
+    /// Reads what the slave showed, blocking until there is something.
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, TtyError> {
+        let proc = current();
+        self.output.readers.wait_until(&self.output.buffer, |buffer| {
+            if proc.as_ref().map_or(false, |proc| proc.pending_signals() != 0) {
+                return Some(Err(TtyError::Interrupted));
+            }
+            pop_into(buffer, buf).map(Ok)
+        })
+    }
+
+    pub fn try_read(&self, buf: &mut [u8]) -> Result<usize, TtyError> {
+        pop_into(&mut self.output.buffer.lock(), buf).ok_or(TtyError::WouldBlock)
+    }
+
+    /// Types `bytes` on the slave, through its line discipline.
+    pub fn write(&self, bytes: &[u8]) -> usize {
+        self.slave.receive(bytes);
+        bytes.len()
+    }
+
+    /// `TIOCGPTN` writes the pty number as a u32, other requests are those
+    /// of the slave; `TIOCSWINSZ` here is how the window size is passed on.
+    ///
+    /// # Safety
+    ///
+    /// as for `Tty::ioctl`
+    pub unsafe fn ioctl(&self, request: u32, arg: usize) -> Result<usize, TtyError> {
+        match request {
+            TIOCGPTN => {
+                *(arg as *mut u32) = self.number as u32;
+                Ok(0)
+            }
+            _ => self.slave.ioctl(request, arg),
+        }
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        self.slave.hang_up();
+        unregister_tty(&self.slave);
+        *NUMBERS.lock() &= !(1 << self.number);
+    }
+}
+
+/// Bytes of `buffer` into `buf`, None if there are none.
+fn pop_into(buffer: &mut Ring<OUTPUT_SIZE>, buf: &mut [u8]) -> Option<usize> {
+    if buffer.is_empty() && !buf.is_empty() {
+        return None;
+    }
+    let mut count = 0;
+    while count < buf.len() {
+        match buffer.pop() {
+            Some(b) => buf[count] = b,
+            None => break,
+        }
+        count += 1;
+    }
+    Some(count)
+}
+
+/// Allocate the lowest free pty number and create its pair, the slave is
+/// registered as `pts/N`.
+pub fn open_pty() -> Result<PtyMaster, TtyError> {
+    let number = {
+        let mut numbers = NUMBERS.lock();
+        let number = (0..MAX_PTYS).find(|n| *numbers & (1 << n) == 0).ok_or(TtyError::NoSpace)?;
+        *numbers |= 1 << number;
+        number
+    };
+    let output = Arc::new(Output {
+        buffer: SpinNoIrqLock::new(Ring::new()),
+        readers: Condvar::new(),
+    });
+    let slave = register_tty(format!("pts/{}", number), Box::new(SlaveDriver { output: output.clone() }));
+    Ok(PtyMaster { number, slave, output })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drivers::tty::find_tty;
+
+    fn read_master(master: &PtyMaster) -> alloc::vec::Vec<u8> {
+        let mut buf = [0; 64];
+        let n = master.try_read(&mut buf).unwrap_or(0);
+        buf[..n].to_vec()
+    }
+
+    #[test_case]
+    fn echo_and_read() {
+        let master = open_pty().unwrap();
+        let slave = master.slave().clone();
+        assert_eq!(master.write(b"ls\r"), 3);
+        // echoed with the slave's output processing
+        assert_eq!(read_master(&master), b"ls\r\n");
+        let mut buf = [0; 64];
+        assert_eq!(slave.try_read(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"ls\n");
+        assert_eq!(slave.write(b"ok\n"), Ok(3));
+        assert_eq!(read_master(&master), b"ok\r\n");
+        assert_eq!(master.try_read(&mut buf), Err(TtyError::WouldBlock));
+    }
+
+    #[test_case]
+    fn drop_hangs_up() {
+        let master = open_pty().unwrap();
+        let slave = master.slave().clone();
+        let name = alloc::format!("pts/{}", master.number());
+        assert!(find_tty(&name).is_some());
+        drop(master);
+        assert!(slave.hung_up());
+        assert!(find_tty(&name).is_none());
+        assert_eq!(slave.write(b"x"), Err(TtyError::HungUp));
+        // the number is free again
+        let master = open_pty().unwrap();
+        assert_eq!(alloc::format!("pts/{}", master.number()), name);
+    }
+}
@@ -0,0 +1,129 @@
+//! `struct termios` and the constants of its flags, with the values of linux
+//! on x86_64 so the ioctls can be served the same way.
+
+pub const NCCS: usize = 19;
+
+// input flags
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+/// erase works on whole utf-8 characters
+pub const IUTF8: u32 = 0o40000;
+
+// output flags
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+pub const OCRNL: u32 = 0o10;
+
+// control flags, only reported
+pub const B38400: u32 = 0o17;
+pub const CS8: u32 = 0o60;
+pub const CREAD: u32 = 0o200;
+pub const HUPCL: u32 = 0o2000;
+
+// local flags
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+// indexes in `cc`, a character of 0 is disabled
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VREPRINT: usize = 12;
+pub const VWERASE: usize = 14;
+pub const VLNEXT: usize = 15;
+pub const VEOL2: usize = 16;
+
+// ioctl requests
+pub const TCGETS: u32 = 0x5401;
+pub const TCSETS: u32 = 0x5402;
+/// after output drained, which it always is
+pub const TCSETSW: u32 = 0x5403;
+/// and drop pending input
+pub const TCSETSF: u32 = 0x5404;
+/// pid of the foreground process, there are no process groups
+pub const TIOCGPGRP: u32 = 0x540f;
+pub const TIOCSPGRP: u32 = 0x5410;
+pub const TIOCGWINSZ: u32 = 0x5413;
+pub const TIOCSWINSZ: u32 = 0x5414;
+/// number of a pty, on its master
+pub const TIOCGPTN: u32 = 0x8004_5430;
+
+/// Control character typed with Ctrl and `letter`.
+const fn ctrl(letter: u8) -> u8 {
+    letter & 0x1f
+}
+
+/// Terminal settings, laid out like the kernel `struct termios` of linux.
+///
+/// `VTIME` and `VLNEXT` are kept but not acted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Termios {
+    /// The settings of a new terminal: canonical mode with echo and
+    /// signals, like `stty sane`.
+    pub const fn new() -> Self {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = ctrl(b'C');
+        cc[VQUIT] = ctrl(b'\\');
+        cc[VERASE] = 0x7f;
+        cc[VKILL] = ctrl(b'U');
+        cc[VEOF] = ctrl(b'D');
+        cc[VMIN] = 1;
+        cc[VSUSP] = ctrl(b'Z');
+        cc[VREPRINT] = ctrl(b'R');
+        cc[VWERASE] = ctrl(b'W');
+        cc[VLNEXT] = ctrl(b'V');
+        Termios {
+            iflag: ICRNL | IUTF8,
+            oflag: OPOST | ONLCR,
+            cflag: B38400 | CS8 | CREAD | HUPCL,
+            lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            line: 0,
+            cc,
+        }
+    }
+
+    pub fn canonical(&self) -> bool {
+        self.lflag & ICANON != 0
+    }
+
+    /// whether `c` is the enabled control character `index`
+    pub fn is(&self, index: usize, c: u8) -> bool {
+        self.cc[index] != 0 && self.cc[index] == c
+    }
+}
+
+/// Size of a terminal, `struct winsize`; programs redraw on `SIGWINCH` when it changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct WinSize {
+    pub rows: u16,
+    pub cols: u16,
+    /// pixels, unused
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
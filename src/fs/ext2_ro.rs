@@ -0,0 +1,382 @@
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
+use core::convert::TryInto;
+
+use crate::drivers::block::BlockDriver;
+
+/// sector size of the block drivers
+const SECTOR_SIZE: usize = 512;
+const SUPERBLOCK_OFFSET: usize = 1024;
+const EXT2_MAGIC: u16 = 0xef53;
+pub const ROOT_INODE: u32 = 2;
+
+/// the largest block size, 64k, as a shift of 1024
+const MAX_LOG_BLOCK_SIZE: u32 = 6;
+/// size of a revision 0 inode, the smallest there is
+const MIN_INODE_SIZE: usize = 128;
+const GROUP_DESC_SIZE: usize = 32;
+const DIRECT_BLOCKS: usize = 12;
+const INDIRECT_BLOCK: usize = 12;
+const DOUBLE_INDIRECT_BLOCK: usize = 13;
+
+const MBR_SIGNATURE: u16 = 0xaa55;
+const MBR_PARTITIONS: usize = 446;
+const MBR_ENTRY_SIZE: usize = 16;
+
+/// inode mode type bits
+const MODE_TYPE_MASK: u16 = 0xf000;
+const MODE_DIR: u16 = 0x4000;
+
+/// the little endian u16 at `at`, none past the end of `buf`
+fn u16_at(buf: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(buf.get(at..at.checked_add(2)?)?.try_into().unwrap()))
+}
+
+/// the little endian u32 at `at`, none past the end of `buf`
+fn u32_at(buf: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(at..at.checked_add(4)?)?.try_into().unwrap()))
+}
+
+/// The fields of an inode needed to read it.
+pub struct Inode {
+    pub mode: u16,
+    pub size: u32,
+    block: [u32; 15],
+}
+
+impl Inode {
+    pub fn is_dir(&self) -> bool {
+        self.mode & MODE_TYPE_MASK == MODE_DIR
+    }
+}
+
+pub struct DirEntry {
+    pub inode: u32,
+    /// 1 file, 2 directory, 7 symlink, as stored by ext2 revision 1
+    pub file_type: u8,
+    pub name: String,
+}
+
+/// Read only access to an ext2 file system on a block device,
+/// enough to walk paths and list directories.
+pub struct Ext2 {
+    dev: Arc<dyn BlockDriver>,
+    /// first sector of the file system on `dev`
+    start: usize,
+    block_size: usize,
+    first_data_block: usize,
+    inodes_count: u32,
+    inodes_per_group: usize,
+    inode_size: usize,
+}
+
+impl Ext2 {
+    /// Find an ext2 file system on `dev`, on the whole disk or in one of
+    /// its primary MBR partitions.
+    pub fn find(dev: Arc<dyn BlockDriver>) -> Option<Self> {
+        if let Some(fs) = Ext2::open(dev.clone(), 0) {
+            return Some(fs);
+        }
+        let mut mbr = [0u8; SECTOR_SIZE];
+        dev.read_at(0, &mut mbr);
+        if u16_at(&mbr, SECTOR_SIZE - 2) != Some(MBR_SIGNATURE) {
+            return None;
+        }
+        (0..4)
+            .filter_map(|i| u32_at(&mbr, MBR_PARTITIONS + i * MBR_ENTRY_SIZE + 8))
+            .map(|lba| lba as usize)
+            .filter(|&lba| lba != 0)
+            .find_map(|lba| Ext2::open(dev.clone(), lba))
+    }
+
+    /// Open the file system starting at sector `start`, if there is one
+    /// with a superblock this driver can use.
+    pub fn open(dev: Arc<dyn BlockDriver>, start: usize) -> Option<Self> {
+        let mut sb = [0u8; 1024];
+        for (i, sector) in sb.chunks_mut(SECTOR_SIZE).enumerate() {
+            dev.read_at(start + SUPERBLOCK_OFFSET / SECTOR_SIZE + i, sector);
+        }
+        if u16_at(&sb, 56)? != EXT2_MAGIC {
+            return None;
+        }
+        let log_block_size = u32_at(&sb, 24)?;
+        if log_block_size > MAX_LOG_BLOCK_SIZE {
+            return None;
+        }
+        let block_size = 1024 << log_block_size;
+        let inodes_per_group = u32_at(&sb, 40)? as usize;
+        let inode_size = if u32_at(&sb, 76)? >= 1 { u16_at(&sb, 88)? as usize } else { MIN_INODE_SIZE };
+        // a power of two no larger than a block never straddles two blocks
+        if inodes_per_group == 0 || !inode_size.is_power_of_two()
+            || !(MIN_INODE_SIZE..=block_size).contains(&inode_size)
+        {
+            return None;
+        }
+        Some(Ext2 {
+            dev,
+            start,
+            block_size,
+            first_data_block: u32_at(&sb, 20)? as usize,
+            inodes_count: u32_at(&sb, 0)?,
+            inodes_per_group,
+            inode_size,
+        })
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn read_block(&self, block: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; self.block_size];
+        let sectors = self.block_size / SECTOR_SIZE;
+        for (i, sector) in buf.chunks_mut(SECTOR_SIZE).enumerate() {
+            self.dev.read_at(self.start + block * sectors + i, sector);
+        }
+        buf
+    }
+
+    /// Inode `ino`, none if there is no such inode.
+    pub fn inode(&self, ino: u32) -> Option<Inode> {
+        if ino == 0 || ino > self.inodes_count {
+            return None;
+        }
+        let index = ino as usize - 1;
+        let (group, index) = (index / self.inodes_per_group, index % self.inodes_per_group);
+        // the descriptor table follows the superblock
+        let per_block = self.block_size / GROUP_DESC_SIZE;
+        let table = self.read_block(self.first_data_block + 1 + group / per_block);
+        let inode_table = u32_at(&table, group % per_block * GROUP_DESC_SIZE + 8)? as usize;
+
+        let offset = index * self.inode_size;
+        let block = self.read_block(inode_table + offset / self.block_size);
+        let raw = &block[offset % self.block_size..];
+        let mut blocks = [0u32; 15];
+        for (i, b) in blocks.iter_mut().enumerate() {
+            *b = u32_at(raw, 40 + i * 4)?;
+        }
+        Some(Inode { mode: u16_at(raw, 0)?, size: u32_at(raw, 4)?, block: blocks })
+    }
+
+    /// block numbers in an indirect block, up to the first hole
+    fn indirect(&self, block: u32) -> Vec<u32> {
+        if block == 0 {
+            return Vec::new();
+        }
+        let buf = self.read_block(block as usize);
+        buf.chunks_exact(4).filter_map(|b| u32_at(b, 0)).take_while(|&b| b != 0).collect()
+    }
+
+    /// Data blocks of `inode`, triple indirect ones are not followed.
+    fn data_blocks(&self, inode: &Inode) -> Vec<u32> {
+        let count = (inode.size as usize + self.block_size - 1) / self.block_size;
+        let mut blocks: Vec<u32> = inode.block[..DIRECT_BLOCKS].to_vec();
+        if count > blocks.len() {
+            blocks.extend(self.indirect(inode.block[INDIRECT_BLOCK]));
+        }
+        if count > blocks.len() {
+            for b in self.indirect(inode.block[DOUBLE_INDIRECT_BLOCK]) {
+                blocks.extend(self.indirect(b));
+            }
+        }
+        blocks.truncate(count);
+        blocks
+    }
+
+    /// Read all of a file into memory.
+    pub fn read_file(&self, inode: &Inode) -> Vec<u8> {
+        let mut data = Vec::with_capacity(inode.size as usize);
+        for b in self.data_blocks(inode) {
+            data.extend(self.read_block(b as usize));
+        }
+        data.truncate(inode.size as usize);
+        data
+    }
+
+    pub fn read_dir(&self, inode: &Inode) -> Vec<DirEntry> {
+        let mut entries = Vec::new();
+        for b in self.data_blocks(inode) {
+            let block = self.read_block(b as usize);
+            let mut at = 0;
+            // an entry is the inode, rec_len, name_len and file type, then the name
+            while let (Some(ino), Some(rec_len), Some(&[name_len, file_type])) =
+                (u32_at(&block, at), u16_at(&block, at + 4), block.get(at + 6..at + 8))
+            {
+                let rec_len = rec_len as usize;
+                if rec_len < 8 {
+                    break;
+                }
+                let name = &block[at + 8..];
+                let name = &name[..(name_len as usize).min(name.len())];
+                if ino != 0 {
+                    entries.push(DirEntry {
+                        inode: ino,
+                        file_type,
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    });
+                }
+                at += rec_len;
+            }
+        }
+        entries
+    }
+
+    /// Inode number of the absolute or root relative `path`.
+    pub fn lookup(&self, path: &str) -> Option<u32> {
+        let mut ino = ROOT_INODE;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let dir = self.inode(ino)?;
+            if !dir.is_dir() {
+                return None;
+            }
+            ino = self.read_dir(&dir).into_iter().find(|e| e.name == name)?.inode;
+        }
+        Some(ino)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+
+    use crate::drivers::{DeviceType, Driver};
+
+    use super::*;
+
+    const BLOCK: usize = 1024;
+    const INODE_TABLE: usize = 5;
+    const ROOT_DIR_BLOCK: usize = 10;
+    const FILE_BLOCK: usize = 11;
+    const FILE_INODE: u32 = 12;
+
+    /// A disk image in memory, zeros past its end.
+    struct MemDisk(Vec<u8>);
+
+    impl Driver for MemDisk {
+        fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+            false
+        }
+
+        fn device_type(&self) -> DeviceType {
+            DeviceType::Block
+        }
+
+        fn get_id(&self) -> String {
+            String::from("memdisk")
+        }
+    }
+
+    impl BlockDriver for MemDisk {
+        fn read_at(&self, block_id: usize, buf: &mut [u8]) {
+            let at = block_id * SECTOR_SIZE;
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = self.0.get(at + i).copied().unwrap_or(0);
+            }
+        }
+
+        fn write_at(&self, _block_id: usize, _buf: &[u8]) {}
+    }
+
+    fn put(image: &mut [u8], at: usize, bytes: &[u8]) {
+        image[at..at + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_inode(image: &mut [u8], ino: u32, mode: u16, size: u32, block: u32) {
+        let at = INODE_TABLE * BLOCK + (ino as usize - 1) * 128;
+        put(image, at, &mode.to_le_bytes());
+        put(image, at + 4, &size.to_le_bytes());
+        put(image, at + 40, &block.to_le_bytes());
+    }
+
+    fn put_dir_entry(image: &mut [u8], at: usize, ino: u32, rec_len: u16, file_type: u8, name: &str) {
+        put(image, at, &ino.to_le_bytes());
+        put(image, at + 4, &rec_len.to_le_bytes());
+        put(image, at + 6, &[name.len() as u8, file_type]);
+        put(image, at + 8, name.as_bytes());
+    }
+
+    /// 1k blocks, 16 inodes, a root directory holding the file `hello`.
+    fn image() -> Vec<u8> {
+        let mut image = vec![0u8; 16 * BLOCK];
+        let sb = SUPERBLOCK_OFFSET;
+        put(&mut image, sb, &16u32.to_le_bytes());
+        put(&mut image, sb + 20, &1u32.to_le_bytes());
+        put(&mut image, sb + 24, &0u32.to_le_bytes());
+        put(&mut image, sb + 40, &16u32.to_le_bytes());
+        put(&mut image, sb + 56, &EXT2_MAGIC.to_le_bytes());
+        put(&mut image, sb + 76, &1u32.to_le_bytes());
+        put(&mut image, sb + 88, &128u16.to_le_bytes());
+        // the group descriptors follow the superblock's block
+        put(&mut image, 2 * BLOCK + 8, &(INODE_TABLE as u32).to_le_bytes());
+        put_inode(&mut image, ROOT_INODE, MODE_DIR | 0o755, BLOCK as u32, ROOT_DIR_BLOCK as u32);
+        put_inode(&mut image, FILE_INODE, 0x8000 | 0o644, 3, FILE_BLOCK as u32);
+        let dir = ROOT_DIR_BLOCK * BLOCK;
+        put_dir_entry(&mut image, dir, ROOT_INODE, 12, 2, ".");
+        put_dir_entry(&mut image, dir + 12, ROOT_INODE, 12, 2, "..");
+        put_dir_entry(&mut image, dir + 24, FILE_INODE, (BLOCK - 24) as u16, 1, "hello");
+        put(&mut image, FILE_BLOCK * BLOCK, b"hi\n");
+        image
+    }
+
+    fn open(image: Vec<u8>) -> Option<Ext2> {
+        Ext2::find(Arc::new(MemDisk(image)))
+    }
+
+    #[test_case]
+    fn reads_files() {
+        let fs = open(image()).unwrap();
+        assert_eq!(fs.block_size(), BLOCK);
+        let root = fs.inode(ROOT_INODE).unwrap();
+        assert!(root.is_dir());
+        let names: Vec<String> = fs.read_dir(&root).into_iter().map(|e| e.name).collect();
+        assert_eq!(names, [".", "..", "hello"]);
+        assert_eq!(fs.lookup("/hello"), Some(FILE_INODE));
+        assert_eq!(fs.read_file(&fs.inode(FILE_INODE).unwrap()), b"hi\n");
+        assert_eq!(fs.lookup("/missing"), None);
+        assert_eq!(fs.lookup("/hello/x"), None);
+    }
+
+    #[test_case]
+    fn rejects_bad_superblocks() {
+        let sb = SUPERBLOCK_OFFSET;
+        let broken: [(usize, &[u8]); 6] = [
+            (sb + 56, &[0, 0]),
+            // inodes per group, the divisor of an inode number
+            (sb + 40, &[0, 0, 0, 0]),
+            // a block size shift that overflows
+            (sb + 24, &[30, 0, 0, 0]),
+            // inode sizes of 0, larger than a block, or not a power of two
+            (sb + 88, &[0, 0]),
+            (sb + 88, &[0, 8]),
+            (sb + 88, &[200, 0]),
+        ];
+        for &(at, bytes) in broken.iter() {
+            let mut image = image();
+            put(&mut image, at, bytes);
+            assert!(open(image).is_none());
+        }
+    }
+
+    #[test_case]
+    fn rejects_bad_inode_numbers() {
+        let fs = open(image()).unwrap();
+        assert!(fs.inode(0).is_none());
+        assert!(fs.inode(17).is_none());
+        assert!(fs.inode(16).is_some());
+    }
+
+    #[test_case]
+    fn clips_directory_entries() {
+        let mut image = image();
+        let last = ROOT_DIR_BLOCK * BLOCK + 24;
+        // a name running past the end of the block, then a record past it
+        put(&mut image, last + 6, &[255]);
+        put(&mut image, last + 4, &(BLOCK as u16 - 30).to_le_bytes());
+        let next = ROOT_DIR_BLOCK * BLOCK + BLOCK - 6;
+        put(&mut image, next, &FILE_INODE.to_le_bytes());
+        put(&mut image, next + 4, &100u16.to_le_bytes());
+        let fs = open(image).unwrap();
+        let entries = fs.read_dir(&fs.inode(ROOT_INODE).unwrap());
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].name.len(), BLOCK - 24 - 8);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ext2;
+pub mod ext2_ro;
 
 pub struct SuperBlock {
     
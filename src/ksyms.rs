@@ -0,0 +1,83 @@
+use core::{convert::TryInto, ptr::read_volatile};
+
+/// room for the symbol entries and names
+const KSYMS_SIZE: usize = 1024 * 1024;
+
+/// bytes per entry: address u64, size u32, name offset u32, name length u32
+const ENTRY_SIZE: usize = 20;
+
+/// Symbol table embedded in the kernel image.
+///
+/// only the header is set at compile time, `tools/ksyms.py` fills in the
+/// function symbols of the linked kernel, sorted by address, with the names
+/// following the entries
+#[repr(C)]
+pub struct SymbolTable {
+    magic: [u8; 4],
+    count: u32,
+    data: [u8; KSYMS_SIZE],
+}
+
+/// written by the post link step, so it must not be folded as all zeros
+#[no_mangle]
+#[used]
+#[link_section = ".ksyms"]
+static mut KSYMS: SymbolTable = SymbolTable {
+    magic: *b"KSYM",
+    count: 0,
+    data: [0; KSYMS_SIZE],
+};
+
+fn table() -> (&'static [u8], usize) {
+    unsafe {
+        let count = read_volatile(&KSYMS.count) as usize;
+        (&KSYMS.data, count.min(KSYMS_SIZE / ENTRY_SIZE))
+    }
+}
+
+fn entry(data: &[u8], i: usize) -> (usize, usize, usize, usize) {
+    let e = &data[i * ENTRY_SIZE..(i + 1) * ENTRY_SIZE];
+    let u32_at = |at: usize| u32::from_le_bytes(e[at..at + 4].try_into().unwrap()) as usize;
+    (u64::from_le_bytes(e[..8].try_into().unwrap()) as usize, u32_at(8), u32_at(12), u32_at(16))
+}
+
+/// number of symbols, 0 when the kernel was not run through `tools/ksyms.py`
+pub fn symbol_count() -> usize {
+    table().1
+}
+
+/// Name of the function containing `addr` and the offset of `addr` in it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let (data, count) = table();
+    // last symbol starting at or below addr
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        if entry(data, mid).0 <= addr {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    let (start, size, name_off, name_len) = entry(data, lo.checked_sub(1)?);
+    if size != 0 && addr >= start + size {
+        return None;
+    }
+    let name = data.get(name_off..name_off + name_len)?;
+    Some((core::str::from_utf8(name).ok()?, addr - start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn finds_functions() {
+        // `tools/run.py` fills in test kernels too
+        assert!(symbol_count() != 0);
+        let addr = finds_functions as usize;
+        let (name, offset) = lookup(addr + 1).unwrap();
+        assert!(name.ends_with("ksyms::tests::finds_functions"), "{}", name);
+        assert_eq!(offset, 1);
+    }
+}
@@ -1,9 +1,9 @@
 #[macro_export]
 macro_rules! print {
-    ($($arg:tt)*) => ($crate::arch::vga::_print(format_args!($($arg)*)));
+    ($($arg:tt)*) => ($crate::console::_print(format_args!($($arg)*)));
 }
 
-/// Like the `println!` macro in the standard library, but prints to the VGA text buffer.
+/// Like the `println!` macro in the standard library, but prints to the screen console and COM1.
 #[macro_export]
 macro_rules! println {
     () => ($crate::print!("\n"));
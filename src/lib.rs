@@ -7,6 +7,7 @@
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(const_fn)]
 #![feature(llvm_asm)]
 #![feature(naked_functions)]
@@ -24,13 +25,51 @@ pub mod consts;
 pub mod memory;
 pub mod fs;
 pub mod process;
+pub mod time;
+pub mod logging;
+pub mod ksyms;
+pub mod shell;
+pub mod console;
 
 #[path = "arch/x86_64/mod.rs"]
 pub mod arch;
 pub mod sync;
 pub mod drivers;
 
-use core::panic::PanicInfo;
+use core::{panic::PanicInfo, sync::atomic::{AtomicBool, Ordering}};
+
+use arch::power::{QemuExitCode, exit_qemu};
+
+/// A `#[test_case]`, a function that panics on failure.
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        print!("{}... ", core::any::type_name::<T>());
+        self();
+        println!("ok");
+    }
+}
+
+/// Runs the `#[test_case]`s, reporting on the console and COM1, and exits qemu
+/// with success once all passed.
+pub fn test_runner(tests: &[&dyn Testable]) {
+    println!("running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    println!("all tests passed");
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Panic handler of the test kernels: the failed test ends the run.
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    PANICKING.store(true, Ordering::SeqCst);
+    println!("failed: {}", info);
+    exit_qemu(QemuExitCode::Failed)
+}
 
 #[cfg(test)]
 #[panic_handler]
@@ -43,9 +82,22 @@ fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
     panic!("allocation error: {:?}", layout)
 }
 
+/// set by the first panic, a panic while printing the backtrace must not recurse
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// a panic is being reported, output has to go out without interrupts
+pub fn panicking() -> bool {
+    PANICKING.load(Ordering::Relaxed)
+}
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    let first = !PANICKING.swap(true, Ordering::SeqCst);
     println!("{}", info);
+    if first {
+        arch::backtrace::print_backtrace();
+    }
+    arch::serial::flush();
     loop {}
 }
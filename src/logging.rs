@@ -0,0 +1,271 @@
+use alloc::{string::{String, ToString}, vec::Vec};
+use core::{fmt::{self, Write}, str::FromStr, sync::atomic::{AtomicBool, AtomicUsize, Ordering}};
+use log::{LevelFilter, Log, Metadata, Record};
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::{arch::{cpu::try_cpu_id, serial::com1}, console::vt::VTS, consts::{DMESG_SIZE, LOG_LEVEL, LOG_VT}, sync::mutex::SpinNoIrqLock, time::monotonic_ns};
+
+/// longest line kept, longer records are cut
+const MAX_LINE: usize = 256;
+
+/// Fixed size buffer a record is formatted into, usable before the heap
+/// and from interrupt context.
+struct LineBuf {
+    buf: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl LineBuf {
+    fn new() -> Self {
+        LineBuf { buf: [0; MAX_LINE], len: 0 }
+    }
+
+    fn as_str(&self) -> &str {
+        // only ever cut at a char boundary in `write_str`
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl fmt::Write for LineBuf {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // keep room for the newline
+        let room = MAX_LINE - 1 - self.len;
+        let mut n = s.len().min(room);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Kernel message ring, the oldest lines are overwritten when it is full.
+struct LogBuffer {
+    buf: [u8; DMESG_SIZE],
+    /// total bytes ever written, the next one goes to `written % DMESG_SIZE`
+    written: usize,
+}
+
+impl LogBuffer {
+    const fn new() -> Self {
+        LogBuffer { buf: [0; DMESG_SIZE], written: 0 }
+    }
+
+    fn push(&mut self, line: &str) {
+        for &b in line.as_bytes() {
+            self.buf[self.written % DMESG_SIZE] = b;
+            self.written += 1;
+        }
+    }
+
+    /// Copy out the whole lines still in the buffer, oldest first.
+    fn read(&self) -> String {
+        let start = self.written.saturating_sub(DMESG_SIZE);
+        let bytes: Vec<u8> = (start..self.written).map(|i| self.buf[i % DMESG_SIZE]).collect();
+        let mut text = String::from_utf8_lossy(&bytes).into_owned();
+        if start != 0 {
+            // the first line lost its beginning
+            let cut = text.find('\n').map_or(text.len(), |i| i + 1);
+            text.replace_range(..cut, "");
+        }
+        text
+    }
+}
+
+static DMESG: SpinNoIrqLock<LogBuffer> = SpinNoIrqLock::new(LogBuffer::new());
+
+/// A console the records are copied to.
+struct Sink {
+    name: &'static str,
+    enabled: AtomicBool,
+    write: fn(&str),
+}
+
+fn screen_write(line: &str) {
+    VTS[LOG_VT].write_str(line);
+}
+
+fn serial_write(line: &str) {
+    com1().write_str(line);
+}
+
+static SINKS: [Sink; 2] = [
+    Sink { name: "screen", enabled: AtomicBool::new(true), write: screen_write },
+    Sink { name: "serial", enabled: AtomicBool::new(true), write: serial_write },
+];
+
+/// Turn the console `name` ("screen" or "serial") on or off, false if there is no such sink.
+pub fn set_sink_enabled(name: &str, enabled: bool) -> bool {
+    match SINKS.iter().find(|s| s.name == name) {
+        Some(sink) => {
+            sink.enabled.store(enabled, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// level for modules without a filter of their own
+static DEFAULT_LEVEL: AtomicUsize = AtomicUsize::new(LOG_LEVEL as usize);
+
+/// records up to this level also go to the consoles, the others only to the ring
+static CONSOLE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Trace as usize);
+
+lazy_static::lazy_static! {
+    /// (module path prefix, level), read from interrupt context
+    static ref FILTERS: RwLock<Vec<(String, LevelFilter)>> = RwLock::new(Vec::new());
+}
+
+fn level_filter(n: usize) -> LevelFilter {
+    match n {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Error,
+        2 => LevelFilter::Warn,
+        3 => LevelFilter::Info,
+        4 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// whether `target` is `module` or inside it
+fn in_module(target: &str, module: &str) -> bool {
+    target.starts_with(module)
+        && (target.len() == module.len() || target[module.len()..].starts_with("::"))
+}
+
+/// level that applies to `target`, the most specific filter wins
+fn level_for(target: &str) -> LevelFilter {
+    FILTERS.read()
+        .iter()
+        .filter(|(module, _)| in_module(target, module))
+        .max_by_key(|(module, _)| module.len())
+        .map_or_else(|| level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed)), |&(_, level)| level)
+}
+
+/// let the `log` macros skip anything no filter would let through
+fn update_max_level() {
+    let max = FILTERS.read()
+        .iter()
+        .map(|&(_, level)| level)
+        .fold(level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed)), |a, b| a.max(b));
+    log::set_max_level(max);
+}
+
+pub fn set_default_level(level: LevelFilter) {
+    DEFAULT_LEVEL.store(level as usize, Ordering::Relaxed);
+    update_max_level();
+}
+
+pub fn set_console_level(level: LevelFilter) {
+    CONSOLE_LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Log `module` and its submodules at `level`.
+pub fn set_module_level(module: &str, level: LevelFilter) {
+    without_interrupts(|| {
+        let mut filters = FILTERS.write();
+        match filters.iter_mut().find(|(m, _)| m == module) {
+            Some(f) => f.1 = level,
+            None => filters.push((module.to_string(), level)),
+        }
+    });
+    update_max_level();
+}
+
+/// Apply a filter spec like `info,myos::arch::pci=debug`, a bare level sets
+/// the default. returns the directive that could not be parsed, if any
+pub fn apply_filter_spec(spec: &str) -> Result<(), String> {
+    for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        let mut parts = directive.splitn(2, '=');
+        let first = parts.next().unwrap_or("");
+        match (parts.next(), LevelFilter::from_str(first)) {
+            (None, Ok(level)) => set_default_level(level),
+            (None, Err(_)) => set_module_level(first, LevelFilter::Trace),
+            (Some(level), _) => match LevelFilter::from_str(level) {
+                Ok(level) => set_module_level(first, level),
+                Err(_) => return Err(directive.to_string()),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Everything still in the kernel message ring.
+pub fn dmesg() -> String {
+    DMESG.lock().read()
+}
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let ns = monotonic_ns();
+        let mut line = LineBuf::new();
+        let _ = write!(line, "[{:5}.{:06}] ", ns / 1_000_000_000, ns % 1_000_000_000 / 1000);
+        match try_cpu_id() {
+            Some(cpu) => { let _ = write!(line, "cpu{} ", cpu); }
+            None => { let _ = write!(line, "cpu? "); }
+        }
+        let _ = write!(line, "{:5} {}: {}", record.level(), record.target(), record.args());
+        line.buf[line.len] = b'\n';
+        line.len += 1;
+
+        let text = line.as_str();
+        DMESG.lock().push(text);
+        if record.level() as usize <= CONSOLE_LEVEL.load(Ordering::Relaxed) {
+            for sink in SINKS.iter().filter(|s| s.enabled.load(Ordering::Relaxed)) {
+                (sink.write)(text);
+            }
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: KernelLogger = KernelLogger;
+
+/// Install the kernel logger, records before this are dropped.
+///
+/// needs neither the heap nor the per-cpu area, so it can run first thing
+pub fn init_logger() {
+    if log::set_logger(&LOGGER).is_ok() {
+        update_max_level();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn filter_spec() {
+        apply_filter_spec("ftest=debug, ftest::inner=off,ftest_bare").unwrap();
+        assert_eq!(level_for("ftest"), LevelFilter::Debug);
+        assert_eq!(level_for("ftest::a::b"), LevelFilter::Debug);
+        // the most specific filter wins
+        assert_eq!(level_for("ftest::inner::c"), LevelFilter::Off);
+        // a bare module logs everything
+        assert_eq!(level_for("ftest_bare::x"), LevelFilter::Trace);
+        assert_eq!(apply_filter_spec("ftest=loud"), Err(String::from("ftest=loud")));
+        assert_eq!(level_for("ftest"), LevelFilter::Debug);
+    }
+
+    #[test_case]
+    fn default_level() {
+        let old = level_filter(DEFAULT_LEVEL.load(Ordering::Relaxed));
+        apply_filter_spec("warn").unwrap();
+        // a prefix is not a parent module
+        assert_eq!(level_for("ftestx"), LevelFilter::Warn);
+        set_default_level(old);
+    }
+}
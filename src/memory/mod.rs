@@ -2,14 +2,39 @@
 pub mod addr;
 pub mod bitalloc;
 
+use core::{alloc::{GlobalAlloc, Layout}, ops::Deref};
+
 use bitalloc::BitAlloc1M;
 use lazy_static::lazy_static;
 use linked_list_allocator::LockedHeap;
+use x86_64::instructions::interrupts::without_interrupts;
 
 use spin::Mutex;
 
 #[global_allocator]
-pub static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub static HEAP_ALLOCATOR: IrqSafeHeap = IrqSafeHeap(LockedHeap::empty());
+
+/// `LockedHeap` that keeps interrupts off while holding its lock,
+/// so interrupt handlers (the scheduler) can allocate
+pub struct IrqSafeHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for IrqSafeHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        without_interrupts(|| self.0.alloc(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        without_interrupts(|| self.0.dealloc(ptr, layout))
+    }
+}
+
+impl Deref for IrqSafeHeap {
+    type Target = LockedHeap;
+
+    fn deref(&self) -> &LockedHeap {
+        &self.0
+    }
+}
 
 lazy_static!(
     pub static ref BITMAP_ALLOCATOR: Mutex<BitAlloc1M> = Mutex::new(BitAlloc1M::default());
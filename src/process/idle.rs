@@ -0,0 +1,55 @@
+use core::sync::atomic::{Ordering, fence};
+use x86_64::instructions::interrupts;
+
+use crate::{arch::{cpu::cpu_id, timer::{enter_tickless, exit_tickless}}, time::{self, ticks, timer::next_expiry}};
+
+use super::{proc::reap_zombies, runqueue::run_queue};
+
+/// longest tickless sleep, so idle cpus still look for work to steal now and then
+const MAX_IDLE_TICKS: u64 = 100;
+
+/// Body of the idle process of every cpu.
+///
+/// when the run queue is empty the periodic tick is stopped until the next
+/// timer is due (only the bsp runs timers), otherwise the cpu halts until
+/// the next tick switches to the queued work
+pub fn idle_loop() -> ! {
+    loop {
+        reap_zombies();
+        interrupts::disable();
+        let cpu = cpu_id();
+        let sleep = if run_queue(cpu).len() != 0 {
+            0
+        } else if cpu == 0 {
+            next_expiry()
+                .map_or(MAX_IDLE_TICKS, |t| t.saturating_sub(ticks()))
+                .min(MAX_IDLE_TICKS)
+        } else {
+            MAX_IDLE_TICKS
+        };
+        if sleep <= 1 {
+            // something is due at the next tick anyway
+            interrupts::enable_and_hlt();
+            continue;
+        }
+        // tickless before looking at the queue again: an `enqueue` that
+        // came after the first look either shows up now or sees the flag
+        // and kicks us
+        enter_tickless(sleep);
+        fence(Ordering::SeqCst);
+        if run_queue(cpu).len() != 0 {
+            leave_tickless(cpu);
+            continue;
+        }
+        interrupts::enable_and_hlt();
+        interrupts::without_interrupts(|| leave_tickless(cpu));
+    }
+}
+
+/// Restart the tick, the bsp catches up on the ticks it skipped.
+fn leave_tickless(cpu: usize) {
+    let elapsed = exit_tickless();
+    if cpu == 0 && elapsed > 0 {
+        time::advance(elapsed);
+    }
+}
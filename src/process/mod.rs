@@ -1,9 +1,37 @@
+use alloc::sync::Arc;
 use spin::RwLock;
 
+use crate::arch::cpu::this_cpu;
 
+use self::proc::Process;
 
 pub mod thread;
 pub mod proc;
+pub mod runqueue;
+pub mod sched;
+pub mod idle;
+pub mod signal;
 
 
-pub static SCHEDULE: RwLock<bool> = RwLock::new(false);
\ No newline at end of file
+pub static SCHEDULE: RwLock<bool> = RwLock::new(false);
+
+/// process running on the calling cpu
+pub fn current() -> Option<Arc<Process>> {
+    this_cpu().current()
+}
+
+/// whether `proc` is the idle process of the calling cpu
+pub fn is_idle(proc: &Arc<Process>) -> bool {
+    this_cpu().idle().map_or(false, |idle| Arc::ptr_eq(&idle, proc))
+}
+
+/// Make `idle` the idle process of the calling cpu.
+///
+/// it is the context the cpu booted in, and runs whenever
+/// the run queue of the cpu is empty and nothing can be stolen
+pub fn init_cpu(idle: Arc<Process>) {
+    let cpu = this_cpu();
+    idle.set_cpu(cpu.id());
+    cpu.set_idle(Some(idle.clone()));
+    cpu.set_current(Some(idle));
+}
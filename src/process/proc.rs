@@ -1,73 +1,342 @@
-use alloc::{collections::BTreeMap, vec::Vec};
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
+use alloc::vec::Vec;
 use spin::RwLock;
 
-use crate::{arch::interrupt::ctx::Context, consts::{MAX_CPU_NUM, MAX_PROCESS_NUM}};
+use crate::{arch::{consts::PAGE_SIZE, cpu::{halt, this_cpu}, interrupt::ctx::Context}, consts::MAX_PROCESS_NUM, drivers::tty::Tty, memory::{BITMAP_ALLOCATOR, addr::phys_to_virt, bitalloc::BitAlloc}, sync::mutex::{MutexGuard, SpinNoIrq, SpinNoIrqLock}};
 
+use super::{current, runqueue::{enqueue, CpuMask, CPU_MASK_ALL}, signal::Signal, sched::{SchedAttr, SchedEntity, SchedPolicy, fair::{MAX_NICE, MIN_NICE}, priority::PRIORITY_LEVELS}};
+
+/// kernel stack of each thread, in 4k frames
+const KERNEL_STACK_FRAMES: usize = 4;
 
 pub struct Process {
-    /// arch specific context
-    pub ctx: Context,
     pid: usize,
-    state: ProcessState,
     is_kernel: bool,
+    /// arch specific context, saved here while the process is not running
+    ctx: SpinNoIrqLock<Context>,
+    state: SpinNoIrqLock<ProcessState>,
+    /// cpus this process may run on, one bit per logical cpu id
+    affinity: AtomicU64,
+    /// cpu whose run queue the process was last put on
+    cpu: AtomicUsize,
+    /// first frame of the kernel stack, if we allocated one
+    stack: Option<usize>,
+    sched_attr: SpinNoIrqLock<SchedAttr>,
+    sched: SpinNoIrqLock<SchedEntity>,
+    /// controlling terminal
+    ctty: SpinNoIrqLock<Option<Arc<Tty>>>,
+    /// `Signal::bit`s of the signals sent and not taken yet
+    signals: AtomicU64,
 }
 
 impl Process {
-    pub fn set_ctx(&mut self, ctx: Context) {
-        self.ctx = ctx;
+    fn new(pid: usize, ctx: Context, stack: Option<usize>) -> Self {
+        Process {
+            pid,
+            is_kernel: true,
+            ctx: SpinNoIrqLock::new(ctx),
+            state: SpinNoIrqLock::new(ProcessState::Ready),
+            affinity: AtomicU64::new(CPU_MASK_ALL),
+            cpu: AtomicUsize::new(0),
+            stack,
+            sched_attr: SpinNoIrqLock::new(SchedAttr::default()),
+            sched: SpinNoIrqLock::new(SchedEntity::default()),
+            ctty: SpinNoIrqLock::new(None),
+            signals: AtomicU64::new(0),
+        }
+    }
+
+    pub fn pid(&self) -> usize {
+        self.pid
+    }
+
+    pub fn is_kernel(&self) -> bool {
+        self.is_kernel
+    }
+
+    pub fn ctx(&self) -> Context {
+        *self.ctx.lock()
+    }
+
+    pub fn set_ctx(&self, ctx: Context) {
+        *self.ctx.lock() = ctx;
+    }
+
+    pub fn state(&self) -> ProcessState {
+        *self.state.lock()
+    }
+
+    pub fn set_state(&self, state: ProcessState) {
+        *self.state.lock() = state;
+    }
+
+    /// Called by the scheduler when switching away from a process in `Wait`.
+    ///
+    /// returns false if it was woken up before it could be switched out,
+    /// in which case it stays runnable
+    pub(super) fn park(&self) -> bool {
+        let mut state = self.state.lock();
+        if *state == ProcessState::Wait {
+            *state = ProcessState::Sleeping;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn affinity(&self) -> CpuMask {
+        self.affinity.load(Ordering::Relaxed)
+    }
+
+    /// Restrict the process to the cpus in `mask`,
+    /// it is moved to an allowed cpu the next time it is scheduled.
+    pub fn set_affinity(&self, mask: CpuMask) {
+        assert!(mask != 0, "empty cpu affinity mask");
+        self.affinity.store(mask, Ordering::Relaxed);
+    }
+
+    pub fn can_run_on(&self, cpu: usize) -> bool {
+        self.affinity() & (1 << cpu) != 0
+    }
+
+    pub fn cpu(&self) -> usize {
+        self.cpu.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn set_cpu(&self, cpu: usize) {
+        self.cpu.store(cpu, Ordering::Relaxed);
+    }
+
+    /// Terminal `print!` writes to, the log terminal if there is none.
+    pub fn ctty(&self) -> Option<Arc<Tty>> {
+        self.ctty.lock().clone()
+    }
+
+    pub fn set_ctty(&self, tty: Option<Arc<Tty>>) {
+        *self.ctty.lock() = tty;
+    }
+
+    pub fn pending_signals(&self) -> u64 {
+        self.signals.load(Ordering::Acquire)
+    }
+
+    /// Clears the pending signals, returning them.
+    pub fn take_signals(&self) -> u64 {
+        self.signals.swap(0, Ordering::AcqRel)
+    }
+
+    pub(super) fn add_signal(&self, signal: Signal) {
+        self.signals.fetch_or(signal.bit(), Ordering::AcqRel);
+    }
+
+    pub fn sched_attr(&self) -> SchedAttr {
+        *self.sched_attr.lock()
+    }
+
+    /// Scheduler bookkeeping of the process, owned by the scheduler it is queued on.
+    pub(super) fn sched(&self) -> MutexGuard<SchedEntity, SpinNoIrq> {
+        self.sched.lock()
+    }
+
+    /// Move the process to another scheduling class,
+    /// takes effect the next time it is queued.
+    pub fn set_policy(&self, policy: SchedPolicy) {
+        self.sched_attr.lock().policy = policy;
+    }
+
+    /// Set the nice value used by the fair scheduler, clamped to -20..=19.
+    pub fn set_nice(&self, nice: i8) {
+        self.sched_attr.lock().nice = nice.max(MIN_NICE).min(MAX_NICE);
+    }
+
+    /// Set the priority used by the priority scheduler, 0 is the highest.
+    pub fn set_priority(&self, priority: u8) {
+        assert!(priority < PRIORITY_LEVELS, "priority out of range");
+        self.sched_attr.lock().priority = priority;
+    }
+
+    /// Make the process a periodic real-time task getting `runtime` ticks
+    /// of every `period`, finished at most `deadline` ticks into the period.
+    pub fn set_deadline(&self, runtime: u64, deadline: u64, period: u64) {
+        assert!(0 < runtime && runtime <= deadline && deadline <= period, "invalid deadline parameters");
+        let mut attr = self.sched_attr.lock();
+        attr.policy = SchedPolicy::Deadline;
+        attr.runtime = runtime;
+        attr.deadline = deadline;
+        attr.period = period;
     }
 }
 
-#[derive(Debug)]
+impl Drop for Process {
+    fn drop(&mut self) {
+        if let Some(frame) = self.stack {
+            let mut b = BITMAP_ALLOCATOR.lock();
+            for i in 0..KERNEL_STACK_FRAMES {
+                b.dealloc(frame + i);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ProcessState {
     Busy,
     Wait,
-    Running
+    /// waiting in a run queue
+    Ready,
+    Running,
+    /// blocked and switched out, see `park_current`
+    Sleeping,
+    /// finished, dropped once no cpu refers to it
+    Exited,
 }
 
 lazy_static!{
     pub static ref PROCESSES: RwLock<BTreeMap<usize, Arc<Process>>> = RwLock::new(BTreeMap::new());
 }
 
+lazy_static!{
+    /// exited processes no cpu runs on any more, freed outside of
+    /// interrupt context
+    static ref ZOMBIES: SpinNoIrqLock<Vec<Arc<Process>>> = SpinNoIrqLock::new(Vec::new());
+}
+
+static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
+
+fn alloc_pid() -> usize {
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    assert!(pid < MAX_PROCESS_NUM, "out of pids");
+    pid
+}
+
 #[inline(always)]
 pub fn init_kernel_process() {
-    create_kernel_process(0);
+    super::init_cpu(create_kernel_process(0));
 }
 
-pub fn create_kernel_process(pid: usize) {
-    let proc = Process {
-        ctx: Context::default(),
-        pid,
-        state: ProcessState::Running,
-        is_kernel: true
-    };
-    PROCESSES.write().insert(pid, Arc::new(proc));
+/// Register the context the calling cpu is running in as process `pid`.
+///
+/// its context is filled in by the first timer interrupt, it is never
+/// put on a run queue and only runs when nothing else is runnable
+pub fn create_kernel_process(pid: usize) -> Arc<Process> {
+    let proc = Arc::new(Process::new(pid, Context::default(), None));
+    proc.set_state(ProcessState::Running);
+    PROCESSES.write().insert(pid, proc.clone());
+    proc
 }
 
-pub fn create_kernel_process2(pid: usize, ctx: Context) {
-    let proc = Process {
-        ctx,
-        pid,
-        state: ProcessState::Running,
-        is_kernel: true
-    };
-    PROCESSES.write().insert(pid, Arc::new(proc));
+/// A process that is not registered and never runs, for the scheduler tests.
+#[cfg(test)]
+pub(super) fn test_process(pid: usize) -> Arc<Process> {
+    Arc::new(Process::new(pid, Context::default(), None))
 }
- 
-pub fn do_print_hello() {
-    println!("hello world from context switch!");
+
+/// Like `create_kernel_process`, with a fresh pid.
+pub fn create_idle_process() -> Arc<Process> {
+    create_kernel_process(alloc_pid())
 }
 
+/// Create a kernel thread running `entry` and put it on a run queue.
+pub fn spawn_kernel_thread(entry: fn()) -> Arc<Process> {
+    reap_zombies();
+    let frame = BITMAP_ALLOCATOR.lock()
+        .alloc_contiguous(KERNEL_STACK_FRAMES, 0)
+        .expect("no memory for kernel stack");
+    let top = phys_to_virt((frame + KERNEL_STACK_FRAMES) * PAGE_SIZE);
+    // return address of `entry`, so a returning thread exits
+    let sp = top - 8;
+    unsafe { *(sp as *mut usize) = kernel_thread_exit as usize };
 
-pub fn switch_to_process1() {
-    let d = PROCESSES.read();
-    let p = d.get(&1).unwrap();
-    
+    let proc = Arc::new(Process::new(alloc_pid(), Context::new_kernel(entry as usize, sp), Some(frame)));
+    // the terminal is inherited from the spawning thread
+    if let Some(parent) = current() {
+        proc.set_ctty(parent.ctty());
+    }
+    PROCESSES.write().insert(proc.pid, proc.clone());
+    enqueue(proc.clone());
+    proc
 }
-// process list used for task scheduling
-//pub static PROCESS_LISTS: [Option<Arc<Process>>; MAX_CPU_NUM] = [None; MAX_CPU_NUM];
 
+extern "C" fn kernel_thread_exit() -> ! {
+    exit_current()
+}
 
+/// Terminate the calling process, it is not scheduled again.
+pub fn exit_current() -> ! {
+    kill_current();
+    loop {
+        halt();
+    }
+}
+
+/// Mark the calling process exited without leaving it,
+/// it is gone once the caller switches away.
+pub fn kill_current() {
+    if let Some(p) = super::current() {
+        p.set_state(ProcessState::Exited);
+        PROCESSES.write().remove(&p.pid);
+    }
+}
+
+/// Block the calling process until `wake` is called on it.
+///
+/// the caller must set its state to `Wait` before arranging for the wakeup,
+/// otherwise a wakeup arriving in between is lost
+pub fn park_current() {
+    let proc = super::current().expect("no process to park");
+    assert!(!super::is_idle(&proc), "idle process cannot block");
+    while proc.state() == ProcessState::Wait {
+        // the next timer tick switches us out, `wake` switches us back in
+        halt();
+    }
+}
+
+/// Make a process blocked in `park_current` runnable again.
+pub fn wake(proc: &Arc<Process>) {
+    let parked = {
+        let mut state = proc.state.lock();
+        match *state {
+            // not switched out yet, it simply keeps running
+            ProcessState::Wait => {
+                *state = ProcessState::Running;
+                false
+            }
+            ProcessState::Sleeping => {
+                *state = ProcessState::Ready;
+                true
+            }
+            _ => false,
+        }
+    };
+    if parked {
+        enqueue(proc.clone());
+    }
+}
+
+/// Keep an exited process alive until `reap_zombies` runs, called by the
+/// scheduler on every switch with the process it switches away from if
+/// that one exited.
+///
+/// the switch happens in an interrupt or exception that returns through a
+/// trap frame on the exited process' stack, so the process stays in the
+/// cpu's dying slot until the cpu switches again, by then from the stack of
+/// another process. the scheduler drops its reference from interrupt
+/// context, where freeing the stack could deadlock on the frame allocator
+pub(super) fn bury(exited: Option<Arc<Process>>) {
+    if let Some(proc) = this_cpu().replace_dying(exited) {
+        ZOMBIES.lock().push(proc);
+    }
+}
 
+/// Free the processes no cpu is on any more.
+pub fn reap_zombies() {
+    let zombies = core::mem::take(&mut *ZOMBIES.lock());
+    drop(zombies);
+}
+
+pub fn do_print_hello() {
+    println!("hello world from context switch!");
+}
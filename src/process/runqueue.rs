@@ -0,0 +1,198 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::lazy_static;
+
+use crate::{arch::{cpu::{cpu_id, online_cpus, this_cpu}, interrupt::ctx::Context, timer::kick}, consts::{INFORM_PER_MSEC, MAX_CPU_NUM, USEC_PER_TICK}, sync::mutex::SpinNoIrqLock, time::ticks};
+
+use super::{proc::{Process, ProcessState, bury}, sched::{ClassScheduler, Scheduler}};
+
+/// set of cpus, one bit per logical cpu id
+pub type CpuMask = u64;
+
+pub const CPU_MASK_ALL: CpuMask = !0;
+
+/// timer ticks between two load balancing passes on a cpu
+const BALANCE_INTERVAL: usize = INFORM_PER_MSEC * 1000 / USEC_PER_TICK;
+
+/// clock of the schedulers, in ticks
+#[inline]
+pub fn sched_clock() -> u64 {
+    ticks()
+}
+
+/// Processes ready to run on one cpu.
+pub struct RunQueue {
+    tasks: SpinNoIrqLock<ClassScheduler>,
+    /// number of queued processes, readable without the lock
+    len: AtomicUsize,
+    ticks: AtomicUsize,
+}
+
+impl RunQueue {
+    fn new() -> Self {
+        RunQueue {
+            tasks: SpinNoIrqLock::new(ClassScheduler::new()),
+            len: AtomicUsize::new(0),
+            ticks: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, proc: Arc<Process>, cpu: usize) {
+        proc.set_cpu(cpu);
+        proc.set_state(ProcessState::Ready);
+        let mut tasks = self.tasks.lock();
+        tasks.push(proc, sched_clock());
+        self.len.store(tasks.len(), Ordering::Relaxed);
+    }
+
+    /// Take the next process allowed to run on `cpu`.
+    ///
+    /// processes whose affinity no longer includes `cpu` are handed back
+    /// so the caller can move them elsewhere
+    fn pop(&self, cpu: usize, misplaced: &mut Vec<Arc<Process>>) -> Option<Arc<Process>> {
+        let mut tasks = self.tasks.lock();
+        let mut next = None;
+        while let Some(p) = tasks.pop(sched_clock()) {
+            if p.can_run_on(cpu) {
+                next = Some(p);
+                break;
+            }
+            misplaced.push(p);
+        }
+        self.len.store(tasks.len(), Ordering::Relaxed);
+        next
+    }
+
+    /// Take a process that may run on `thief`.
+    ///
+    /// gives up instead of spinning when the queue is locked, two cpus
+    /// stealing from each other must not wait on one another
+    fn steal(&self, thief: usize) -> Option<Arc<Process>> {
+        let mut tasks = self.tasks.try_lock()?;
+        let proc = tasks.steal(thief);
+        self.len.store(tasks.len(), Ordering::Relaxed);
+        proc
+    }
+
+    /// whether `current` should give up the cpu after this tick
+    fn need_resched(&self, current: &Process) -> bool {
+        self.tasks.lock().tick(current, sched_clock())
+    }
+}
+
+lazy_static! {
+    static ref RUN_QUEUES: Vec<RunQueue> = (0..MAX_CPU_NUM).map(|_| RunQueue::new()).collect();
+}
+
+pub fn run_queue(cpu: usize) -> &'static RunQueue {
+    &RUN_QUEUES[cpu]
+}
+
+/// Put `proc` on the least loaded online cpu it may run on.
+pub fn enqueue(proc: Arc<Process>) {
+    let cpu = online_cpus()
+        .map(|c| c.id())
+        .filter(|&id| proc.can_run_on(id))
+        .min_by_key(|&id| RUN_QUEUES[id].len())
+        .unwrap_or_else(cpu_id);
+    RUN_QUEUES[cpu].push(proc, cpu);
+    if cpu != cpu_id() {
+        kick(cpu);
+    }
+}
+
+/// Steal one process from the busiest cpu that has one for `thief`.
+fn steal(thief: usize) -> Option<Arc<Process>> {
+    let mut victims: Vec<usize> = online_cpus()
+        .map(|c| c.id())
+        .filter(|&id| id != thief && RUN_QUEUES[id].len() > 0)
+        .collect();
+    victims.sort_by_key(|&id| core::cmp::Reverse(RUN_QUEUES[id].len()));
+    victims.into_iter().find_map(|id| RUN_QUEUES[id].steal(thief))
+}
+
+/// Pull work from the busiest cpu until both queues are about even.
+fn balance(cpu: usize) {
+    let busiest = online_cpus()
+        .map(|c| c.id())
+        .filter(|&id| id != cpu)
+        .max_by_key(|&id| RUN_QUEUES[id].len());
+    if let Some(busiest) = busiest {
+        let mine = RUN_QUEUES[cpu].len();
+        let theirs = RUN_QUEUES[busiest].len();
+        for _ in 0..theirs.saturating_sub(mine) / 2 {
+            match RUN_QUEUES[busiest].steal(cpu) {
+                Some(p) => RUN_QUEUES[cpu].push(p, cpu),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Switch processes on a timer tick.
+///
+/// `ctx` is the context of the interrupted process, it is saved and
+/// replaced by the context of the process to run next
+pub fn tick(ctx: &mut Context) {
+    let this = this_cpu();
+    let cpu = this.id();
+    let rq = &RUN_QUEUES[cpu];
+    if (rq.ticks.fetch_add(1, Ordering::Relaxed) + 1) % BALANCE_INTERVAL.max(1) == 0 {
+        balance(cpu);
+    }
+
+    let (prev, idle) = match (this.current(), this.idle()) {
+        (Some(prev), Some(idle)) => (prev, idle),
+        // this cpu has not registered its idle process yet
+        _ => return,
+    };
+    if !Arc::ptr_eq(&prev, &idle) && prev.state() == ProcessState::Running
+        && prev.can_run_on(cpu) && !rq.need_resched(&prev) {
+        return;
+    }
+    switch(prev, idle, ctx);
+}
+
+/// Give up the cpu right away, e.g. after the current process was killed.
+pub fn schedule(ctx: &mut Context) {
+    let this = this_cpu();
+    if let (Some(prev), Some(idle)) = (this.current(), this.idle()) {
+        switch(prev, idle, ctx);
+    }
+}
+
+fn switch(prev: Arc<Process>, idle: Arc<Process>, ctx: &mut Context) {
+    let this = this_cpu();
+    let cpu = this.id();
+    let rq = &RUN_QUEUES[cpu];
+    let is_idle = Arc::ptr_eq(&prev, &idle);
+    prev.set_ctx(*ctx);
+
+    let mut misplaced = Vec::new();
+    let mut exited = None;
+    if !is_idle {
+        match prev.state() {
+            ProcessState::Exited => exited = Some(prev),
+            // blocked, `wake` puts it back on a run queue
+            ProcessState::Wait if prev.park() => {}
+            _ if prev.can_run_on(cpu) => rq.push(prev, cpu),
+            _ => misplaced.push(prev),
+        }
+    }
+    let next = rq.pop(cpu, &mut misplaced)
+        .or_else(|| steal(cpu))
+        .unwrap_or(idle);
+    for p in misplaced {
+        enqueue(p);
+    }
+    bury(exited);
+
+    next.set_cpu(cpu);
+    next.set_state(ProcessState::Running);
+    *ctx = next.ctx();
+    this.set_current(Some(next));
+}
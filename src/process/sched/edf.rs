@@ -0,0 +1,171 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+
+use crate::process::proc::Process;
+
+use super::{SchedAttr, SchedEntity, Scheduler};
+
+/// Earliest deadline first scheduler for periodic real-time processes.
+///
+/// a process gets `runtime` ticks every `period`, due `deadline` ticks
+/// after the period starts. one that uses up its budget is throttled until
+/// its next period begins, as `SCHED_DEADLINE` does, so it cannot take more
+/// than `runtime / period` of the cpu and the other classes run meanwhile
+pub struct EdfScheduler {
+    /// keyed on (absolute deadline, pid)
+    tasks: BTreeMap<(u64, usize), Arc<Process>>,
+    /// out of budget, keyed on (start of the next period, pid)
+    throttled: BTreeMap<(u64, usize), Arc<Process>>,
+}
+
+impl EdfScheduler {
+    pub fn new() -> Self {
+        EdfScheduler {
+            tasks: BTreeMap::new(),
+            throttled: BTreeMap::new(),
+        }
+    }
+
+    /// Queue the throttled processes whose next period started by `now`.
+    fn replenish(&mut self, now: u64) {
+        while let Some(&(start, pid)) = self.throttled.keys().next() {
+            if start > now {
+                break;
+            }
+            let proc = self.throttled.remove(&(start, pid)).unwrap();
+            let deadline = {
+                let attr = proc.sched_attr();
+                let mut se = proc.sched();
+                se.replenish_at = 0;
+                activate(&mut se, &attr, start);
+                se.abs_deadline
+            };
+            self.tasks.insert((deadline, pid), proc);
+        }
+    }
+}
+
+/// Start a period at `start` with a full budget.
+fn activate(se: &mut SchedEntity, attr: &SchedAttr, start: u64) {
+    se.abs_deadline = start + attr.deadline;
+    se.budget = attr.runtime;
+}
+
+impl Scheduler for EdfScheduler {
+    fn push(&mut self, proc: Arc<Process>, now: u64) {
+        let attr = proc.sched_attr();
+        let (deadline, throttled) = {
+            let mut se = proc.sched();
+            if se.replenish_at > now {
+                (se.replenish_at, true)
+            } else {
+                if se.replenish_at != 0 {
+                    se.replenish_at = 0;
+                    activate(&mut se, &attr, now);
+                } else if now >= se.abs_deadline {
+                    // a new activation
+                    activate(&mut se, &attr, now);
+                }
+                (se.abs_deadline, false)
+            }
+        };
+        if throttled {
+            self.throttled.insert((deadline, proc.pid()), proc);
+        } else {
+            self.tasks.insert((deadline, proc.pid()), proc);
+        }
+    }
+
+    fn pop(&mut self, now: u64) -> Option<Arc<Process>> {
+        self.replenish(now);
+        let key = *self.tasks.keys().next()?;
+        self.tasks.remove(&key)
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<Arc<Process>> {
+        let key = *self.tasks.iter().rev().find(|(_, p)| p.can_run_on(cpu))?.0;
+        self.tasks.remove(&key)
+    }
+
+    fn tick(&mut self, current: &Process, now: u64) -> bool {
+        let attr = current.sched_attr();
+        let mut se = current.sched();
+        se.budget = se.budget.saturating_sub(1);
+        if se.budget == 0 {
+            let next_period = se.abs_deadline.saturating_sub(attr.deadline) + attr.period;
+            if next_period > now {
+                // gives up the cpu, `push` puts it aside until then
+                se.replenish_at = next_period;
+                return true;
+            }
+            // overran past its whole period, start another one right away
+            activate(&mut se, &attr, now);
+        }
+        match self.tasks.keys().next() {
+            Some(&(next, _)) => next < se.abs_deadline,
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len() + self.throttled.len()
+    }
+
+    fn has_ready(&self, now: u64) -> bool {
+        !self.tasks.is_empty() || self.throttled.keys().next().map_or(false, |&(start, _)| start <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use crate::process::proc::{Process, test_process};
+
+    use super::*;
+
+    fn deadline_task(pid: usize, runtime: u64, deadline: u64, period: u64) -> Arc<Process> {
+        let proc = test_process(pid);
+        proc.set_deadline(runtime, deadline, period);
+        proc
+    }
+
+    #[test_case]
+    fn earliest_deadline_first() {
+        let mut edf = EdfScheduler::new();
+        edf.push(deadline_task(1, 2, 10, 10), 0);
+        edf.push(deadline_task(2, 2, 5, 10), 0);
+        assert_eq!(edf.pop(0).unwrap().pid(), 2);
+        assert_eq!(edf.pop(0).unwrap().pid(), 1);
+        assert!(edf.pop(0).is_none());
+    }
+
+    #[test_case]
+    fn throttled_until_next_period() {
+        let mut edf = EdfScheduler::new();
+        let proc = deadline_task(1, 2, 5, 10);
+        edf.push(proc.clone(), 0);
+        let proc = edf.pop(0).unwrap();
+        assert!(!edf.tick(&proc, 1));
+        // budget used up, gives the cpu away until tick 10
+        assert!(edf.tick(&proc, 2));
+        edf.push(proc, 2);
+        assert_eq!(edf.len(), 1);
+        assert!(!edf.has_ready(9));
+        assert!(edf.pop(9).is_none());
+        assert!(edf.has_ready(10));
+        let proc = edf.pop(10).unwrap();
+        let se = *proc.sched();
+        assert_eq!((se.abs_deadline, se.budget, se.replenish_at), (15, 2, 0));
+    }
+
+    #[test_case]
+    fn preempted_by_earlier_deadline() {
+        let mut edf = EdfScheduler::new();
+        edf.push(deadline_task(1, 5, 20, 20), 0);
+        let running = edf.pop(0).unwrap();
+        edf.push(deadline_task(2, 1, 30, 30), 0);
+        assert!(!edf.tick(&running, 1));
+        edf.push(deadline_task(3, 1, 4, 10), 1);
+        assert!(edf.tick(&running, 2));
+    }
+}
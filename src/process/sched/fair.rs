@@ -0,0 +1,163 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+
+use crate::process::proc::Process;
+
+use super::Scheduler;
+
+pub const MIN_NICE: i8 = -20;
+pub const MAX_NICE: i8 = 19;
+
+/// load weight of a nice 0 process
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// vruntime the running process may get ahead of the next one before it is preempted
+const MIN_GRANULARITY: u64 = NICE_0_WEIGHT;
+
+/// how far ahead of the queue a process may come back from another cpu or a sleep
+const MAX_LAG: u64 = 20 * NICE_0_WEIGHT;
+
+/// load weights of nice -20..=19, each step is about 10% of cpu time (taken from linux)
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+pub fn nice_to_weight(nice: i8) -> u64 {
+    NICE_TO_WEIGHT[(nice.max(MIN_NICE).min(MAX_NICE) - MIN_NICE) as usize]
+}
+
+/// CFS-like scheduler, always runs the process that got the least weighted cpu time.
+pub struct FairScheduler {
+    /// keyed on (vruntime, pid)
+    tasks: BTreeMap<(u64, usize), Arc<Process>>,
+    /// lower bound of the vruntime of every process on this cpu
+    min_vruntime: u64,
+}
+
+impl FairScheduler {
+    pub fn new() -> Self {
+        FairScheduler {
+            tasks: BTreeMap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    fn leftmost(&self) -> Option<u64> {
+        self.tasks.keys().next().map(|&(v, _)| v)
+    }
+}
+
+impl Scheduler for FairScheduler {
+    fn push(&mut self, proc: Arc<Process>, _now: u64) {
+        let vruntime = {
+            let mut se = proc.sched();
+            // neither a long sleep nor a busy cpu elsewhere should decide
+            // how long the process waits here
+            se.vruntime = se.vruntime.max(self.min_vruntime).min(self.min_vruntime + MAX_LAG);
+            se.vruntime
+        };
+        self.tasks.insert((vruntime, proc.pid()), proc);
+    }
+
+    fn pop(&mut self, _now: u64) -> Option<Arc<Process>> {
+        let key = *self.tasks.keys().next()?;
+        self.min_vruntime = self.min_vruntime.max(key.0);
+        self.tasks.remove(&key)
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<Arc<Process>> {
+        let key = *self.tasks.iter().rev().find(|(_, p)| p.can_run_on(cpu))?.0;
+        self.tasks.remove(&key)
+    }
+
+    fn tick(&mut self, current: &Process, _now: u64) -> bool {
+        let weight = nice_to_weight(current.sched_attr().nice);
+        let vruntime = {
+            let mut se = current.sched();
+            se.vruntime += NICE_0_WEIGHT * NICE_0_WEIGHT / weight;
+            se.vruntime
+        };
+        match self.leftmost() {
+            Some(next) => {
+                self.min_vruntime = self.min_vruntime.max(vruntime.min(next));
+                vruntime > next + MIN_GRANULARITY
+            }
+            None => {
+                self.min_vruntime = self.min_vruntime.max(vruntime);
+                false
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::process::proc::test_process;
+
+    use super::*;
+
+    #[test_case]
+    fn weights() {
+        assert_eq!(nice_to_weight(0), NICE_0_WEIGHT);
+        assert_eq!(nice_to_weight(-20), 88761);
+        // clamped to the valid range
+        assert_eq!(nice_to_weight(100), nice_to_weight(MAX_NICE));
+    }
+
+    #[test_case]
+    fn preempts_past_granularity() {
+        let mut fair = FairScheduler::new();
+        fair.push(test_process(1), 0);
+        fair.push(test_process(2), 0);
+        let running = fair.pop(0).unwrap();
+        assert_eq!(running.pid(), 1);
+        assert!(!fair.tick(&running, 1));
+        assert!(fair.tick(&running, 2));
+        fair.push(running, 2);
+        assert_eq!(fair.pop(2).unwrap().pid(), 2);
+    }
+
+    #[test_case]
+    fn nice_runs_longer() {
+        let mut fair = FairScheduler::new();
+        let running = test_process(1);
+        running.set_nice(-5);
+        fair.push(test_process(2), 0);
+        let mut ticks = 0;
+        while !fair.tick(&running, ticks) {
+            ticks += 1;
+        }
+        // 335 of vruntime a tick instead of 1024, preempted on the fourth
+        // tick instead of the second
+        assert_eq!(ticks, 3);
+    }
+
+    #[test_case]
+    fn sleeper_lag_is_bounded() {
+        let mut fair = FairScheduler::new();
+        let running = test_process(1);
+        for now in 0..100 {
+            fair.tick(&running, now);
+        }
+        assert_eq!(fair.min_vruntime, 100 * NICE_0_WEIGHT);
+        // a long sleep is not credited
+        let sleeper = test_process(2);
+        fair.push(sleeper.clone(), 100);
+        assert_eq!(sleeper.sched().vruntime, fair.min_vruntime);
+        // nor held against one that ran a lot elsewhere
+        let busy = test_process(3);
+        busy.sched().vruntime = 1 << 40;
+        fair.push(busy.clone(), 100);
+        assert_eq!(busy.sched().vruntime, fair.min_vruntime + MAX_LAG);
+    }
+}
@@ -0,0 +1,144 @@
+use alloc::{boxed::Box, sync::Arc};
+
+use super::proc::Process;
+
+pub mod edf;
+pub mod fair;
+pub mod priority;
+
+use self::{edf::EdfScheduler, fair::FairScheduler, priority::PriorityScheduler};
+
+/// A scheduling policy for the processes queued on one cpu.
+///
+/// the running process is not queued, it is handed to `tick` instead.
+/// `now` is the scheduler clock, in timer ticks
+pub trait Scheduler: Send {
+    /// add a runnable process
+    fn push(&mut self, proc: Arc<Process>, now: u64);
+
+    /// remove the process that should run next
+    fn pop(&mut self, now: u64) -> Option<Arc<Process>>;
+
+    /// remove a process allowed to run on `cpu`, to migrate it there
+    fn steal(&mut self, cpu: usize) -> Option<Arc<Process>>;
+
+    /// account a tick to the running process,
+    /// return true if it should give up the cpu
+    fn tick(&mut self, current: &Process, now: u64) -> bool;
+
+    /// number of queued processes
+    fn len(&self) -> usize;
+
+    /// whether `pop` would return a process, queued ones may have to wait
+    fn has_ready(&self, _now: u64) -> bool {
+        self.len() > 0
+    }
+}
+
+/// Scheduling class of a process, in order of precedence:
+/// a runnable process of a class always preempts the classes after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// earliest deadline first, for periodic real-time tasks
+    Deadline = 0,
+    /// strict priority with aging
+    Priority = 1,
+    /// virtual runtime based fair share, weighted by nice value
+    Fair = 2,
+}
+
+const CLASS_NUM: usize = 3;
+
+/// Scheduling attributes of a process, set by the user of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedAttr {
+    pub policy: SchedPolicy,
+    /// -20 (most cpu) to 19 (least cpu), used by `Fair`
+    pub nice: i8,
+    /// 0 is the highest, used by `Priority`
+    pub priority: u8,
+    /// ticks of cpu time per period, used by `Deadline`
+    pub runtime: u64,
+    /// ticks from the start of a period until the runtime must be done
+    pub deadline: u64,
+    /// ticks between two activations
+    pub period: u64,
+}
+
+impl Default for SchedAttr {
+    fn default() -> Self {
+        SchedAttr {
+            policy: SchedPolicy::Fair,
+            nice: 0,
+            priority: priority::DEFAULT_PRIORITY,
+            runtime: 0,
+            deadline: 0,
+            period: 0,
+        }
+    }
+}
+
+/// Bookkeeping of the schedulers, one per process.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedEntity {
+    /// weighted cpu time, `Fair`
+    pub vruntime: u64,
+    /// when the process was last queued, `Priority`
+    pub enqueued_at: u64,
+    /// ticks run since the process was picked, `Priority`
+    pub slice_used: u64,
+    /// end of the current period, `Deadline`
+    pub abs_deadline: u64,
+    /// runtime left in the current period, `Deadline`
+    pub budget: u64,
+    /// start of the next period while the budget is used up, 0 otherwise, `Deadline`
+    pub replenish_at: u64,
+}
+
+/// Per-cpu scheduler holding one `Scheduler` per `SchedPolicy`.
+pub struct ClassScheduler {
+    /// indexed by `SchedPolicy`
+    classes: [Box<dyn Scheduler>; CLASS_NUM],
+}
+
+impl ClassScheduler {
+    pub fn new() -> Self {
+        ClassScheduler {
+            classes: [
+                Box::new(EdfScheduler::new()),
+                Box::new(PriorityScheduler::new()),
+                Box::new(FairScheduler::new()),
+            ],
+        }
+    }
+}
+
+impl Scheduler for ClassScheduler {
+    fn push(&mut self, proc: Arc<Process>, now: u64) {
+        let class = proc.sched_attr().policy as usize;
+        self.classes[class].push(proc, now);
+    }
+
+    fn pop(&mut self, now: u64) -> Option<Arc<Process>> {
+        self.classes.iter_mut().find_map(|c| c.pop(now))
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<Arc<Process>> {
+        // real-time tasks lose the most by moving, take them last
+        self.classes.iter_mut().rev().find_map(|c| c.steal(cpu))
+    }
+
+    fn tick(&mut self, current: &Process, now: u64) -> bool {
+        let class = current.sched_attr().policy as usize;
+        self.classes[..class].iter().any(|c| c.has_ready(now))
+            || self.classes[class].tick(current, now)
+    }
+
+    fn len(&self) -> usize {
+        self.classes.iter().map(|c| c.len()).sum()
+    }
+
+    fn has_ready(&self, now: u64) -> bool {
+        self.classes.iter().any(|c| c.has_ready(now))
+    }
+}
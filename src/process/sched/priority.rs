@@ -0,0 +1,133 @@
+use alloc::{sync::Arc, vec::Vec};
+
+use crate::process::proc::Process;
+
+use super::Scheduler;
+
+/// number of priority levels, 0 is the highest
+pub const PRIORITY_LEVELS: u8 = 32;
+
+pub const DEFAULT_PRIORITY: u8 = 16;
+
+/// ticks a process runs before yielding to others of the same priority
+const TIMESLICE: u64 = 10;
+
+/// ticks of waiting that raise a queued process by one level,
+/// so low priorities are not starved forever
+const AGING_TICKS: u64 = 20;
+
+/// Strict priority scheduler with aging, round robin within a level.
+pub struct PriorityScheduler {
+    /// in order of arrival
+    tasks: Vec<Arc<Process>>,
+}
+
+impl PriorityScheduler {
+    pub fn new() -> Self {
+        PriorityScheduler {
+            tasks: Vec::new(),
+        }
+    }
+
+    /// priority of a queued process, raised by the time it waited
+    fn effective(proc: &Process, now: u64) -> u8 {
+        let waited = now.saturating_sub(proc.sched().enqueued_at);
+        let boost = (waited / AGING_TICKS).min(PRIORITY_LEVELS as u64) as u8;
+        proc.sched_attr().priority.saturating_sub(boost)
+    }
+
+    /// index of the best queued process, the earliest one among equals
+    fn best(&self, now: u64) -> Option<(usize, u8)> {
+        self.tasks.iter()
+            .enumerate()
+            .map(|(i, p)| (i, Self::effective(p, now)))
+            .min_by_key(|&(i, prio)| (prio, i))
+    }
+}
+
+impl Scheduler for PriorityScheduler {
+    fn push(&mut self, proc: Arc<Process>, now: u64) {
+        {
+            let mut se = proc.sched();
+            se.enqueued_at = now;
+            se.slice_used = 0;
+        }
+        self.tasks.push(proc);
+    }
+
+    fn pop(&mut self, now: u64) -> Option<Arc<Process>> {
+        let (idx, _) = self.best(now)?;
+        Some(self.tasks.remove(idx))
+    }
+
+    fn steal(&mut self, cpu: usize) -> Option<Arc<Process>> {
+        let idx = self.tasks.iter().rposition(|p| p.can_run_on(cpu))?;
+        Some(self.tasks.remove(idx))
+    }
+
+    fn tick(&mut self, current: &Process, now: u64) -> bool {
+        let slice_used = {
+            let mut se = current.sched();
+            se.slice_used += 1;
+            se.slice_used
+        };
+        let prio = current.sched_attr().priority;
+        match self.best(now) {
+            Some((_, best)) => best < prio || (best == prio && slice_used >= TIMESLICE),
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use crate::process::proc::{Process, test_process};
+
+    use super::*;
+
+    fn with_priority(pid: usize, priority: u8) -> Arc<Process> {
+        let proc = test_process(pid);
+        proc.set_priority(priority);
+        proc
+    }
+
+    #[test_case]
+    fn highest_first() {
+        let mut sched = PriorityScheduler::new();
+        sched.push(with_priority(1, 20), 0);
+        sched.push(with_priority(2, 5), 0);
+        sched.push(with_priority(3, 5), 0);
+        let order: alloc::vec::Vec<usize> = (0..3).map(|_| sched.pop(0).unwrap().pid()).collect();
+        assert_eq!(order, [2, 3, 1]);
+    }
+
+    #[test_case]
+    fn aging() {
+        let mut sched = PriorityScheduler::new();
+        sched.push(with_priority(1, 20), 0);
+        sched.push(with_priority(2, 5), 15 * AGING_TICKS);
+        // raised to 5 by now, and queued first
+        assert_eq!(sched.pop(15 * AGING_TICKS).unwrap().pid(), 1);
+    }
+
+    #[test_case]
+    fn round_robin_within_a_level() {
+        let mut sched = PriorityScheduler::new();
+        let running = with_priority(1, 10);
+        sched.push(with_priority(2, 10), 0);
+        for now in 1..TIMESLICE {
+            assert!(!sched.tick(&running, now));
+        }
+        assert!(sched.tick(&running, TIMESLICE));
+        // a higher priority preempts right away
+        let mut sched = PriorityScheduler::new();
+        sched.push(with_priority(3, 2), 0);
+        assert!(sched.tick(&running, 0));
+    }
+}
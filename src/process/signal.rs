@@ -0,0 +1,37 @@
+use alloc::sync::Arc;
+
+use super::proc::{Process, wake};
+
+/// Signals the kernel sends, numbered as on linux.
+///
+/// there are no handlers yet: a signal stays pending until the process
+/// takes it, and makes interruptible waits such as terminal reads return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Signal {
+    /// the terminal went away
+    Hup = 1,
+    /// Ctrl-C
+    Int = 2,
+    /// Ctrl-\
+    Quit = 3,
+    /// Ctrl-Z
+    Tstp = 20,
+    /// the terminal changed its size
+    Winch = 28,
+}
+
+impl Signal {
+    /// bit of the signal in a pending mask
+    pub fn bit(self) -> u64 {
+        1 << self as u8
+    }
+}
+
+/// Make `signal` pending for `proc`, waking it if it is blocked.
+///
+/// safe from interrupt context
+pub fn send_signal(proc: &Arc<Process>, signal: Signal) {
+    proc.add_signal(signal);
+    wake(proc);
+}
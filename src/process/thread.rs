@@ -1,4 +1,18 @@
+use core::time::Duration;
 
+use crate::time::timer::{add_timer, cancel_timer};
 
+use super::{current, proc::{ProcessState, park_current, wake}};
 
-
+/// Put the calling thread to sleep for at least `duration`.
+///
+/// the thread is switched out until the timer fires, precision is one tick
+pub fn sleep(duration: Duration) {
+    let proc = current().expect("sleep outside of a thread");
+    proc.set_state(ProcessState::Wait);
+    let p = proc.clone();
+    let timer = add_timer(duration, move || wake(&p));
+    park_current();
+    // woken early, e.g. by a signal: the timer must not wake a later wait
+    cancel_timer(timer);
+}
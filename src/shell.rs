@@ -0,0 +1,425 @@
+//! Debug shell on its own virtual terminal and on COM1, for poking at hardware.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+use x86_64::instructions::{interrupts::without_interrupts, port::Port};
+
+use crate::{arch::{consts::{PAGE_SIZE, PHYSICAL_MEMORY_OFFSET}, keyboard::{KEYBOARD, Layout}, memory::{frame_stats, is_mapped}, pci::pci_devices, power::{reboot, shutdown}, serial::com1}, console::vt, consts::SHELL_VT, drivers::{BLK_DRIVERS, block::BlockDriver, tty::{Tty, TtyError, pty::open_pty, termios::{ECHO, ECHOCTL, ICANON, ISIG, TCGETS, TCSETSW, Termios}, ttys}}, fs::ext2_ro::Ext2, logging::dmesg, memory::HEAP_ALLOCATOR, process::{current, proc::{PROCESSES, Process}}};
+
+const PROMPT: &str = "> ";
+const MAX_LINE: usize = 256;
+const SECTOR_SIZE: usize = 512;
+/// most bytes `peek` dumps at once
+const MAX_PEEK: usize = 4096;
+/// most sectors `read` dumps at once
+const MAX_READ_SECTORS: usize = 64;
+
+/// `stty` settings, a local flag each
+const STTY_FLAGS: &[(&str, u32)] = &[("icanon", ICANON), ("echo", ECHO), ("isig", ISIG), ("echoctl", ECHOCTL)];
+
+struct Command {
+    name: &'static str,
+    args: &'static str,
+    help: &'static str,
+    run: fn(&[&str]) -> Result<(), String>,
+}
+
+const COMMANDS: &[Command] = &[
+    Command { name: "help", args: "", help: "list the commands", run: help },
+    Command { name: "ps", args: "", help: "list processes", run: ps },
+    Command { name: "mem", args: "", help: "frame allocator and heap usage", run: mem },
+    Command { name: "pci", args: "", help: "list pci functions", run: pci },
+    Command { name: "dmesg", args: "", help: "print the kernel log", run: print_dmesg },
+    Command { name: "peek", args: "<phys> [len]", help: "dump physical memory", run: peek },
+    Command { name: "poke", args: "<phys> <value> [1|2|4|8]", help: "write physical memory", run: poke },
+    Command { name: "in", args: "<port> [1|2|4]", help: "read an i/o port", run: port_in },
+    Command { name: "out", args: "<port> <value> [1|2|4]", help: "write an i/o port", run: port_out },
+    Command { name: "blk", args: "", help: "list block devices", run: blk },
+    Command { name: "read", args: "<dev> <sector> [count]", help: "dump sectors of a block device", run: read_sectors },
+    Command { name: "ls", args: "<dev> [path]", help: "list an ext2 directory", run: ls },
+    Command { name: "cat", args: "<dev> <path>", help: "print an ext2 file", run: cat },
+    Command { name: "stty", args: "[[-]icanon|[-]echo|[-]isig|[-]echoctl|sane]...", help: "show or change the terminal settings", run: stty },
+    Command { name: "kbd", args: "[us104|uk105|dvorak104|azerty] [1|2]", help: "show or change the keyboard layout and scancode set", run: kbd },
+    Command { name: "ttys", args: "", help: "list terminals", run: list_ttys },
+    Command { name: "pty", args: "<text>", help: "type a line on a new pty and read it back", run: pty },
+    Command { name: "reboot", args: "", help: "restart the machine", run: run_reboot },
+    Command { name: "poweroff", args: "", help: "turn the machine off", run: run_shutdown },
+];
+
+/// numbers are hex with a 0x prefix, decimal otherwise
+fn parse_num(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| alloc::format!("bad number {}", s))
+}
+
+fn arg(args: &[&str], i: usize) -> Result<u64, String> {
+    parse_num(args.get(i).ok_or("missing argument")?)
+}
+
+fn opt_arg(args: &[&str], i: usize, default: u64) -> Result<u64, String> {
+    args.get(i).map_or(Ok(default), |s| parse_num(s))
+}
+
+fn width(args: &[&str], i: usize, allowed: &[u64]) -> Result<usize, String> {
+    let width = opt_arg(args, i, allowed[0])?;
+    if allowed.contains(&width) {
+        Ok(width as usize)
+    } else {
+        Err(alloc::format!("bad width {}", width))
+    }
+}
+
+fn hexdump(base: usize, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let mut s = String::new();
+        let _ = write!(s, "{:#010x}:", base + i * 16);
+        for b in line {
+            let _ = write!(s, " {:02x}", b);
+        }
+        s.push_str("  ");
+        s.extend(line.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' }));
+        println!("{}", s);
+    }
+}
+
+fn help(_: &[&str]) -> Result<(), String> {
+    for c in COMMANDS {
+        println!("{:<8} {:<26} {}", c.name, c.args, c.help);
+    }
+    Ok(())
+}
+
+fn ps(_: &[&str]) -> Result<(), String> {
+    println!("{:>5} {:<9} {:>3} {:<9} {:>4}", "pid", "state", "cpu", "policy", "nice");
+    // printing can block, so not under the lock
+    let processes: Vec<Arc<Process>> = PROCESSES.read().values().cloned().collect();
+    for p in processes {
+        let (pid, attr) = (p.pid(), p.sched_attr());
+        println!("{:>5} {:<9} {:>3} {:<9} {:>4}", pid, alloc::format!("{:?}", p.state()), p.cpu(), alloc::format!("{:?}", attr.policy), attr.nice);
+    }
+    Ok(())
+}
+
+fn mem(_: &[&str]) -> Result<(), String> {
+    let (free, total) = frame_stats();
+    println!("frames: {} free of {} ({} KiB free)", free, total, free * 4);
+    // interrupt handlers allocate, they must not find the heap locked
+    let (used, free, size) = without_interrupts(|| {
+        let heap = HEAP_ALLOCATOR.lock();
+        (heap.used(), heap.free(), heap.size())
+    });
+    println!("heap: {} used, {} free of {} bytes", used, free, size);
+    Ok(())
+}
+
+fn pci(_: &[&str]) -> Result<(), String> {
+    for dev in pci_devices() {
+        println!(
+            "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}.{:02x} irq {}",
+            dev.loc.bus, dev.loc.device, dev.loc.function,
+            dev.id.vendor_id, dev.id.device_id,
+            dev.id.class, dev.id.subclass,
+            dev.pic_interrupt_line,
+        );
+        for (i, bar) in dev.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                println!("    bar{} {:x?}", i, bar);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_dmesg(_: &[&str]) -> Result<(), String> {
+    print!("{}", dmesg());
+    Ok(())
+}
+
+/// virtual address of physical `phys..phys + len`, if every page of it is mapped
+fn phys_range(phys: u64, len: usize) -> Result<usize, String> {
+    let not_mapped = || alloc::format!("{:#x} is not mapped", phys);
+    let virt = (phys as usize).checked_add(PHYSICAL_MEMORY_OFFSET).ok_or_else(not_mapped)?;
+    let end = virt.checked_add(len).ok_or_else(not_mapped)?;
+    match (virt & !(PAGE_SIZE - 1)..end).step_by(PAGE_SIZE).find(|&page| !is_mapped(page)) {
+        Some(page) => Err(alloc::format!("{:#x} is not mapped", page - PHYSICAL_MEMORY_OFFSET)),
+        None => Ok(virt),
+    }
+}
+
+fn peek(args: &[&str]) -> Result<(), String> {
+    let phys = arg(args, 0)?;
+    let len = opt_arg(args, 1, 64)? as usize;
+    if len > MAX_PEEK {
+        return Err(alloc::format!("at most {} bytes", MAX_PEEK));
+    }
+    let virt = phys_range(phys, len)?;
+    let data: Vec<u8> = (0..len).map(|i| unsafe { core::ptr::read_volatile((virt + i) as *const u8) }).collect();
+    hexdump(phys as usize, &data);
+    Ok(())
+}
+
+fn poke(args: &[&str]) -> Result<(), String> {
+    let phys = arg(args, 0)?;
+    let value = arg(args, 1)?;
+    let width = width(args, 2, &[4, 1, 2, 8])?;
+    let virt = phys_range(phys, width)?;
+    unsafe {
+        match width {
+            1 => core::ptr::write_volatile(virt as *mut u8, value as u8),
+            2 => core::ptr::write_volatile(virt as *mut u16, value as u16),
+            4 => core::ptr::write_volatile(virt as *mut u32, value as u32),
+            _ => core::ptr::write_volatile(virt as *mut u64, value),
+        }
+    }
+    Ok(())
+}
+
+fn port_arg(args: &[&str], i: usize) -> Result<u16, String> {
+    let port = arg(args, i)?;
+    if port > 0xffff {
+        return Err(alloc::format!("bad port {:#x}", port));
+    }
+    Ok(port as u16)
+}
+
+fn port_in(args: &[&str]) -> Result<(), String> {
+    let port = port_arg(args, 0)?;
+    let value: u32 = unsafe {
+        match width(args, 1, &[1, 2, 4])? {
+            1 => Port::<u8>::new(port).read() as u32,
+            2 => Port::<u16>::new(port).read() as u32,
+            _ => Port::<u32>::new(port).read(),
+        }
+    };
+    println!("{:#x}", value);
+    Ok(())
+}
+
+fn port_out(args: &[&str]) -> Result<(), String> {
+    let port = port_arg(args, 0)?;
+    let value = arg(args, 1)?;
+    unsafe {
+        match width(args, 2, &[1, 2, 4])? {
+            1 => Port::<u8>::new(port).write(value as u8),
+            2 => Port::<u16>::new(port).write(value as u16),
+            _ => Port::<u32>::new(port).write(value as u32),
+        }
+    }
+    Ok(())
+}
+
+fn block_device(args: &[&str]) -> Result<Arc<dyn BlockDriver>, String> {
+    let index = arg(args, 0)? as usize;
+    BLK_DRIVERS.read().get(index).cloned().ok_or_else(|| alloc::format!("no block device {}", index))
+}
+
+fn blk(_: &[&str]) -> Result<(), String> {
+    for (i, dev) in BLK_DRIVERS.read().iter().enumerate() {
+        println!("{} {}", i, dev.get_id());
+    }
+    Ok(())
+}
+
+fn read_sectors(args: &[&str]) -> Result<(), String> {
+    let dev = block_device(args)?;
+    let sector = arg(args, 1)? as usize;
+    let count = opt_arg(args, 2, 1)? as usize;
+    if count > MAX_READ_SECTORS {
+        return Err(alloc::format!("at most {} sectors", MAX_READ_SECTORS));
+    }
+    // the dump shows byte offsets, which have to fit as well
+    let end = sector.checked_add(count)
+        .filter(|end| end.checked_mul(SECTOR_SIZE).is_some())
+        .ok_or("sector out of range")?;
+    let mut buf = [0u8; SECTOR_SIZE];
+    for s in sector..end {
+        dev.read_at(s, &mut buf);
+        hexdump(s * SECTOR_SIZE, &buf);
+    }
+    Ok(())
+}
+
+fn open_ext2(args: &[&str]) -> Result<Ext2, String> {
+    Ext2::find(block_device(args)?).ok_or_else(|| String::from("no ext2 file system"))
+}
+
+fn ls(args: &[&str]) -> Result<(), String> {
+    let fs = open_ext2(args)?;
+    let path = args.get(1).copied().unwrap_or("/");
+    let dir = fs.lookup(path).and_then(|ino| fs.inode(ino)).ok_or("no such file or directory")?;
+    if !dir.is_dir() {
+        return Err(alloc::format!("{} is not a directory", path));
+    }
+    for entry in fs.read_dir(&dir) {
+        let kind = match entry.file_type {
+            2 => 'd',
+            7 => 'l',
+            _ => '-',
+        };
+        match fs.inode(entry.inode) {
+            Some(inode) => println!("{} {:>8} {:>10} {}", kind, entry.inode, inode.size, entry.name),
+            None => println!("{} {:>8} {:>10} {}", kind, entry.inode, "?", entry.name),
+        }
+    }
+    Ok(())
+}
+
+fn cat(args: &[&str]) -> Result<(), String> {
+    let fs = open_ext2(args)?;
+    let path = args.get(1).ok_or("missing path")?;
+    let file = fs.lookup(path).and_then(|ino| fs.inode(ino)).ok_or("no such file or directory")?;
+    if file.is_dir() {
+        return Err(alloc::format!("{} is a directory", path));
+    }
+    print!("{}", String::from_utf8_lossy(&fs.read_file(&file)));
+    Ok(())
+}
+
+fn kbd(args: &[&str]) -> Result<(), String> {
+    for &arg in args {
+        match arg {
+            "1" | "2" => {
+                if !KEYBOARD.set_scancode_set(arg.as_bytes()[0] - b'0') {
+                    return Err(String::from("keyboard refused the scancode set"));
+                }
+            }
+            _ => KEYBOARD.set_layout(Layout::from_name(arg).ok_or_else(|| alloc::format!("unknown layout {}", arg))?),
+        }
+    }
+    println!("{:?} layout, scancode set {}", KEYBOARD.layout(), KEYBOARD.scancode_set());
+    Ok(())
+}
+
+fn stty(args: &[&str]) -> Result<(), String> {
+    let tty = current().and_then(|proc| proc.ctty()).ok_or("no controlling terminal")?;
+    let mut termios = Termios::new();
+    unsafe { tty.ioctl(TCGETS, &mut termios as *mut Termios as usize) }.map_err(|e| alloc::format!("{:?}", e))?;
+    for &arg in args {
+        if arg == "sane" {
+            termios = Termios::new();
+            continue;
+        }
+        let (name, on) = match arg.strip_prefix('-') {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        let flag = STTY_FLAGS.iter().find(|f| f.0 == name).ok_or_else(|| alloc::format!("unknown setting {}", arg))?.1;
+        if on {
+            termios.lflag |= flag;
+        } else {
+            termios.lflag &= !flag;
+        }
+    }
+    if !args.is_empty() {
+        unsafe { tty.ioctl(TCSETSW, &termios as *const Termios as usize) }.map_err(|e| alloc::format!("{:?}", e))?;
+    }
+    let mut s = String::from(tty.name());
+    for (name, flag) in STTY_FLAGS {
+        let _ = write!(s, " {}{}", if termios.lflag & flag != 0 { "" } else { "-" }, name);
+    }
+    println!("{}", s);
+    Ok(())
+}
+
+fn list_ttys(_: &[&str]) -> Result<(), String> {
+    println!("{:<6} {:>4} {:>4} {:>5}", "name", "rows", "cols", "fg");
+    for tty in ttys() {
+        let winsize = tty.winsize();
+        let fg = tty.foreground().map_or(String::from("-"), |proc| alloc::format!("{}", proc.pid()));
+        println!("{:<6} {:>4} {:>4} {:>5}", tty.name(), winsize.rows, winsize.cols, fg);
+    }
+    Ok(())
+}
+
+/// Opens a pty, types `args` on it through the master and shows what the
+/// master saw echoed and what a reader of the slave gets.
+fn pty(args: &[&str]) -> Result<(), String> {
+    let master = open_pty().map_err(|e| alloc::format!("{:?}", e))?;
+    let mut line = args.join(" ");
+    line.push('\r');
+    master.write(line.as_bytes());
+    let mut buf = [0; MAX_LINE];
+    let echoed = master.try_read(&mut buf).unwrap_or(0);
+    println!("{} echoed {:?}", master.slave().name(), String::from_utf8_lossy(&buf[..echoed]));
+    let read = master.slave().try_read(&mut buf).unwrap_or(0);
+    println!("{} read {:?}", master.slave().name(), String::from_utf8_lossy(&buf[..read]));
+    Ok(())
+}
+
+fn run_reboot(_: &[&str]) -> Result<(), String> {
+    reboot()
+}
+
+fn run_shutdown(_: &[&str]) -> Result<(), String> {
+    shutdown()
+}
+
+fn execute(line: &str) {
+    let mut words = line.split_whitespace();
+    let name = match words.next() {
+        Some(name) => name,
+        None => return,
+    };
+    let args: Vec<&str> = words.collect();
+    match COMMANDS.iter().find(|c| c.name == name) {
+        Some(c) => {
+            if let Err(e) = (c.run)(&args) {
+                println!("{}: {}", name, e);
+            }
+        }
+        None => println!("unknown command {}, try help", name),
+    }
+}
+
+/// Read a line from `tty`, edited by its line discipline. false at end of file
+fn read_line(tty: &Tty, line: &mut String) -> Result<bool, TtyError> {
+    let mut bytes = Vec::new();
+    let mut buf = [0; MAX_LINE];
+    while !bytes.ends_with(b"\n") {
+        let count = tty.read(&mut buf)?;
+        if count == 0 {
+            return Ok(false);
+        }
+        bytes.extend_from_slice(&buf[..count]);
+    }
+    *line = String::from_utf8_lossy(&bytes).into_owned();
+    Ok(true)
+}
+
+/// The shell on `tty`, which becomes the controlling terminal of the calling
+/// thread, and the thread its foreground process.
+fn run(tty: Arc<Tty>) {
+    let proc = current().expect("shell outside of a thread");
+    proc.set_ctty(Some(tty.clone()));
+    tty.set_foreground(&proc);
+    let mut line = String::new();
+    println!("debug shell, type help for the commands");
+    loop {
+        print!("{}", PROMPT);
+        match read_line(&tty, &mut line) {
+            Ok(true) => execute(&line),
+            // Ctrl-D on an empty line
+            Ok(false) => println!(),
+            // Ctrl-C or Ctrl-Z, the line discipline dropped the line
+            Err(_) => {
+                proc.take_signals();
+                println!();
+            }
+        }
+    }
+}
+
+/// Kernel thread running the shell on the shell terminal, forever.
+pub fn shell_main() {
+    run(vt::tty(SHELL_VT));
+}
+
+/// Kernel thread running the shell on COM1, if it has a tty.
+pub fn serial_shell_main() {
+    if let Some(tty) = com1().tty() {
+        run(tty.clone());
+    }
+}
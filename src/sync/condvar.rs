@@ -1 +1,55 @@
+use alloc::{sync::Arc, vec::Vec};
 
+use crate::process::{current, proc::{Process, ProcessState, park_current, wake}};
+
+use super::mutex::SpinNoIrqLock;
+
+/// Processes waiting for a condition on data behind a `SpinNoIrqLock`.
+///
+/// notifying is safe from interrupt context, so a driver can wake its readers
+pub struct Condvar {
+    waiters: SpinNoIrqLock<Vec<Arc<Process>>>,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Condvar { waiters: SpinNoIrqLock::new(Vec::new()) }
+    }
+
+    /// Block until `f` returns something for the data in `lock`.
+    ///
+    /// `f` runs with `lock` held and is retried after every notification.
+    /// the caller is queued before `lock` is released, so a notification
+    /// sent in between is not lost. it is dequeued again once it runs, woken
+    /// by a notification or by something else such as a signal, so a later
+    /// notification cannot wake it out of an unrelated wait
+    pub fn wait_until<T, R>(&self, lock: &SpinNoIrqLock<T>, mut f: impl FnMut(&mut T) -> Option<R>) -> R {
+        loop {
+            let mut data = lock.lock();
+            if let Some(r) = f(&mut *data) {
+                return r;
+            }
+            let proc = current().expect("wait outside of a thread");
+            proc.set_state(ProcessState::Wait);
+            self.waiters.lock().push(proc.clone());
+            drop(data);
+            park_current();
+            self.waiters.lock().retain(|p| !Arc::ptr_eq(p, &proc));
+        }
+    }
+
+    /// Wake the longest waiting process.
+    pub fn notify_one(&self) {
+        let mut waiters = self.waiters.lock();
+        if !waiters.is_empty() {
+            wake(&waiters.remove(0));
+        }
+    }
+
+    /// Wake every waiting process.
+    pub fn notify_all(&self) {
+        for proc in self.waiters.lock().drain(..) {
+            wake(&proc);
+        }
+    }
+}
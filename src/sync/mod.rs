@@ -1,2 +1,3 @@
 pub mod mutex;
-pub mod condvar;
\ No newline at end of file
+pub mod condvar;
+pub mod ring;
@@ -91,8 +91,8 @@ impl<T: ?Sized, S: MutexSupport> Mutex<T, S> {
         if initialization == 1
             || self
                 .support_initialization
-                .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).expect("msg")
-                != 0
+                .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
         {
             // Wait for another thread to initialize
             while self.support_initialization.load(Ordering::Acquire) == 1 {
@@ -191,7 +191,7 @@ impl FlagsGuard {
 
 impl Drop for FlagsGuard {
     fn drop(&mut self) {
-        restore(0);
+        restore(self.0);
     }
 }
 
@@ -0,0 +1,164 @@
+use lazy_static::lazy_static;
+use log::info;
+use spin::RwLock;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::consts::USEC_PER_TICK;
+
+use super::ticks;
+
+const NSEC_PER_SEC: u128 = 1_000_000_000;
+
+/// A free running counter the monotonic clock can be read from.
+pub trait ClockSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// current counter value, must never go backwards
+    fn read(&self) -> u64;
+
+    /// counter increments per second
+    fn frequency(&self) -> u64;
+
+    /// how good the source is, the best registered one is used
+    fn rating(&self) -> u32;
+}
+
+/// The timer tick, always there but with tick granularity.
+struct TickClock;
+
+impl ClockSource for TickClock {
+    fn name(&self) -> &'static str {
+        "tick"
+    }
+
+    fn read(&self) -> u64 {
+        ticks()
+    }
+
+    fn frequency(&self) -> u64 {
+        1_000_000 / USEC_PER_TICK as u64
+    }
+
+    fn rating(&self) -> u32 {
+        1
+    }
+}
+
+/// The source in use, and where the clock stood when it was picked.
+struct Clock {
+    source: &'static dyn ClockSource,
+    /// counter value when `source` was picked
+    base: u64,
+    /// monotonic time when `source` was picked
+    base_ns: u64,
+}
+
+impl Clock {
+    fn now_ns(&self) -> u64 {
+        let delta = self.source.read().wrapping_sub(self.base) as u128;
+        self.base_ns + (delta * NSEC_PER_SEC / self.source.frequency().max(1) as u128) as u64
+    }
+}
+
+lazy_static! {
+    /// read from interrupt context, so only taken for writing with interrupts disabled
+    static ref CLOCK: RwLock<Clock> = RwLock::new(Clock {
+        source: &TickClock,
+        base: 0,
+        base_ns: 0,
+    });
+}
+
+/// Offer `source` to the monotonic clock, it takes over if it is rated higher.
+///
+/// the clock carries on from where the previous source left it,
+/// so switching never makes it jump back
+pub fn register_clocksource(source: &'static dyn ClockSource) {
+    let previous = without_interrupts(|| {
+        let mut clock = CLOCK.write();
+        if source.rating() <= clock.source.rating() {
+            return None;
+        }
+        let now = clock.now_ns();
+        let previous = clock.source;
+        clock.base = source.read();
+        clock.base_ns = now;
+        clock.source = source;
+        Some(previous)
+    });
+    // the logger reads the clock, so not while it is locked
+    if let Some(previous) = previous {
+        info!("switching from {} to {} ({} Hz)", previous.name(), source.name(), source.frequency());
+    }
+}
+
+/// name of the clocksource in use
+pub fn current_clocksource() -> &'static str {
+    CLOCK.read().source.name()
+}
+
+/// nanoseconds since boot, from the best clocksource available
+pub fn monotonic_ns() -> u64 {
+    CLOCK.read().now_ns()
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    use super::*;
+
+    /// A counter the test sets by hand.
+    struct TestSource {
+        count: AtomicU64,
+        frequency: u64,
+    }
+
+    impl ClockSource for TestSource {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn read(&self) -> u64 {
+            self.count.load(Ordering::Relaxed)
+        }
+
+        fn frequency(&self) -> u64 {
+            self.frequency
+        }
+
+        fn rating(&self) -> u32 {
+            0
+        }
+    }
+
+    static HPET_LIKE: TestSource = TestSource { count: AtomicU64::new(1000), frequency: 14_318_180 };
+    static TSC_LIKE: TestSource = TestSource { count: AtomicU64::new(u64::MAX - 9), frequency: 3_000_000_000 };
+
+    #[test_case]
+    fn counts_to_nanoseconds() {
+        let clock = Clock { source: &HPET_LIKE, base: 1000, base_ns: 5 };
+        assert_eq!(clock.now_ns(), 5);
+        HPET_LIKE.count.store(1000 + 14_318_180, Ordering::Relaxed);
+        assert_eq!(clock.now_ns(), 1_000_000_005);
+        // rounded down to whole nanoseconds
+        HPET_LIKE.count.store(1000 + 1, Ordering::Relaxed);
+        assert_eq!(clock.now_ns(), 5 + 69);
+        // a day of counts does not overflow the conversion
+        HPET_LIKE.count.store(1000 + 14_318_180 * 86_400, Ordering::Relaxed);
+        assert_eq!(clock.now_ns(), 86_400 * 1_000_000_000 + 5);
+    }
+
+    #[test_case]
+    fn counter_wraps() {
+        let clock = Clock { source: &TSC_LIKE, base: u64::MAX - 9, base_ns: 0 };
+        // 3 ghz, 30 counts are 10ns, 20 of them after the wrap
+        TSC_LIKE.count.store(20, Ordering::Relaxed);
+        assert_eq!(clock.now_ns(), 10);
+    }
+
+    #[test_case]
+    fn tick_frequency() {
+        assert_eq!(TickClock.frequency() * USEC_PER_TICK as u64, 1_000_000);
+    }
+}
@@ -0,0 +1,42 @@
+use core::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use crate::consts::USEC_PER_TICK;
+
+pub mod clocksource;
+pub mod realtime;
+pub mod timer;
+
+pub use clocksource::monotonic_ns;
+pub use realtime::realtime_ns;
+
+/// timer ticks since the timer interrupt was enabled
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the bsp when `ticks` timer ticks passed,
+/// more than one after a tickless idle period.
+pub fn advance(ticks: u64) {
+    let now = TICKS.fetch_add(ticks, Ordering::Relaxed) + ticks;
+    timer::expire(now);
+}
+
+/// monotonic tick counter
+#[inline]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// time since the timer interrupt was enabled, with tick granularity
+pub fn uptime() -> Duration {
+    ticks_to_duration(ticks())
+}
+
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_micros(ticks * USEC_PER_TICK as u64)
+}
+
+/// number of ticks covering `d`, rounded up
+pub fn duration_to_ticks(d: Duration) -> u64 {
+    let us = d.as_micros();
+    let per_tick = USEC_PER_TICK as u128;
+    ((us + per_tick - 1) / per_tick) as u64
+}
@@ -0,0 +1,129 @@
+use core::{fmt, sync::atomic::{AtomicU64, Ordering}};
+
+use super::monotonic_ns;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// wall clock time at monotonic time 0, in ns since the unix epoch
+static REALTIME_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Broken down UTC time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub const fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        DateTime { year, month, day, hour, minute, second }
+    }
+
+    /// seconds since the unix epoch
+    pub fn to_unix(&self) -> u64 {
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let secs = days * SECS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        secs.max(0) as u64
+    }
+
+    pub fn from_unix(secs: u64) -> Self {
+        let days = (secs / SECS_PER_DAY as u64) as i64;
+        let rem = secs % SECS_PER_DAY as u64;
+        let (year, month, day) = civil_from_days(days);
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// days since 1970-01-01 of a proleptic gregorian date,
+/// from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// inverse of `days_from_civil`
+fn civil_from_days(z: i64) -> (i64, i64, i64) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, m, d)
+}
+
+/// Set the wall clock, `now_ns` since the unix epoch is the time right now.
+pub fn set_realtime(now_ns: u64) {
+    REALTIME_OFFSET.store(now_ns.saturating_sub(monotonic_ns()), Ordering::Relaxed);
+}
+
+/// nanoseconds since the unix epoch, 0 based until the rtc was read
+pub fn realtime_ns() -> u64 {
+    REALTIME_OFFSET.load(Ordering::Relaxed) + monotonic_ns()
+}
+
+/// seconds since the unix epoch, as stored in file timestamps
+pub fn realtime_secs() -> u64 {
+    realtime_ns() / NSEC_PER_SEC
+}
+
+pub fn now() -> DateTime {
+    DateTime::from_unix(realtime_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn unix_time() {
+        assert_eq!(DateTime::new(1970, 1, 1, 0, 0, 0).to_unix(), 0);
+        assert_eq!(DateTime::new(2000, 3, 1, 0, 0, 0).to_unix(), 951_868_800);
+        assert_eq!(DateTime::new(2024, 2, 29, 12, 0, 0).to_unix(), 1_709_208_000);
+        assert_eq!(DateTime::new(2099, 12, 31, 23, 59, 59).to_unix(), 4_102_444_799);
+    }
+
+    #[test_case]
+    fn round_trip() {
+        for &secs in [0, 951_868_800 - 1, 1_709_208_000, 4_102_444_799].iter() {
+            assert_eq!(DateTime::from_unix(secs).to_unix(), secs);
+        }
+        assert_eq!(DateTime::from_unix(951_868_800 - 1), DateTime::new(2000, 2, 29, 23, 59, 59));
+    }
+
+    #[test_case]
+    fn display() {
+        assert_eq!(alloc::format!("{}", DateTime::new(2024, 2, 9, 8, 7, 6)), "2024-02-09 08:07:06");
+    }
+}
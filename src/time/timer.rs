@@ -0,0 +1,240 @@
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
+use core::{sync::atomic::{AtomicU64, Ordering}, time::Duration};
+use lazy_static::lazy_static;
+
+use crate::{arch::{cpu::cpu_id, timer::kick}, sync::mutex::SpinNoIrqLock};
+
+use super::{duration_to_ticks, ticks};
+
+/// bits of expiry time resolved by each level of the wheel
+const SLOT_BITS: u64 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+const SLOT_MASK: u64 = SLOTS as u64 - 1;
+/// 4 levels of 64 slots cover 2^24 ticks ahead, longer timers are re-filed
+const LEVELS: usize = 4;
+
+type Callback = Box<dyn FnMut() + Send>;
+
+struct Timer {
+    id: u64,
+    expires: u64,
+    /// re-armed every `period` ticks if set
+    period: Option<u64>,
+    callback: Callback,
+}
+
+/// Hierarchical timer wheel.
+///
+/// level 0 has one slot per tick, every slot of level n covers a whole
+/// turn of level n - 1. when a lower level wraps around, the next slot of
+/// the level above is cascaded down, so a timer is filed at most `LEVELS` times
+struct TimerWheel {
+    /// last tick processed
+    now: u64,
+    levels: Vec<Vec<Vec<Timer>>>,
+    /// ids of the timers that are armed, cancelled ones are dropped when they come up
+    active: BTreeSet<u64>,
+}
+
+impl TimerWheel {
+    fn new() -> Self {
+        TimerWheel {
+            now: 0,
+            levels: (0..LEVELS).map(|_| (0..SLOTS).map(|_| Vec::new()).collect()).collect(),
+            active: BTreeSet::new(),
+        }
+    }
+
+    fn add(&mut self, timer: Timer) {
+        let expires = timer.expires.max(self.now);
+        let delta = expires - self.now;
+        let mut level = 0;
+        while level + 1 < LEVELS && delta >= 1 << (SLOT_BITS * (level as u64 + 1)) {
+            level += 1;
+        }
+        // too far in the future for the wheel: park in the furthest slot,
+        // it is filed again when that slot cascades
+        let max = self.now + (1 << (SLOT_BITS * LEVELS as u64)) - 1;
+        let at = expires.min(max);
+        let slot = ((at >> (SLOT_BITS * level as u64)) & SLOT_MASK) as usize;
+        self.levels[level][slot].push(timer);
+    }
+
+    /// Move the timers of the current slot of `level` down the wheel.
+    fn cascade(&mut self, level: usize) {
+        let slot = ((self.now >> (SLOT_BITS * level as u64)) & SLOT_MASK) as usize;
+        let timers = core::mem::replace(&mut self.levels[level][slot], Vec::new());
+        for t in timers {
+            self.add(t);
+        }
+    }
+
+    /// Advance to tick `now`, returning the timers that expired.
+    fn advance(&mut self, now: u64) -> Vec<Timer> {
+        let mut expired = Vec::new();
+        while self.now < now {
+            self.now += 1;
+            let mut level = 1;
+            while level < LEVELS && (self.now >> (SLOT_BITS * (level as u64 - 1))) & SLOT_MASK == 0 {
+                self.cascade(level);
+                level += 1;
+            }
+            let slot = (self.now & SLOT_MASK) as usize;
+            let timers = core::mem::replace(&mut self.levels[0][slot], Vec::new());
+            for t in timers {
+                if !self.active.contains(&t.id) {
+                    continue;
+                }
+                if t.expires > self.now {
+                    // parked in a far slot, not due yet
+                    self.add(t);
+                } else {
+                    expired.push(t);
+                }
+            }
+        }
+        expired
+    }
+
+    /// earliest expiry of an armed timer
+    fn next_expiry(&self) -> Option<u64> {
+        self.levels.iter()
+            .flat_map(|l| l.iter())
+            .flat_map(|s| s.iter())
+            .filter(|t| self.active.contains(&t.id))
+            .map(|t| t.expires)
+            .min()
+    }
+}
+
+lazy_static! {
+    static ref WHEEL: SpinNoIrqLock<TimerWheel> = SpinNoIrqLock::new(TimerWheel::new());
+}
+
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Handle of an armed timer, see `cancel_timer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerHandle(u64);
+
+fn arm(after: u64, period: Option<u64>, callback: Callback) -> TimerHandle {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    let earliest = {
+        let mut wheel = WHEEL.lock();
+        let expires = ticks().max(wheel.now) + after.max(1);
+        let earliest = wheel.next_expiry().map_or(true, |next| expires < next);
+        wheel.active.insert(id);
+        wheel.add(Timer { id, expires, period, callback });
+        earliest
+    };
+    // the bsp may be sleeping until the timer that was first so far, it
+    // looks again when it wakes up; on the bsp itself that happens anyway
+    if earliest && cpu_id() != 0 {
+        kick(0);
+    }
+    TimerHandle(id)
+}
+
+/// Run `f` once, `after` from now.
+///
+/// callbacks run in the timer interrupt of the bsp, they must not block
+pub fn add_timer<F: FnMut() + Send + 'static>(after: Duration, f: F) -> TimerHandle {
+    arm(duration_to_ticks(after), None, Box::new(f))
+}
+
+/// Run `f` every `period`, starting one period from now.
+pub fn add_periodic_timer<F: FnMut() + Send + 'static>(period: Duration, f: F) -> TimerHandle {
+    let period = duration_to_ticks(period).max(1);
+    arm(period, Some(period), Box::new(f))
+}
+
+/// Disarm a timer, returns false if it already fired (or never existed).
+pub fn cancel_timer(handle: TimerHandle) -> bool {
+    WHEEL.lock().active.remove(&handle.0)
+}
+
+/// tick at which the next armed timer fires
+pub fn next_expiry() -> Option<u64> {
+    WHEEL.lock().next_expiry()
+}
+
+/// Run the timers due at tick `now`.
+pub(super) fn expire(now: u64) {
+    let mut expired = WHEEL.lock().advance(now);
+    // callbacks run without the wheel locked, they may arm timers
+    for t in expired.iter_mut() {
+        (t.callback)();
+    }
+    let mut wheel = WHEEL.lock();
+    for mut t in expired {
+        match t.period {
+            Some(period) if wheel.active.contains(&t.id) => {
+                // one that fell behind, over a tickless sleep, fires on the
+                // next tick: the slot of an earlier one is past for this turn
+                t.expires = (t.expires + period).max(wheel.now + 1);
+                wheel.add(t);
+            }
+            _ => {
+                wheel.active.remove(&t.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn file(wheel: &mut TimerWheel, id: u64, expires: u64) {
+        wheel.active.insert(id);
+        wheel.add(Timer { id, expires, period: None, callback: Box::new(|| {}) });
+    }
+
+    /// (id, tick) of every timer that fired, advancing one tick at a time
+    fn run_until(wheel: &mut TimerWheel, end: u64) -> Vec<(u64, u64)> {
+        let mut fired = Vec::new();
+        while wheel.now < end {
+            let now = wheel.now + 1;
+            fired.extend(wheel.advance(now).into_iter().map(|t| (t.id, now)));
+        }
+        fired
+    }
+
+    #[test_case]
+    fn cascade() {
+        let mut wheel = TimerWheel::new();
+        // one timer on each level, and one beyond the wheel
+        let expiries = [5, 64, 100, 4096 + 7, 300_000, (1 << 24) + 3];
+        for (id, &expires) in expiries.iter().enumerate() {
+            file(&mut wheel, id as u64, expires);
+        }
+        assert_eq!(wheel.next_expiry(), Some(5));
+        let fired = run_until(&mut wheel, (1 << 24) + 10);
+        let expected: Vec<(u64, u64)> = expiries.iter().enumerate().map(|(id, &e)| (id as u64, e)).collect();
+        assert_eq!(fired, expected);
+        assert_eq!(wheel.next_expiry(), None);
+    }
+
+    #[test_case]
+    fn cancelled_timers_do_not_fire() {
+        let mut wheel = TimerWheel::new();
+        file(&mut wheel, 1, 10);
+        file(&mut wheel, 2, 200);
+        wheel.active.remove(&1);
+        assert_eq!(wheel.next_expiry(), Some(200));
+        assert_eq!(run_until(&mut wheel, 300), [(2, 200)]);
+    }
+
+    #[test_case]
+    fn late_advance() {
+        let mut wheel = TimerWheel::new();
+        file(&mut wheel, 1, 70);
+        // many ticks at once, as after a tickless sleep
+        let fired: Vec<u64> = wheel.advance(1000).into_iter().map(|t| t.id).collect();
+        assert_eq!(fired, [1]);
+        file(&mut wheel, 2, 1001);
+        assert_eq!(run_until(&mut wheel, 1001), [(2, 1001)]);
+    }
+}
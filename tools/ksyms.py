@@ -0,0 +1,126 @@
+#!/usr/bin/env python3
+"""Fill the `.ksyms` section of a linked kernel with its function symbols.
+
+usage: ksyms.py <kernel elf>
+
+`tools/run.py`, the cargo runner, does this before it makes the disk images.
+
+The section holds `ksyms::SymbolTable`: the magic b"KSYM", a u32 count, then
+`count` entries of (address u64, size u32, name offset u32, name length u32)
+sorted by address, then the demangled names. Offsets are relative to the
+first entry. Everything is little endian.
+"""
+import re
+import struct
+import sys
+
+SHT_SYMTAB = 2
+STT_FUNC = 2
+ENTRY = struct.Struct("<QIII")
+HEADER = struct.Struct("<4sI")
+
+ESCAPES = {
+    "SP": "@", "BP": "*", "RF": "&", "LT": "<", "GT": ">", "LP": "(", "RP": ")", "C": ",",
+}
+
+
+def demangle_segment(seg):
+    if seg.startswith("_$"):
+        seg = seg[1:]
+
+    def escape(m):
+        code = m.group(1)
+        if code in ESCAPES:
+            return ESCAPES[code]
+        if code.startswith("u"):
+            return chr(int(code[1:], 16))
+        return m.group(0)
+
+    seg = re.sub(r"\$([A-Za-z0-9]+)\$", escape, seg)
+    return seg.replace("..", "::")
+
+
+def demangle(name):
+    """legacy rust mangling `_ZN<len><seg>...E`, without the trailing hash"""
+    if not name.startswith("_ZN") or not name.endswith("E"):
+        return name
+    body, segs, i = name[3:-1], [], 0
+    while i < len(body):
+        m = re.match(r"\d+", body[i:])
+        if not m:
+            return name
+        i += len(m.group(0))
+        n = int(m.group(0))
+        segs.append(body[i:i + n])
+        i += n
+    if segs and re.fullmatch(r"h[0-9a-f]{16}", segs[-1]):
+        segs.pop()
+    return "::".join(demangle_segment(s) for s in segs)
+
+
+def sections(elf):
+    shoff, = struct.unpack_from("<Q", elf, 0x28)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", elf, 0x3a)
+    headers = []
+    for i in range(shnum):
+        # name, type, flags, addr, offset, size, link, info, align, entsize
+        headers.append(struct.unpack_from("<IIQQQQIIQQ", elf, shoff + i * shentsize))
+    strtab = headers[shstrndx]
+    names = elf[strtab[4]:strtab[4] + strtab[5]]
+    return [(names[h[0]:names.index(b"\0", h[0])].decode(), h) for h in headers]
+
+
+def functions(elf, secs):
+    symtab = next((h for _, h in secs if h[1] == SHT_SYMTAB), None)
+    if symtab is None:
+        sys.exit("ksyms: no symbol table, was the kernel stripped?")
+    strtab = secs[symtab[6]][1]
+    strs = elf[strtab[4]:strtab[4] + strtab[5]]
+    funcs = {}
+    for off in range(symtab[4], symtab[4] + symtab[5], 24):
+        st_name, st_info, _, st_shndx, st_value, st_size = struct.unpack_from("<IBBHQQ", elf, off)
+        if st_info & 0xf != STT_FUNC or st_shndx == 0 or st_value == 0:
+            continue
+        name = strs[st_name:strs.index(b"\0", st_name)].decode(errors="replace")
+        funcs.setdefault(st_value, (st_size, demangle(name)))
+    return sorted(funcs.items())
+
+
+def patch(path):
+    """fill the `.ksyms` section of the kernel at `path` in place"""
+    with open(path, "rb") as f:
+        elf = bytearray(f.read())
+    if elf[:4] != b"\x7fELF" or elf[4] != 2:
+        sys.exit("ksyms: %s is not a 64 bit elf" % path)
+    secs = sections(elf)
+    ksyms = next((h for name, h in secs if name == ".ksyms"), None)
+    if ksyms is None:
+        sys.exit("ksyms: no .ksyms section in %s" % path)
+    offset, size = ksyms[4], ksyms[5]
+    if elf[offset:offset + 4] != b"KSYM":
+        sys.exit("ksyms: bad magic in .ksyms")
+
+    funcs = functions(elf, secs)
+    entries, names = bytearray(), bytearray()
+    base = len(funcs) * ENTRY.size
+    for addr, (fsize, name) in funcs:
+        encoded = name.encode()
+        entries += ENTRY.pack(addr, min(fsize, 0xffffffff), base + len(names), len(encoded))
+        names += encoded
+    table = HEADER.pack(b"KSYM", len(funcs)) + entries + names
+    if len(table) > size:
+        sys.exit("ksyms: %d bytes of symbols do not fit in %d, grow KSYMS_SIZE" % (len(table), size))
+    elf[offset:offset + size] = table + bytes(size - len(table))
+    with open(path, "wb") as f:
+        f.write(elf)
+    print("ksyms: %d symbols, %d of %d bytes" % (len(funcs), len(table), size))
+
+
+def main():
+    if len(sys.argv) != 2:
+        sys.exit(__doc__)
+    patch(sys.argv[1])
+
+
+if __name__ == "__main__":
+    main()
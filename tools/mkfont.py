@@ -0,0 +1,139 @@
+#!/usr/bin/env python3
+"""Render an 8x16 PSF1 console font from a monospaced TrueType font.
+
+usage: mkfont.py <font.ttf> <out.psf>
+
+The 256 glyphs follow code page 437, the character set of the VGA text
+buffer, so the framebuffer console can draw the same bytes. Glyphs are
+rendered by libfreetype in monochrome with hinting, at the pixel size whose
+advance is 8 pixels, on a baseline 4 pixels above the bottom of the cell.
+Code points the font lacks are left blank.
+
+src/drivers/font8x16.psf was made from DejaVu Sans Mono.
+"""
+import ctypes
+import ctypes.util
+import sys
+
+WIDTH = 8
+HEIGHT = 16
+BASELINE = 12
+
+PSF1_MAGIC = b"\x36\x04"
+
+FT_LOAD_RENDER = 1 << 2
+FT_LOAD_MONOCHROME = 1 << 12
+FT_LOAD_TARGET_MONO = 2 << 16
+
+CP437_HIGH = (
+    "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»"
+    "░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀"
+    "αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■ "
+)
+CP437_LOW = (
+    "\u0000☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼"
+)
+
+
+def cp437(byte):
+    if byte < 0x20:
+        return CP437_LOW[byte]
+    if byte < 0x7f:
+        return chr(byte)
+    if byte == 0x7f:
+        return "⌂"
+    return CP437_HIGH[byte - 0x80]
+
+
+class Bitmap(ctypes.Structure):
+    _fields_ = [
+        ("rows", ctypes.c_uint), ("width", ctypes.c_uint), ("pitch", ctypes.c_int),
+        ("buffer", ctypes.POINTER(ctypes.c_ubyte)), ("num_grays", ctypes.c_ushort),
+        ("pixel_mode", ctypes.c_ubyte), ("palette_mode", ctypes.c_ubyte),
+        ("palette", ctypes.c_void_p),
+    ]
+
+
+class GlyphSlot(ctypes.Structure):
+    _fields_ = [
+        ("library", ctypes.c_void_p), ("face", ctypes.c_void_p), ("next", ctypes.c_void_p),
+        ("glyph_index", ctypes.c_uint), ("generic", ctypes.c_void_p * 2),
+        ("metrics", ctypes.c_long * 8), ("linear_hori_advance", ctypes.c_long),
+        ("linear_vert_advance", ctypes.c_long), ("advance", ctypes.c_long * 2),
+        ("format", ctypes.c_uint), ("bitmap", Bitmap),
+        ("bitmap_left", ctypes.c_int), ("bitmap_top", ctypes.c_int),
+    ]
+
+
+class Face(ctypes.Structure):
+    _fields_ = [
+        ("num_faces", ctypes.c_long), ("face_index", ctypes.c_long),
+        ("face_flags", ctypes.c_long), ("style_flags", ctypes.c_long),
+        ("num_glyphs", ctypes.c_long), ("family_name", ctypes.c_char_p),
+        ("style_name", ctypes.c_char_p), ("num_fixed_sizes", ctypes.c_int),
+        ("available_sizes", ctypes.c_void_p), ("num_charmaps", ctypes.c_int),
+        ("charmaps", ctypes.c_void_p), ("generic", ctypes.c_void_p * 2),
+        ("bbox", ctypes.c_long * 4), ("units_per_em", ctypes.c_ushort),
+        ("ascender", ctypes.c_short), ("descender", ctypes.c_short),
+        ("height", ctypes.c_short), ("max_advance_width", ctypes.c_short),
+        ("max_advance_height", ctypes.c_short), ("underline_position", ctypes.c_short),
+        ("underline_thickness", ctypes.c_short), ("glyph", ctypes.POINTER(GlyphSlot)),
+    ]
+
+
+def check(error, what):
+    if error:
+        sys.exit("%s failed: freetype error %d" % (what, error))
+
+
+def open_face(path):
+    ft = ctypes.CDLL(ctypes.util.find_library("freetype") or "libfreetype.so.6")
+    library = ctypes.c_void_p()
+    check(ft.FT_Init_FreeType(ctypes.byref(library)), "FT_Init_FreeType")
+    face = ctypes.POINTER(Face)()
+    check(ft.FT_New_Face(library, path.encode(), 0, ctypes.byref(face)), "FT_New_Face")
+    # the largest size that still advances by at most WIDTH pixels
+    size = HEIGHT
+    while size > 1:
+        check(ft.FT_Set_Pixel_Sizes(face, 0, size), "FT_Set_Pixel_Sizes")
+        check(ft.FT_Load_Char(face, ord("M"), FT_LOAD_TARGET_MONO), "FT_Load_Char")
+        if face.contents.glyph.contents.advance[0] <= WIDTH * 64:
+            break
+        size -= 1
+    return ft, face
+
+
+def render(ft, face, char):
+    glyph = [0] * HEIGHT
+    index = ft.FT_Get_Char_Index(face, ord(char))
+    if index == 0:
+        return glyph
+    flags = FT_LOAD_RENDER | FT_LOAD_MONOCHROME | FT_LOAD_TARGET_MONO
+    check(ft.FT_Load_Glyph(face, index, flags), "FT_Load_Glyph")
+    slot = face.contents.glyph.contents
+    bitmap = slot.bitmap
+    for y in range(bitmap.rows):
+        row = BASELINE - slot.bitmap_top + y
+        if not 0 <= row < HEIGHT:
+            continue
+        for x in range(bitmap.width):
+            col = slot.bitmap_left + x
+            byte = bitmap.buffer[y * bitmap.pitch + x // 8]
+            if 0 <= col < WIDTH and byte & (0x80 >> (x % 8)):
+                glyph[row] |= 0x80 >> col
+    return glyph
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+    ft, face = open_face(sys.argv[1])
+    out = bytearray(PSF1_MAGIC + bytes([0, HEIGHT]))
+    for byte in range(256):
+        out += bytes(render(ft, face, cp437(byte)))
+    with open(sys.argv[2], "wb") as f:
+        f.write(out)
+
+
+if __name__ == "__main__":
+    main()